@@ -0,0 +1,125 @@
+use super::fixture::{Dims, Fixture};
+use bench_sandbox::rng::HashSet;
+use bench_sandbox::sharded::{Shard, Sharded};
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+const DIMS: Dims = Dims::new(25, 100, 2_000);
+const SHARDS: [usize; 2] = [1, 16]; // 1 shard is the single `Mutex<FakeProtocol>`
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+/// Each thread handles the batches of its nodes (`node_id % threads`), then
+/// the operations of the batch from the same node, on a protocol shared by
+/// all the threads
+fn scaling<P>(
+    c: &mut Criterion,
+    name: &str,
+    new_protocol: impl Fn() -> P,
+    handle: impl Fn(&Sharded<P>, u64, HashSet<u64>) + Sync,
+) where
+    P: Shard + Send,
+    P::Peers: Send,
+{
+    let batches = Fixture::new(DIMS).batches();
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(DIMS.announced()));
+    for shards in SHARDS {
+        for threads in THREADS {
+            let id = BenchmarkId::new(format!("{} shards", shards), threads);
+            group.bench_with_input(id, &threads, |b, &threads| {
+                b.iter(|| {
+                    let protocol = Sharded::new(shards, &new_protocol);
+                    std::thread::scope(|scope| {
+                        for thread in 0..threads {
+                            let (protocol, batches, handle) = (&protocol, &batches, &handle);
+                            scope.spawn(move || {
+                                for (node_id, batch) in batches.iter() {
+                                    if *node_id as usize % threads == thread {
+                                        handle(protocol, *node_id, batch.clone());
+                                    }
+                                }
+                            });
+                        }
+                    });
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+pub fn less_cpu_concurrent_handlers(c: &mut Criterion) {
+    use bench_sandbox::less_cpu::*;
+    scaling(
+        c,
+        "Less cpu concurrent handlers",
+        || FakeProtocol {
+            is_measured: false,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, 200, DIMS.operations)
+        },
+        |protocol, node_id, batch| {
+            let operations: OperationMap = batch
+                .iter()
+                .map(|op_id| (*op_id, Operation::default()))
+                .collect();
+            protocol.on_ids(batch, |op_ids, protocol| {
+                on_batch_received(op_ids, node_id, protocol)
+            });
+            protocol.on_map(operations, |operations, protocol| {
+                on_operation_received(node_id, operations, protocol)
+            });
+        },
+    );
+}
+
+pub fn more_cpu_concurrent_handlers(c: &mut Criterion) {
+    use bench_sandbox::more_cpu::*;
+    scaling(
+        c,
+        "More cpu concurrent handlers",
+        || FakeProtocol {
+            is_measured: false,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size)
+        },
+        |protocol, node_id, batch| {
+            let operations: AskedOperations = batch
+                .iter()
+                .map(|op_id| (*op_id, Some(Operation::default())))
+                .collect();
+            protocol.on_ids(batch, |op_ids, protocol| {
+                on_batch_received(op_ids, node_id, protocol)
+            });
+            protocol.on_map(operations, |operations, protocol| {
+                on_operation_received(node_id, operations, protocol)
+            });
+        },
+    );
+}
+
+pub fn hybrid_concurrent_handlers(c: &mut Criterion) {
+    use bench_sandbox::hybrid::*;
+    scaling(
+        c,
+        "Hybrid concurrent handlers",
+        || FakeProtocol {
+            is_measured: false,
+            ..FakeProtocol::new(
+                DIMS.nodes,
+                DIMS.batch_size,
+                std::time::Duration::from_millis(200),
+            )
+        },
+        |protocol, node_id, batch| {
+            let operations: OperationMap = batch
+                .iter()
+                .map(|op_id| (*op_id, Operation::default()))
+                .collect();
+            protocol.on_ids(batch, |op_ids, protocol| {
+                on_batch_received(op_ids, node_id, protocol)
+            });
+            protocol.on_map(operations, |operations, protocol| {
+                on_operation_received(node_id, operations, protocol)
+            });
+        },
+    );
+}
@@ -0,0 +1,74 @@
+use super::fixture;
+use bench_sandbox::rng::HashSet;
+use bench_sandbox::{rng::Stream, timer::TimerHeap};
+use criterion::Criterion;
+use rand::Rng;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+const T: u64 = 25; // Number of nodes
+const N: usize = 10_000; // Number of deferred future sets
+const IDS: u64 = 10; // Ids by future set
+const MAX_PERIOD: u64 = 400; // ms, the deadlines are not in push order
+const STEP: u64 = 10; // ms between two send loops
+
+type Entry = (Instant, u64, HashSet<u64>);
+
+fn entries(start: Instant) -> Vec<Entry> {
+    let mut rng = fixture::rng(Stream::DeferredAsks);
+    (0..N)
+        .map(|n| {
+            let deadline = start + Duration::from_millis(rng.gen_range(0..MAX_PERIOD));
+            let op_ids = (0..IDS).map(|id| n as u64 * IDS + id).collect();
+            (deadline, rng.gen_range(0..T), op_ids)
+        })
+        .collect()
+}
+
+/// Buffer the future sets, then pop the due ones at each send loop, with the
+/// previous `VecDeque` (popped in push order, an entry waits for the ones
+/// before it) and with the [TimerHeap]
+pub fn deferred_asks(c: &mut Criterion) {
+    let start = Instant::now();
+    let entries = entries(start);
+    let steps = (0..=MAX_PERIOD / STEP).map(|step| start + Duration::from_millis(step * STEP));
+
+    let mut group = c.benchmark_group("Deferred asks");
+    group.bench_function("deque", |b| {
+        b.iter(|| {
+            let mut buffer = VecDeque::new();
+            for entry in entries.iter().cloned() {
+                buffer.push_back(entry);
+            }
+            let mut popped = 0;
+            for now in steps.clone() {
+                while buffer
+                    .front()
+                    .is_some_and(|(deadline, _, _)| *deadline <= now)
+                {
+                    buffer.pop_front();
+                    popped += 1;
+                }
+            }
+            assert_eq!(popped, N);
+        })
+    });
+    group.bench_function("timer heap", |b| {
+        b.iter(|| {
+            let mut buffer = TimerHeap::default();
+            for (deadline, node_id, op_ids) in entries.iter().cloned() {
+                buffer.push(deadline, (node_id, op_ids));
+            }
+            let mut popped = 0;
+            for now in steps.clone() {
+                while buffer.pop_due(now).is_some() {
+                    popped += 1;
+                }
+            }
+            assert_eq!(popped, N);
+        })
+    });
+    group.finish();
+}
@@ -0,0 +1,111 @@
+//! Inputs shared by the benches: the operations announced by each node, in a
+//! random order for each node, sliced in batches.
+
+use bench_sandbox::{
+    rng::{HashMap, HashSet, SimRng, Stream},
+    scenario::DEFAULT_SEED,
+};
+use rand::seq::SliceRandom;
+
+/// Seed of the inputs of the benches, from the `SEED` environment variable
+pub fn seed() -> u64 {
+    std::env::var("SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(DEFAULT_SEED)
+}
+
+/// Generator of `stream` for the [seed] of the benches
+pub fn rng(stream: Stream) -> SimRng {
+    stream.rng(seed())
+}
+
+/// Size of a bench
+#[derive(Clone, Copy, Debug)]
+pub struct Dims {
+    /// T, the number of nodes
+    pub nodes: usize,
+    pub batch_size: usize,
+    /// N, the number of operations
+    pub operations: usize,
+}
+
+impl Dims {
+    pub const fn new(nodes: usize, batch_size: usize, operations: usize) -> Self {
+        Self {
+            nodes,
+            batch_size,
+            operations,
+        }
+    }
+
+    /// Ids announced in a run, every announcer announces N ids
+    pub fn announced(&self) -> u64 {
+        ((self.nodes + 1) * self.operations) as u64
+    }
+}
+
+impl std::fmt::Display for Dims {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "T={} batch={} N={}",
+            self.nodes, self.batch_size, self.operations
+        )
+    }
+}
+
+/// Keeps the dims of the original benches, `[[0; N + 1]; T + 1]`: T + 1
+/// announcers for a protocol created for T nodes, and orders of the N ids
+/// plus a second 0, shuffled, of which the first N are announced. So an
+/// announcer may skip an id and announce 0 twice.
+pub struct Fixture {
+    pub dims: Dims,
+    /// Order of the operations for each announcer
+    pub orders: Vec<Vec<u64>>,
+}
+
+impl Fixture {
+    pub fn new(dims: Dims) -> Self {
+        let mut rng = rng(Stream::BatchSender);
+        let orders = (0..=dims.nodes)
+            .map(|_| {
+                let mut order: Vec<u64> = (0..dims.operations as u64).collect();
+                order.push(0);
+                order.shuffle(&mut rng);
+                order.truncate(dims.operations);
+                order
+            })
+            .collect();
+        Self { dims, orders }
+    }
+
+    /// The batches in the order they are received: the first batch of each
+    /// node, then the second one...
+    pub fn batches(&self) -> Vec<(u64, HashSet<u64>)> {
+        let batch_size = self.dims.batch_size;
+        (0..self.dims.operations)
+            .step_by(batch_size)
+            .flat_map(|p| {
+                self.orders.iter().enumerate().map(move |(node_id, order)| {
+                    let end = (p + batch_size).min(order.len());
+                    (node_id as u64, order[p..end].iter().cloned().collect())
+                })
+            })
+            .collect()
+    }
+
+    /// The operations of each batch, with `operation(op_id)` for each id
+    pub fn operations<V>(&self, operation: impl Fn(u64) -> V) -> Vec<(u64, HashMap<u64, V>)> {
+        self.batches()
+            .into_iter()
+            .map(|(node_id, batch)| {
+                let operations = batch
+                    .into_iter()
+                    .map(|op_id| (op_id, operation(op_id)))
+                    .collect();
+                (node_id, operations)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,368 @@
+use super::fixture::{Dims, Fixture};
+use bench_sandbox::rng::{HashMap, HashSet};
+use criterion::{measurement::WallTime, BatchSize, BenchmarkGroup, BenchmarkId, Criterion};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+const DIMS: Dims = Dims::new(25, 100, 10_000);
+/// The announcements wait while the state is populated, they are all due
+/// when the hook runs
+const ANNOUNCE_DELAY: Duration = Duration::from_secs(3600);
+
+/// The state of the measured node in the middle of a run: every batch has
+/// been received and asked, the operations of the first half of the batches
+/// have been received, the nodes asked the ids of the first half.
+pub(super) struct MidRun<O> {
+    pub batches: Vec<(u64, HashSet<u64>)>,
+    pub received: Vec<(u64, HashMap<u64, O>)>,
+    pub pending: Vec<(u64, HashMap<u64, O>)>,
+    pub asks: Vec<(u64, HashSet<u64>)>,
+}
+
+impl<O> MidRun<O> {
+    fn new(operation: impl Fn(u64) -> O) -> Self {
+        Self::of(&Fixture::new(DIMS), operation)
+    }
+
+    pub fn of(fixture: &Fixture, operation: impl Fn(u64) -> O) -> Self {
+        let batches = fixture.batches();
+        let mut received = fixture.operations(operation);
+        let pending = received.split_off(received.len() / 2);
+        let asks = batches[..received.len()].to_vec();
+        Self {
+            batches,
+            received,
+            pending,
+            asks,
+        }
+    }
+}
+
+/// Bench one call of `hook` on a new state from `populate`, the state isn't
+/// measured
+pub(super) fn bench_hook<P, I: Clone>(
+    group: &mut BenchmarkGroup<WallTime>,
+    id: BenchmarkId,
+    populate: &impl Fn() -> P,
+    input: &I,
+    hook: impl Fn(&mut P, I),
+) {
+    group.bench_function(id, |b| {
+        b.iter_batched(
+            || (populate(), input.clone()),
+            |(mut protocol, input)| hook(&mut protocol, input),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+pub fn less_cpu_hooks(c: &mut Criterion) {
+    use bench_sandbox::less_cpu::*;
+    let mid_run = MidRun::new(|_| Operation::default());
+    let populate = || {
+        let mut protocol = FakeProtocol {
+            is_measured: false,
+            announce_delay: ANNOUNCE_DELAY,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, 200, DIMS.operations)
+        };
+        for (node_id, batch) in mid_run.batches.iter() {
+            on_batch_received(batch.clone(), *node_id, &mut protocol);
+        }
+        for (node_id, operations) in mid_run.received.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        protocol.announce_delay = Duration::ZERO;
+        // the batches still pending were announced again while their ids were
+        // asked to another node, they wait in the buffer. They are all due.
+        let mut buffered = mid_run.batches[mid_run.received.len()..].to_vec();
+        while let Some((_, entry)) = protocol.op_batch_buffer.pop_first() {
+            buffered.push(entry);
+        }
+        let now = Instant::now();
+        for entry in buffered {
+            protocol.op_batch_buffer.push(now, entry);
+        }
+        protocol
+    };
+
+    let mut group = c.benchmark_group("Less cpu hooks on a mid-run state");
+    group.sample_size(10);
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_operation_received"),
+        &populate,
+        &mid_run.pending,
+        |protocol, pending| {
+            for (node_id, operations) in pending {
+                on_operation_received(node_id, operations, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_ask_received"),
+        &populate,
+        &mid_run.asks,
+        |protocol, asks| {
+            for (node_id, op_ids) in asks {
+                on_ask_received(node_id, op_ids, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_send_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_send_loop(protocol),
+    );
+    group.finish();
+}
+
+pub fn more_cpu_hooks(c: &mut Criterion) {
+    use bench_sandbox::more_cpu::*;
+    let mid_run = MidRun::new(|_| Some(Operation::default()));
+    let populate = || {
+        let mut protocol = FakeProtocol {
+            is_measured: false,
+            announce_delay: ANNOUNCE_DELAY,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size)
+        };
+        for (node_id, batch) in mid_run.batches.iter() {
+            on_batch_received(batch.clone(), *node_id, &mut protocol);
+        }
+        on_asking_loop(&mut protocol);
+        for (node_id, operations) in mid_run.received.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        // the nodes wishlists are served by `on_send_operation_loop`
+        for (node_id, op_ids) in mid_run.asks.iter() {
+            on_ask_received(*node_id, op_ids.clone(), &mut protocol);
+        }
+        protocol.announce_delay = Duration::ZERO;
+        // this algorithm always queues what it sends
+        ASK_BATCH_QUEUE.lock().unwrap().clear();
+        BATCH_SEND_QUEUE.lock().unwrap().clear();
+        SEND_OPERATION.lock().unwrap().clear();
+        protocol
+    };
+
+    let mut group = c.benchmark_group("More cpu hooks on a mid-run state");
+    group.sample_size(10);
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_operation_received"),
+        &populate,
+        &mid_run.pending,
+        |protocol, pending| {
+            for (node_id, operations) in pending {
+                on_operation_received(node_id, operations, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_ask_received"),
+        &populate,
+        &mid_run.asks,
+        |protocol, asks| {
+            for (node_id, op_ids) in asks {
+                on_ask_received(node_id, op_ids, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_asking_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_asking_loop(protocol),
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_send_operation_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_send_operation_loop(protocol),
+    );
+    group.finish();
+}
+
+pub fn hybrid_hooks(c: &mut Criterion) {
+    use bench_sandbox::hybrid::*;
+    let mid_run = MidRun::new(|_| Operation::default());
+    let populate = || {
+        let mut protocol = FakeProtocol {
+            is_measured: false,
+            announce_delay: ANNOUNCE_DELAY,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, Duration::from_millis(200))
+        };
+        for (node_id, batch) in mid_run.batches.iter() {
+            on_batch_received(batch.clone(), *node_id, &mut protocol);
+        }
+        for (node_id, operations) in mid_run.received.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        protocol.announce_delay = Duration::ZERO;
+        // the ids in flight of the wishlist are due
+        protocol.retry_period = Duration::ZERO;
+        protocol
+    };
+
+    let mut group = c.benchmark_group("Hybrid hooks on a mid-run state");
+    group.sample_size(10);
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_operation_received"),
+        &populate,
+        &mid_run.pending,
+        |protocol, pending| {
+            for (node_id, operations) in pending {
+                on_operation_received(node_id, operations, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_ask_received"),
+        &populate,
+        &mid_run.asks,
+        |protocol, asks| {
+            for (node_id, op_ids) in asks {
+                on_ask_received(node_id, op_ids, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_asking_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_asking_loop(protocol),
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_send_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_send_loop(protocol),
+    );
+    group.finish();
+}
+
+/// The ids received since the last reconciliation with each node, in the
+/// reconcile benches: the ids of its next `RECON_BATCHES` batches. The node
+/// knows the same ids except one in `RECON_CHANGED`, replaced by an id that
+/// we don't know.
+const RECON_CHANGED: u64 = 40;
+
+pub fn reconcile_hooks(c: &mut Criterion) {
+    use bench_sandbox::reconcile::*;
+    let mid_run = MidRun::new(|_| Operation::default());
+    let recon_sets: BTreeMap<u64, OperationIds> = mid_run.batches[mid_run.received.len()..]
+        .iter()
+        .fold(BTreeMap::new(), |mut recon_sets, (node_id, batch)| {
+            let recon_set: &mut OperationIds = recon_sets.entry(*node_id).or_default();
+            if recon_set.len() < RECON_BATCHES * DIMS.batch_size {
+                recon_set.extend(batch);
+            }
+            recon_sets
+        });
+    let populate = || {
+        let mut protocol = FakeProtocol {
+            is_measured: false,
+            recon_q: 0.1,
+            ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, Duration::from_millis(200))
+        };
+        for (node_id, batch) in mid_run.batches.iter() {
+            on_batch_received(batch.clone(), *node_id, &mut protocol);
+        }
+        for (node_id, operations) in mid_run.received.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        for (node_id, recon_set) in recon_sets.iter() {
+            if let Some(node_info) = protocol.node_infos.get_mut(node_id) {
+                node_info.recon_set = recon_set.clone();
+            }
+        }
+        // the ids in flight of the wishlist are due
+        protocol.retry_period = Duration::ZERO;
+        protocol
+    };
+    let mut fresh = DIMS.operations as u64..;
+    let remote_sets: Vec<(u64, OperationIds)> = recon_sets
+        .iter()
+        .map(|(node_id, recon_set)| {
+            let remote_set = recon_set
+                .iter()
+                .map(|&op_id| match op_id % RECON_CHANGED {
+                    0 => fresh.next().unwrap(),
+                    _ => op_id,
+                })
+                .collect();
+            (*node_id, remote_set)
+        })
+        .collect();
+    let starts: Vec<(u64, usize)> = remote_sets
+        .iter()
+        .map(|(node_id, remote_set)| (*node_id, remote_set.len()))
+        .collect();
+    // the sizes we choose for the tables, the extra announcer of the fixture
+    // isn't a peer and is refused
+    let mut protocol = populate();
+    let sketches: Vec<(u64, Iblt)> = remote_sets
+        .into_iter()
+        .filter_map(|(node_id, remote_set)| {
+            match on_recon_started(node_id, remote_set.len(), &mut protocol) {
+                ReconReply::Sketch(cells) => Some((node_id, Iblt::from_ids(remote_set, cells))),
+                _ => None,
+            }
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("Reconcile hooks on a mid-run state");
+    group.sample_size(10);
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_recon_started"),
+        &populate,
+        &starts,
+        |protocol, starts| {
+            for (node_id, remote_len) in starts {
+                on_recon_started(node_id, remote_len, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_sketch_received"),
+        &populate,
+        &sketches,
+        |protocol, sketches| {
+            for (node_id, sketch) in sketches {
+                on_sketch_received(node_id, sketch, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_operation_received"),
+        &populate,
+        &mid_run.pending,
+        |protocol, pending| {
+            for (node_id, operations) in pending {
+                on_operation_received(node_id, operations, protocol);
+            }
+        },
+    );
+    bench_hook(
+        &mut group,
+        BenchmarkId::from_parameter("on_asking_loop"),
+        &populate,
+        &(),
+        |protocol, _| on_asking_loop(protocol),
+    );
+    group.finish();
+}
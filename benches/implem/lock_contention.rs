@@ -0,0 +1,249 @@
+use super::fixture::{Dims, Fixture};
+use criterion::{BenchmarkGroup, BenchmarkId, Criterion};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const DIMS: Dims = Dims::new(25, 100, 2_000);
+
+/// Threads sharing the `Mutex` of the protocol with the measured hook
+#[derive(Clone, Copy)]
+struct Contention {
+    /// Lock and read the state (like the memory sampler of the
+    /// measurements) every millisecond
+    readers: usize,
+    /// Lock and keep the lock `hold` (like the other hooks), then yield
+    writers: usize,
+    hold: Duration,
+}
+
+const CONTENTIONS: [Contention; 4] = [
+    Contention {
+        readers: 0,
+        writers: 0,
+        hold: Duration::ZERO,
+    },
+    Contention {
+        readers: 1,
+        writers: 1,
+        hold: Duration::from_micros(10),
+    },
+    Contention {
+        readers: 4,
+        writers: 4,
+        hold: Duration::from_micros(10),
+    },
+    Contention {
+        readers: 4,
+        writers: 4,
+        hold: Duration::from_micros(100),
+    },
+];
+
+impl std::fmt::Display for Contention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}r {}w {}us",
+            self.readers,
+            self.writers,
+            self.hold.as_micros()
+        )
+    }
+}
+
+/// Run `iters` calls of `hook` on the inputs, one after the other, while the
+/// contenders lock the protocol. Return the time spent waiting for the lock
+/// and the time spent in the hook. The protocol is rebuilt when the inputs
+/// are exhausted, that's not counted.
+fn run<P: Send + 'static, I: Clone>(
+    iters: u64,
+    contention: Contention,
+    new_protocol: &impl Fn() -> P,
+    read: fn(&P),
+    inputs: &[I],
+    hook: &impl Fn(I, &mut P),
+) -> (Duration, Duration) {
+    let protocol = Arc::new(Mutex::new(new_protocol()));
+    let running = Arc::new(AtomicBool::new(true));
+    let mut contenders = vec![];
+    for _ in 0..contention.readers {
+        let (protocol, running) = (protocol.clone(), running.clone());
+        contenders.push(std::thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                read(&protocol.lock().unwrap());
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }));
+    }
+    for _ in 0..contention.writers {
+        let (protocol, running) = (protocol.clone(), running.clone());
+        contenders.push(std::thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                let guard = protocol.lock().unwrap();
+                let start = Instant::now();
+                while start.elapsed() < contention.hold {
+                    std::hint::spin_loop();
+                }
+                std::mem::drop(guard);
+                std::thread::yield_now();
+            }
+        }));
+    }
+    let (mut wait, mut algo) = (Duration::ZERO, Duration::ZERO);
+    for i in 0..iters as usize {
+        if i > 0 && i % inputs.len() == 0 {
+            *protocol.lock().unwrap() = new_protocol();
+        }
+        let input = inputs[i % inputs.len()].clone();
+        let start = Instant::now();
+        let mut guard = protocol.lock().unwrap();
+        let locked = Instant::now();
+        // >>>>>>>>>> what we measure
+        hook(input, &mut guard);
+        // <<<<<<<<<<
+        algo += locked.elapsed();
+        wait += locked - start;
+    }
+    running.store(false, Ordering::Relaxed);
+    for contender in contenders {
+        contender.join().unwrap();
+    }
+    (wait, algo)
+}
+
+/// Two benchmarks for each contention: the time waiting for the lock and the
+/// time in the hook
+fn contended<P: Send + 'static, I: Clone>(
+    group: &mut BenchmarkGroup<criterion::measurement::WallTime>,
+    new_protocol: impl Fn() -> P,
+    read: fn(&P),
+    inputs: Vec<I>,
+    hook: impl Fn(I, &mut P),
+) {
+    for contention in CONTENTIONS {
+        group.bench_function(BenchmarkId::new("lock wait", contention), |b| {
+            b.iter_custom(|iters| run(iters, contention, &new_protocol, read, &inputs, &hook).0)
+        });
+        group.bench_function(BenchmarkId::new("algorithm", contention), |b| {
+            b.iter_custom(|iters| run(iters, contention, &new_protocol, read, &inputs, &hook).1)
+        });
+    }
+}
+
+pub fn less_cpu_lock_contention(c: &mut Criterion) {
+    use bench_sandbox::less_cpu::*;
+    let new_protocol = || FakeProtocol {
+        is_measured: false,
+        ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, 200, DIMS.operations)
+    };
+    let read = |protocol: &FakeProtocol| {
+        protocol.memory();
+    };
+    let fixture = Fixture::new(DIMS);
+    let batches = fixture.batches();
+
+    let mut group = c.benchmark_group("Less cpu on_batch_received contention");
+    group.sample_size(10);
+    contended(
+        &mut group,
+        new_protocol,
+        read,
+        batches.clone(),
+        |(node_id, batch), protocol| {
+            on_batch_received(batch, node_id, protocol);
+        },
+    );
+    group.finish();
+
+    let mut group = c.benchmark_group("Less cpu on_operation_received contention");
+    group.sample_size(10);
+    let operations: Vec<(u64, OperationMap)> = fixture.operations(|_| Operation::default());
+    contended(
+        &mut group,
+        new_protocol,
+        read,
+        operations.clone(),
+        |(node_id, operations), protocol| on_operation_received(node_id, operations, protocol),
+    );
+    group.finish();
+
+    // the asked operations are received before
+    let mut group = c.benchmark_group("Less cpu on_ask_received contention");
+    group.sample_size(10);
+    let with_operations = || {
+        let mut protocol = new_protocol();
+        for (node_id, operations) in operations.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        protocol
+    };
+    contended(
+        &mut group,
+        with_operations,
+        read,
+        batches,
+        |(node_id, batch), protocol| on_ask_received(node_id, batch, protocol),
+    );
+    group.finish();
+}
+
+pub fn more_cpu_lock_contention(c: &mut Criterion) {
+    use bench_sandbox::more_cpu::*;
+    let new_protocol = || FakeProtocol {
+        is_measured: false,
+        ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size)
+    };
+    let read = |protocol: &FakeProtocol| {
+        protocol.memory();
+    };
+    let fixture = Fixture::new(DIMS);
+    let batches = fixture.batches();
+
+    let mut group = c.benchmark_group("More cpu on_batch_received contention");
+    group.sample_size(10);
+    contended(
+        &mut group,
+        new_protocol,
+        read,
+        batches.clone(),
+        |(node_id, batch), protocol| on_batch_received(batch, node_id, protocol),
+    );
+    group.finish();
+
+    let mut group = c.benchmark_group("More cpu on_operation_received contention");
+    group.sample_size(10);
+    let operations: Vec<(u64, AskedOperations)> =
+        fixture.operations(|_| Some(Operation::default()));
+    contended(
+        &mut group,
+        new_protocol,
+        read,
+        operations.clone(),
+        |(node_id, operations), protocol| on_operation_received(node_id, operations, protocol),
+    );
+    group.finish();
+
+    // the asked operations are received before
+    let mut group = c.benchmark_group("More cpu on_ask_received contention");
+    group.sample_size(10);
+    let with_operations = || {
+        let mut protocol = new_protocol();
+        for (node_id, operations) in operations.iter() {
+            on_operation_received(*node_id, operations.clone(), &mut protocol);
+        }
+        protocol
+    };
+    contended(
+        &mut group,
+        with_operations,
+        read,
+        batches,
+        |(node_id, batch), protocol| on_ask_received(node_id, batch, protocol),
+    );
+    group.finish();
+}
@@ -1,2 +1,8 @@
+pub mod concurrent_handlers;
+pub mod deferred_asks;
+pub mod fixture;
+pub mod hooks;
+pub mod lock_contention;
+pub mod parameterised;
 pub mod receive_and_ask;
-pub mod simple_receive_batch;
\ No newline at end of file
+pub mod simple_receive_batch;
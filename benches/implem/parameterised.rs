@@ -0,0 +1,258 @@
+use super::{
+    fixture::{Dims, Fixture},
+    hooks::{bench_hook, MidRun},
+};
+use bench_sandbox::rng::{HashMap, HashSet};
+use criterion::{measurement::WallTime, BenchmarkGroup, BenchmarkId, Criterion, Throughput};
+use std::time::Duration;
+
+/// Each sweep changes one dimension of `BASE`
+const BASE: Dims = Dims::new(25, 100, 2_000);
+const NODES: [usize; 3] = [10, 50, 200];
+const BATCH_SIZES: [usize; 3] = [10, 100, 1000];
+const OPERATIONS: [usize; 3] = [1_000, 2_000, 10_000];
+
+/// Name of the dimension, its values and the dims of a value
+type Sweep = (&'static str, &'static [usize], fn(usize) -> Dims);
+
+const SWEEPS: [Sweep; 3] = [
+    ("nodes", &NODES, |nodes| Dims { nodes, ..BASE }),
+    ("batch size", &BATCH_SIZES, |batch_size| Dims {
+        batch_size,
+        ..BASE
+    }),
+    ("operations", &OPERATIONS, |operations| Dims {
+        operations,
+        ..BASE
+    }),
+];
+
+/// One group by dimension, `bench` adds the hooks for each value of the
+/// dimension
+fn sweep(
+    c: &mut Criterion,
+    algo: &str,
+    mut bench: impl FnMut(&mut BenchmarkGroup<WallTime>, &Fixture, usize),
+) {
+    for (dimension, values, dims_of) in SWEEPS {
+        let mut group = c.benchmark_group(format!("{} hooks by {}", algo, dimension));
+        group.sample_size(10);
+        for &value in values {
+            let dims = dims_of(value);
+            group.throughput(Throughput::Elements(dims.announced()));
+            bench(&mut group, &Fixture::new(dims), value);
+        }
+        group.finish();
+    }
+}
+
+/// Name of a loop and the loop
+type Loop<P> = (&'static str, fn(&mut P));
+
+/// The hooks of an algorithm with a protocol `P`, `O` is what an answer
+/// holds for each operation
+struct Hooks<P, O> {
+    name: &'static str,
+    new_protocol: fn(Dims) -> P,
+    operation: fn() -> O,
+    on_batch_received: fn(u64, HashSet<u64>, &mut P),
+    on_operation_received: fn(u64, HashMap<u64, O>, &mut P),
+    on_ask_received: fn(u64, HashSet<u64>, &mut P),
+    /// Run on the state of the middle of a run, see [MidRun]
+    loops: Vec<Loop<P>>,
+    /// Makes due what the loops wait for, once the state is populated
+    due: fn(&mut P, &MidRun<O>),
+}
+
+fn parameterised<P, O: Clone>(c: &mut Criterion, hooks: Hooks<P, O>) {
+    sweep(c, hooks.name, |group, fixture, value| {
+        let dims = fixture.dims;
+        let new_protocol = || (hooks.new_protocol)(dims);
+        let mid_run = MidRun::of(fixture, |_| (hooks.operation)());
+        let operations: Vec<(u64, HashMap<u64, O>)> = mid_run
+            .received
+            .iter()
+            .chain(mid_run.pending.iter())
+            .cloned()
+            .collect();
+        bench_hook(
+            group,
+            BenchmarkId::new("on_batch_received", value),
+            &new_protocol,
+            &mid_run.batches,
+            |protocol, batches| {
+                for (node_id, batch) in batches {
+                    (hooks.on_batch_received)(node_id, batch, protocol);
+                }
+            },
+        );
+        bench_hook(
+            group,
+            BenchmarkId::new("on_operation_received", value),
+            &new_protocol,
+            &operations,
+            |protocol, operations| {
+                for (node_id, operations) in operations {
+                    (hooks.on_operation_received)(node_id, operations, protocol);
+                }
+            },
+        );
+        // every operation is asked by every node
+        let all: HashMap<u64, O> = (0..dims.operations as u64)
+            .map(|op_id| (op_id, (hooks.operation)()))
+            .collect();
+        bench_hook(
+            group,
+            BenchmarkId::new("on_ask_received", value),
+            &|| {
+                let mut protocol = new_protocol();
+                (hooks.on_operation_received)(0, all.clone(), &mut protocol);
+                protocol
+            },
+            &mid_run.batches,
+            |protocol, batches| {
+                for (node_id, batch) in batches {
+                    (hooks.on_ask_received)(node_id, batch, protocol);
+                }
+            },
+        );
+        let populate = || {
+            let mut protocol = new_protocol();
+            for (node_id, batch) in mid_run.batches.iter() {
+                (hooks.on_batch_received)(*node_id, batch.clone(), &mut protocol);
+            }
+            for (node_id, operations) in mid_run.received.iter() {
+                (hooks.on_operation_received)(*node_id, operations.clone(), &mut protocol);
+            }
+            for (node_id, op_ids) in mid_run.asks.iter() {
+                (hooks.on_ask_received)(*node_id, op_ids.clone(), &mut protocol);
+            }
+            (hooks.due)(&mut protocol, &mid_run);
+            protocol
+        };
+        for (name, hook) in hooks.loops.iter() {
+            bench_hook(
+                group,
+                BenchmarkId::new(*name, value),
+                &populate,
+                &(),
+                |protocol, _| hook(protocol),
+            );
+        }
+    });
+}
+
+pub fn less_cpu_parameterised(c: &mut Criterion) {
+    use bench_sandbox::less_cpu::*;
+    parameterised(
+        c,
+        Hooks {
+            name: "Less cpu",
+            new_protocol: |dims| FakeProtocol {
+                is_measured: false,
+                ..FakeProtocol::new(dims.nodes, dims.batch_size, 200, dims.operations)
+            },
+            operation: Operation::default,
+            on_batch_received: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            on_operation_received,
+            on_ask_received,
+            loops: vec![("on_send_loop", on_send_loop)],
+            // the batches still pending wait in the buffer, like in the
+            // mid-run hooks, they are all due
+            due: |protocol, mid_run| {
+                let mut buffered = mid_run.batches[mid_run.received.len()..].to_vec();
+                while let Some((_, entry)) = protocol.op_batch_buffer.pop_first() {
+                    buffered.push(entry);
+                }
+                let now = std::time::Instant::now();
+                for entry in buffered {
+                    protocol.op_batch_buffer.push(now, entry);
+                }
+            },
+        },
+    );
+}
+
+pub fn more_cpu_parameterised(c: &mut Criterion) {
+    use bench_sandbox::more_cpu::*;
+    parameterised(
+        c,
+        Hooks {
+            name: "More cpu",
+            new_protocol: |dims| FakeProtocol {
+                is_measured: false,
+                ..FakeProtocol::new(dims.nodes, dims.batch_size)
+            },
+            operation: || Some(Operation::default()),
+            on_batch_received: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            on_operation_received,
+            on_ask_received,
+            loops: vec![
+                ("on_asking_loop", on_asking_loop),
+                ("on_send_operation_loop", on_send_operation_loop),
+            ],
+            due: |_, _| {
+                // this algorithm always queues what it sends
+                ASK_BATCH_QUEUE.lock().unwrap().clear();
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                SEND_OPERATION.lock().unwrap().clear();
+            },
+        },
+    );
+}
+
+pub fn hybrid_parameterised(c: &mut Criterion) {
+    use bench_sandbox::hybrid::*;
+    parameterised(
+        c,
+        Hooks {
+            name: "Hybrid",
+            new_protocol: |dims| FakeProtocol {
+                is_measured: false,
+                ..FakeProtocol::new(dims.nodes, dims.batch_size, Duration::from_millis(200))
+            },
+            operation: Operation::default,
+            on_batch_received: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            on_operation_received,
+            on_ask_received,
+            loops: vec![
+                ("on_asking_loop", on_asking_loop),
+                ("on_send_loop", on_send_loop),
+            ],
+            // the ids in flight of the wishlist are due
+            due: |protocol, _| protocol.retry_period = Duration::ZERO,
+        },
+    );
+}
+
+pub fn reconcile_parameterised(c: &mut Criterion) {
+    use bench_sandbox::reconcile::*;
+    parameterised(
+        c,
+        Hooks {
+            name: "Reconcile",
+            new_protocol: |dims| FakeProtocol {
+                is_measured: false,
+                ..FakeProtocol::new(dims.nodes, dims.batch_size, Duration::from_millis(200))
+            },
+            operation: Operation::default,
+            on_batch_received: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            on_operation_received,
+            on_ask_received,
+            loops: vec![
+                ("on_asking_loop", on_asking_loop),
+                ("on_send_loop", on_send_loop),
+            ],
+            // the ids in flight of the wishlist are due
+            due: |protocol, _| protocol.retry_period = Duration::ZERO,
+        },
+    );
+}
@@ -1,175 +1,173 @@
+use super::fixture::{Dims, Fixture};
+use bench_sandbox::rng::HashSet;
 use criterion::Criterion;
-use rand::{seq::SliceRandom, Rng};
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-pub fn less_cpu_receive_and_ask(c: &mut Criterion) {
-    use bench_sandbox::less_cpu::*;
-    const MAX_BATCH_SIZE: usize = 100;
-    const OP_BATCH_PROC_PERIOD: u64 = 200;
-    const T: usize = 25; // Numer of nodes
-    const N: usize = 10_000; // Number of operations
-                             // for this test we need to have the same buffer size as the input
-                             // operations number
-    const OP_BATCH_BUF_CAPACITY: usize = N;
-    if N % MAX_BATCH_SIZE != 0 {
-        panic!("For the test N should be a multiple of MAX_BATCH_SIZE");
-    }
-    let mut orders = Box::new([[0; N + 1]; T + 1]); // Order of operations for each node
-    let mut operations = Box::new([0usize; N + 1]); // Table of operations
-    for n in 0..N {
-        for order in orders.iter_mut() {
-            order[n] = n; // init an order to shuffle later
-        }
-        operations[n] = rand::random();
-    }
-    let mut thread_rng = rand::thread_rng();
-    for order in orders.iter_mut() {
-        order.shuffle(&mut thread_rng);
-    }
-    let protocol = Arc::new(Mutex::new(FakeProtocol::new(
-        T,
-        MAX_BATCH_SIZE,
-        OP_BATCH_PROC_PERIOD,
-        OP_BATCH_BUF_CAPACITY,
-    )));
-    let protocol_thrd = protocol.clone();
-    let running = Arc::new(AtomicBool::new(true));
-    let running_thrd = running.clone();
-    let batched = Arc::new(Mutex::new(OperationIds::default()));
-    let batched_thrd = Arc::new(Mutex::new(OperationIds::default()));
+const DIMS: Dims = Dims::new(25, 100, 10_000);
 
-    /* Thread to pop operations asked */
-    std::thread::spawn(move || {
-        let mut thread_rng = rand::thread_rng();
-        while running_thrd.load(std::sync::atomic::Ordering::Relaxed) {
-            let operations = {
-                let mut guard_op_ids = batched_thrd.lock().unwrap();
-                let op_ids = if guard_op_ids.len() > MAX_BATCH_SIZE {
-                    let mut op_ids = OperationMap::default();
-                    guard_op_ids.iter().take(MAX_BATCH_SIZE).for_each(|id| {
-                        op_ids.insert(*id, String::new());
-                    });
-                    op_ids.iter().for_each(|(id, _)| {
-                        guard_op_ids.remove(id);
-                    });
-                    op_ids
-                } else {
-                    continue;
-                };
-                op_ids
-            };
-
-            on_operation_received(
-                thread_rng.gen_range(0..T) as u64,
-                operations,
-                &mut protocol_thrd.lock().unwrap(),
-            );
-            // maybe I need to change the following
-            std::thread::sleep(std::time::Duration::from_nanos(20));
-        }
-    });
+/// Ids asked to a node
+type Ask = (u64, HashSet<u64>);
 
-    c.bench_function("Less cpu on receive batch with an asker thread", |b| {
-        b.iter(|| {
-            let mut p = 0;
-            while p < N {
-                for (node_id, order) in orders.iter().enumerate() {
-                    let mut batch = OperationIds::default();
-                    let mut guard_op_ids = batched.lock().unwrap();
-                    for &op_id in order.iter().skip(p).take(MAX_BATCH_SIZE) {
-                        batch.insert(op_id as u64);
-                        guard_op_ids.insert(op_id as u64);
+/// Feed the batches to a new protocol while an asker thread answers the asks
+/// with their operations. `receive` returns the ids asked by the hook, `poll`
+/// the ones asked by the loops of the algorithm, `answer` delivers the
+/// operations. The thread stops when the batches are done and nothing is
+/// asked anymore, it's joined before the next iteration. Only `receive` is
+/// measured, with the time waiting for the lock.
+fn with_asker<P: Send>(
+    iters: u64,
+    batches: &[Ask],
+    new_protocol: &impl Fn() -> P,
+    receive: &impl Fn(&mut P, u64, HashSet<u64>) -> HashSet<u64>,
+    poll: &(impl Fn(&mut P) -> Vec<Ask> + Sync),
+    answer: &(impl Fn(&mut P, u64, HashSet<u64>) + Sync),
+) -> Duration {
+    let mut measured = Duration::ZERO;
+    for _ in 0..iters {
+        let protocol = Mutex::new(new_protocol());
+        let batches_done = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel::<Ask>();
+        std::thread::scope(|scope| {
+            let (protocol, batches_done) = (&protocol, &batches_done);
+            /* Thread to answer the operations asked */
+            let asker = scope.spawn(move || {
+                let mut answered = 0;
+                loop {
+                    // loaded before the asks, the last ones are sent before
+                    let done = batches_done.load(Ordering::Acquire);
+                    let mut asks: Vec<Ask> = receiver.try_iter().collect();
+                    asks.extend(poll(&mut protocol.lock().unwrap()));
+                    if asks.is_empty() {
+                        if done {
+                            return answered;
+                        }
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    for (node_id, op_ids) in asks {
+                        answered += op_ids.len();
+                        answer(&mut protocol.lock().unwrap(), node_id, op_ids);
                     }
-                    std::mem::drop(guard_op_ids);
-                    // >>>>>>>>>> what we measure
-                    on_batch_received(batch, node_id as u64, &mut protocol.lock().unwrap());
-                    // <<<<<<<<<<
                 }
-                p += MAX_BATCH_SIZE;
+            });
+            for (node_id, batch) in batches.iter() {
+                let start = Instant::now();
+                // >>>>>>>>>> what we measure
+                let asked = receive(&mut protocol.lock().unwrap(), *node_id, batch.clone());
+                // <<<<<<<<<<
+                measured += start.elapsed();
+                if !asked.is_empty() {
+                    sender.send((*node_id, asked)).unwrap();
+                }
             }
-            running.store(false, std::sync::atomic::Ordering::Relaxed);
-        })
+            batches_done.store(true, Ordering::Release);
+            let answered = asker.join().unwrap();
+            // every operation is asked at least once
+            assert!(answered >= DIMS.operations);
+        });
+    }
+    measured
+}
+
+pub fn less_cpu_receive_and_ask(c: &mut Criterion) {
+    use bench_sandbox::less_cpu::*;
+    const OP_BATCH_PROC_PERIOD: u64 = 200;
+    // for this test we need to have the same buffer size as the input
+    // operations number
+    const OP_BATCH_BUF_CAPACITY: usize = DIMS.operations;
+    let batches = Fixture::new(DIMS).batches();
+    let new_protocol = || FakeProtocol {
+        is_measured: false,
+        ..FakeProtocol::new(
+            DIMS.nodes,
+            DIMS.batch_size,
+            OP_BATCH_PROC_PERIOD,
+            OP_BATCH_BUF_CAPACITY,
+        )
+    };
+    let receive =
+        |protocol: &mut FakeProtocol, node_id, batch| on_batch_received(batch, node_id, protocol);
+    // the future sets are asked again by `on_send_loop` after the period,
+    // the asked ids are received by then
+    let poll = |_: &mut FakeProtocol| vec![];
+    let answer = |protocol: &mut FakeProtocol, node_id, op_ids: OperationIds| {
+        let operations = op_ids
+            .into_iter()
+            .map(|op_id| (op_id, Operation::default()))
+            .collect();
+        on_operation_received(node_id, operations, protocol);
+    };
+
+    c.bench_function("Less cpu on receive batch with an asker thread", |b| {
+        b.iter_custom(|iters| with_asker(iters, &batches, &new_protocol, &receive, &poll, &answer))
     });
 }
 
 pub fn more_cpu_receive_and_ask(c: &mut Criterion) {
     use bench_sandbox::more_cpu::*;
-    const MAX_BATCH_SIZE: usize = 100;
-    const T: usize = 25; // Numer of nodes
-    const N: usize = 10_000; // Number of operations
-                             // for this test we need to have the same buffer size as the input
-                             // operations number
-    if N % MAX_BATCH_SIZE != 0 {
-        panic!("For the test N should be a multiple of MAX_BATCH_SIZE");
-    }
-    let mut orders = Box::new([[0; N + 1]; T + 1]); // Order of operations for each node
-    let mut operations = Box::new([0usize; N + 1]); // Table of operations
-    for n in 0..N {
-        for order in orders.iter_mut() {
-            order[n] = n; // init an order to shuffle later
-        }
-        operations[n] = rand::random();
-    }
-    let mut thread_rng = rand::thread_rng();
-    for order in orders.iter_mut() {
-        order.shuffle(&mut thread_rng);
-    }
-    let protocol = Arc::new(Mutex::new(FakeProtocol::new(T, MAX_BATCH_SIZE)));
-    let protocol_thrd = protocol.clone();
-    let running = Arc::new(AtomicBool::new(true));
-    let running_thrd = running.clone();
-    let batched = Arc::new(Mutex::new(OperationIds::default()));
-    let batched_thrd = Arc::new(Mutex::new(OperationIds::default()));
+    let batches = Fixture::new(DIMS).batches();
+    let new_protocol = || FakeProtocol {
+        is_measured: false,
+        ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size)
+    };
+    // the ids are asked by the asking loop, not by the hook
+    let receive = |protocol: &mut FakeProtocol, node_id, batch| {
+        on_batch_received(batch, node_id, protocol);
+        OperationIds::default()
+    };
+    let poll = |protocol: &mut FakeProtocol| {
+        on_asking_loop(protocol);
+        // the asks and the announcements are always queued by this algorithm
+        ASK_BATCH_QUEUE.lock().unwrap().clear();
+        BATCH_SEND_QUEUE.lock().unwrap().clear();
+        protocol
+            .wanted
+            .iter()
+            .filter(|(_, op_ids)| !op_ids.is_empty())
+            .map(|(node_id, op_ids)| (*node_id, op_ids.clone()))
+            .collect()
+    };
+    let answer = |protocol: &mut FakeProtocol, node_id, op_ids: OperationIds| {
+        let operations = op_ids
+            .into_iter()
+            .map(|op_id| (op_id, Some(Operation::default())))
+            .collect();
+        on_operation_received(node_id, operations, protocol);
+    };
 
-    /* Thread to pop operations asked */
-    std::thread::spawn(move || {
-        let mut thread_rng = rand::thread_rng();
-        while running_thrd.load(std::sync::atomic::Ordering::Relaxed) {
-            let operations = {
-                let mut guard_op_ids = batched_thrd.lock().unwrap();
-                let op_ids = if guard_op_ids.len() > MAX_BATCH_SIZE {
-                    let mut op_ids = AskedOperations::default();
-                    guard_op_ids.iter().take(MAX_BATCH_SIZE).for_each(|id| {
-                        op_ids.insert(*id, Some(String::new()));
-                    });
-                    op_ids.iter().for_each(|(id, _)| {
-                        guard_op_ids.remove(id);
-                    });
-                    op_ids
-                } else {
-                    continue;
-                };
-                op_ids
-            };
-            on_operation_received(
-                thread_rng.gen_range(0..T) as u64,
-                operations,
-                &mut protocol_thrd.lock().unwrap(),
-            );
-            // maybe I need to change the following
-            std::thread::sleep(std::time::Duration::from_nanos(20));
-        }
+    c.bench_function("More cpu on receive batch with an asker thread", |b| {
+        b.iter_custom(|iters| with_asker(iters, &batches, &new_protocol, &receive, &poll, &answer))
     });
+}
 
-    c.bench_function("More cpu on receive batch with an asker thread", |b| {
-        b.iter(|| {
-            let mut p = 0;
-            while p < N {
-                for (node_id, order) in orders.iter().enumerate() {
-                    let mut batch = OperationIds::default();
-                    let mut guard_op_ids = batched.lock().unwrap();
-                    for &op_id in order.iter().skip(p).take(MAX_BATCH_SIZE) {
-                        batch.insert(op_id as u64);
-                        guard_op_ids.insert(op_id as u64);
-                    }
-                    std::mem::drop(guard_op_ids);
-                    // >>>>>>>>>> what we measure
-                    on_batch_received(batch, node_id as u64, &mut protocol.lock().unwrap())
-                    // <<<<<<<<<<
-                }
-                p += MAX_BATCH_SIZE;
-            }
-            running.store(false, std::sync::atomic::Ordering::Relaxed);
-        })
+pub fn hybrid_receive_and_ask(c: &mut Criterion) {
+    use bench_sandbox::hybrid::*;
+    const RETRY_PERIOD: std::time::Duration = std::time::Duration::from_millis(200);
+    let batches = Fixture::new(DIMS).batches();
+    let new_protocol = || FakeProtocol {
+        is_measured: false,
+        ..FakeProtocol::new(DIMS.nodes, DIMS.batch_size, RETRY_PERIOD)
+    };
+    let receive =
+        |protocol: &mut FakeProtocol, node_id, batch| on_batch_received(batch, node_id, protocol);
+    // the wishlist holds ids in flight, they are answered before the retry
+    // period
+    let poll = |_: &mut FakeProtocol| vec![];
+    let answer = |protocol: &mut FakeProtocol, node_id, op_ids: OperationIds| {
+        let operations = op_ids
+            .into_iter()
+            .map(|op_id| (op_id, Operation::default()))
+            .collect();
+        on_operation_received(node_id, operations, protocol);
+    };
+
+    c.bench_function("Hybrid on receive batch with an asker thread", |b| {
+        b.iter_custom(|iters| with_asker(iters, &batches, &new_protocol, &receive, &poll, &answer))
     });
 }
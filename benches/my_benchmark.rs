@@ -1,15 +1,42 @@
 use criterion::{criterion_group, criterion_main};
 mod implem;
-use implem::receive_and_ask::{less_cpu_receive_and_ask, more_cpu_receive_and_ask};
-use implem::simple_receive_batch::{less_cpu_simple_receive, more_cpu_simple_receive};
-
-// TODO same test as less_cpu_simple_receive but with locks outsides (calls of asks)
+use implem::concurrent_handlers::{
+    hybrid_concurrent_handlers, less_cpu_concurrent_handlers, more_cpu_concurrent_handlers,
+};
+use implem::deferred_asks::deferred_asks;
+use implem::hooks::{hybrid_hooks, less_cpu_hooks, more_cpu_hooks, reconcile_hooks};
+use implem::lock_contention::{less_cpu_lock_contention, more_cpu_lock_contention};
+use implem::parameterised::{
+    hybrid_parameterised, less_cpu_parameterised, more_cpu_parameterised, reconcile_parameterised,
+};
+use implem::receive_and_ask::{
+    hybrid_receive_and_ask, less_cpu_receive_and_ask, more_cpu_receive_and_ask,
+};
+use implem::simple_receive_batch::{
+    hybrid_simple_receive, less_cpu_simple_receive, more_cpu_simple_receive,
+};
 
 criterion_group!(
     benches,
     less_cpu_simple_receive,
     more_cpu_simple_receive,
+    hybrid_simple_receive,
     less_cpu_receive_and_ask,
-    more_cpu_receive_and_ask
+    more_cpu_receive_and_ask,
+    hybrid_receive_and_ask,
+    deferred_asks,
+    less_cpu_concurrent_handlers,
+    more_cpu_concurrent_handlers,
+    hybrid_concurrent_handlers,
+    less_cpu_lock_contention,
+    more_cpu_lock_contention,
+    less_cpu_parameterised,
+    more_cpu_parameterised,
+    hybrid_parameterised,
+    reconcile_parameterised,
+    less_cpu_hooks,
+    more_cpu_hooks,
+    hybrid_hooks,
+    reconcile_hooks
 );
 criterion_main!(benches);
@@ -0,0 +1,141 @@
+//! Misbehaving peers of the simulations. The measurements ask here what a
+//! remote node announces and how it answers to our asks.
+
+use crate::operation::Operation;
+use crate::rng::{HashMap, HashSet};
+
+/// Ids from here are never the ones of real operations
+pub const FAKE_IDS: u64 = 1 << 62;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Behavior {
+    Honest,
+    /// Announce ids of operations it doesn't have, in place of some real ones
+    Phantom,
+    /// Never answer to the asks
+    Mute,
+    /// Answer with invalid operations
+    Corrupt,
+    /// Send batches far bigger than `max_batch_size`
+    Flood,
+    /// Send again its previous batch with each new one
+    Replay,
+}
+
+/// Behaviors of the remote nodes, the nodes not listed are honest
+#[derive(Clone, Debug, Default)]
+pub struct Adversaries {
+    behaviors: HashMap<u64, Behavior>,
+    next_fake_id: u64,
+}
+
+impl Adversaries {
+    pub fn with(mut self, node_id: u64, behavior: Behavior) -> Self {
+        self.behaviors.insert(node_id, behavior);
+        self
+    }
+
+    pub fn behavior(&self, node_id: u64) -> Behavior {
+        self.behaviors
+            .get(&node_id)
+            .cloned()
+            .unwrap_or(Behavior::Honest)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.behaviors.is_empty()
+    }
+
+    fn fake_ids(&mut self, count: usize) -> impl Iterator<Item = u64> {
+        let start = FAKE_IDS + self.next_fake_id;
+        self.next_fake_id += count as u64;
+        start..start + count as u64
+    }
+
+    /// Batches really sent by `node_id` when an honest node would send
+    /// `batch`. `previous` is the last honest batch of the node.
+    pub fn announce(
+        &mut self,
+        node_id: u64,
+        batch: HashSet<u64>,
+        previous: Option<&HashSet<u64>>,
+        max_batch_size: usize,
+    ) -> Vec<HashSet<u64>> {
+        match self.behavior(node_id) {
+            Behavior::Phantom => {
+                // fake ids in place of real ones, the batch isn't oversized
+                let fakes = max_batch_size / 10 + 1;
+                let mut real: Vec<u64> = batch.into_iter().collect();
+                real.sort_unstable();
+                real.truncate(max_batch_size.saturating_sub(fakes));
+                let mut batch: HashSet<u64> = real.into_iter().collect();
+                batch.extend(self.fake_ids(fakes.min(max_batch_size)));
+                vec![batch]
+            }
+            Behavior::Flood => {
+                let mut batch = batch;
+                batch.extend(self.fake_ids(max_batch_size * 10));
+                vec![batch]
+            }
+            Behavior::Replay => match previous {
+                Some(previous) => vec![batch, previous.clone()],
+                None => vec![batch],
+            },
+            _ => vec![batch],
+        }
+    }
+
+    /// Answer of `node_id` to an ask of `op_ids`, None if it never answers.
+    /// `operation` gives the real operation of an id.
+    pub fn answer<F: Fn(u64) -> Operation>(
+        &self,
+        node_id: u64,
+        op_ids: &HashSet<u64>,
+        operation: F,
+    ) -> Option<Vec<(u64, Option<Operation>)>> {
+        let behavior = self.behavior(node_id);
+        if behavior == Behavior::Mute {
+            return None;
+        }
+        let answer = op_ids
+            .iter()
+            .map(|&op_id| {
+                if op_id >= FAKE_IDS {
+                    // nobody has that operation
+                    return (op_id, None);
+                }
+                let mut op = operation(op_id);
+                if behavior == Behavior::Corrupt {
+                    op.content = String::from("corrupted");
+                }
+                (op_id, Some(op))
+            })
+            .collect();
+        Some(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phantom_batches_are_not_oversized() {
+        let mut adversaries = Adversaries::default().with(1, Behavior::Phantom);
+        let batch: HashSet<u64> = (0..10).collect();
+        let batches = adversaries.announce(1, batch.clone(), None, 10);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 10);
+        let fakes = batches[0]
+            .iter()
+            .filter(|&&op_id| op_id >= FAKE_IDS)
+            .count();
+        assert_eq!(fakes, 2);
+        assert!(batches[0]
+            .iter()
+            .all(|op_id| *op_id >= FAKE_IDS || batch.contains(op_id)));
+        // a small batch keeps its real ids
+        let batches = adversaries.announce(1, (0..3).collect(), None, 10);
+        assert_eq!(batches[0].len(), 5);
+    }
+}
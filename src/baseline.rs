@@ -0,0 +1,404 @@
+//! Results of the benches and of the measurements, stored by git revision in
+//! a local file. A new run is compared to a stored one with a Mann-Whitney U
+//! test: an algorithm regresses when it's slower (or uses more bandwidth) with
+//! a significant difference and beyond a threshold.
+
+use crate::metrics::Report;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::Path,
+    process::Command,
+    time::SystemTime,
+};
+
+/// Where the results are stored, one line by revision, metric and benchmark
+pub const BASELINE_FILE: &str = "baselines.tsv";
+/// Significance of the tests
+pub const ALPHA: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Metric {
+    /// Time of an iteration of a bench, in nanoseconds
+    Time,
+    /// Bytes exchanged by the measured node in a scenario
+    Bandwidth,
+    /// Average time to receive an operation in a scenario, in milliseconds
+    Latency,
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Time => "time",
+            Metric::Bandwidth => "bandwidth",
+            Metric::Latency => "latency",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Metric::Time, Metric::Bandwidth, Metric::Latency]
+            .into_iter()
+            .find(|metric| metric.name() == name)
+    }
+}
+
+/// Samples of each benchmark, by metric and name
+pub type Results = BTreeMap<(Metric, String), Vec<f64>>;
+
+/// Keep the latency and the bandwidth of a run of `algorithm`
+pub fn record_report(results: &mut Results, algorithm: &str, scenario: &str, report: &Report) {
+    let name = format!("{}/{}", algorithm, scenario);
+    results
+        .entry((Metric::Latency, name.clone()))
+        .or_default()
+        .push(report.latency.as_secs_f64() * 1_000.);
+    results
+        .entry((Metric::Bandwidth, name))
+        .or_default()
+        .push(report.bandwidth as f64);
+}
+
+/// Run the benches matching `filter` (all of them if empty) and read the
+/// time of each sample from the `raw.csv` files written by criterion
+pub fn run_benches(filter: &str, results: &mut Results) -> io::Result<()> {
+    let start = SystemTime::now();
+    let mut command = Command::new("cargo");
+    command.args(["bench", "--bench", "my_benchmark"]);
+    if !filter.is_empty() {
+        command.args(["--", filter]);
+    }
+    if !command.status()?.success() {
+        return Err(io::Error::other("cargo bench failed"));
+    }
+    read_criterion_dir(Path::new("target/criterion"), start, results)
+}
+
+/// The `new/raw.csv` files written since `since`, the other ones are from
+/// the previous runs
+fn read_criterion_dir(dir: &Path, since: SystemTime, results: &mut Results) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            read_criterion_dir(&path, since, results)?;
+            continue;
+        }
+        let is_new = path.ends_with("new/raw.csv") && fs::metadata(&path)?.modified()? >= since;
+        if !is_new {
+            continue;
+        }
+        // group,function,value,throughput_num,throughput_type,sample_measured_value,unit,iteration_count
+        for line in io::BufReader::new(fs::File::open(&path)?).lines().skip(1) {
+            let line = line?;
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() != 8 {
+                continue;
+            }
+            let (measured, iterations) = match (fields[5].parse::<f64>(), fields[7].parse::<f64>())
+            {
+                (Ok(measured), Ok(iterations)) if iterations > 0. => (measured, iterations),
+                _ => continue,
+            };
+            let name = fields[..3]
+                .iter()
+                .filter(|field| !field.is_empty())
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("/");
+            results
+                .entry((Metric::Time, name))
+                .or_default()
+                .push(measured / iterations);
+        }
+    }
+    Ok(())
+}
+
+/// Current git revision, with a `-dirty` suffix if the tree has changes
+pub fn revision() -> io::Result<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()?;
+    let mut revision = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if revision.is_empty() {
+        return Err(io::Error::other("not a git repository"));
+    }
+    let status = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .output()?;
+    if !status.stdout.is_empty() {
+        revision.push_str("-dirty");
+    }
+    Ok(revision)
+}
+
+/// Stored results by revision, in the order they were recorded
+pub fn load(path: &Path) -> io::Result<Vec<(String, Results)>> {
+    let mut stored: Vec<(String, Results)> = vec![];
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(stored),
+        Err(err) => return Err(err),
+    };
+    // revision <tab> metric <tab> name <tab> comma separated samples
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let metric = match fields.get(1).and_then(|name| Metric::parse(name)) {
+            Some(metric) if fields.len() == 4 => metric,
+            _ => continue,
+        };
+        let samples = fields[3]
+            .split(',')
+            .filter_map(|value| value.parse().ok())
+            .collect();
+        let index = match stored.iter().position(|(rev, _)| rev == fields[0]) {
+            Some(index) => index,
+            None => {
+                stored.push((fields[0].to_string(), Results::default()));
+                stored.len() - 1
+            }
+        };
+        stored[index]
+            .1
+            .insert((metric, fields[2].to_string()), samples);
+    }
+    Ok(stored)
+}
+
+/// Store the results of `revision`, replacing what was stored for it
+pub fn store(path: &Path, revision: &str, results: &Results) -> io::Result<()> {
+    let mut stored = load(path)?;
+    stored.retain(|(rev, _)| rev != revision);
+    stored.push((revision.to_string(), results.clone()));
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    for (rev, results) in stored.iter() {
+        for ((metric, name), samples) in results.iter() {
+            let samples: Vec<String> = samples.iter().map(|value| value.to_string()).collect();
+            writeln!(
+                file,
+                "{}\t{}\t{}\t{}",
+                rev,
+                metric.name(),
+                name,
+                samples.join(",")
+            )?;
+        }
+    }
+    file.flush()
+}
+
+fn median(samples: &[f64]) -> f64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    match sorted.len() {
+        0 => 0.,
+        len if len % 2 == 0 => (sorted[len / 2 - 1] + sorted[len / 2]) / 2.,
+        len => sorted[len / 2],
+    }
+}
+
+/// Standard normal cumulative distribution, Abramowitz and Stegun 7.1.26
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1. / (1. + 0.327_591_1 * x);
+    let poly = t
+        * (0.254_829_592
+            + t * (-0.284_496_736
+                + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let erf = 1. - poly * (-x * x).exp();
+    if z >= 0. {
+        (1. + erf) / 2.
+    } else {
+        (1. - erf) / 2.
+    }
+}
+
+/// One-sided p-value of a Mann-Whitney U test that `new` is greater than
+/// `base`, with the normal approximation corrected for the ties
+pub fn mann_whitney_greater(new: &[f64], base: &[f64]) -> f64 {
+    let (n1, n2) = (new.len() as f64, base.len() as f64);
+    if new.is_empty() || base.is_empty() {
+        return 1.;
+    }
+    let mut u = 0.;
+    for a in new {
+        for b in base {
+            if a > b {
+                u += 1.;
+            } else if a == b {
+                u += 0.5;
+            }
+        }
+    }
+    let mut all: Vec<f64> = new.iter().chain(base).cloned().collect();
+    all.sort_by(|a, b| a.total_cmp(b));
+    let ties: f64 = all
+        .chunk_by(|a, b| a == b)
+        .map(|tie| {
+            let t = tie.len() as f64;
+            t * t * t - t
+        })
+        .sum();
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12. * ((n + 1.) - ties / (n * (n - 1.)));
+    if variance <= 0. {
+        return 1.;
+    }
+    let z = (u - n1 * n2 / 2. - 0.5) / variance.sqrt();
+    1. - normal_cdf(z)
+}
+
+/// A benchmark worse in `new` than in `base`
+#[derive(Clone, Debug)]
+pub struct Regression {
+    pub metric: Metric,
+    pub name: String,
+    /// Median of the new samples over the median of the stored ones
+    pub ratio: f64,
+    pub p_value: f64,
+}
+
+/// Compare each benchmark present in both runs and print it. The ones with a
+/// median more than `threshold` worse (0.1 for 10%), and a p-value under
+/// [ALPHA], are returned.
+pub fn compare(base: &Results, new: &Results, threshold: f64) -> Vec<Regression> {
+    let mut regressions = vec![];
+    for ((metric, name), samples) in new.iter() {
+        let stored = match base.get(&(*metric, name.clone())) {
+            Some(stored) => stored,
+            None => continue,
+        };
+        let ratio = median(samples) / median(stored).max(f64::EPSILON);
+        let p_value = mann_whitney_greater(samples, stored);
+        let regressed = ratio > 1. + threshold && p_value < ALPHA;
+        println!(
+            "{} {} {}: x{:.3} (p = {:.4})",
+            if regressed { "REGRESSION" } else { "ok" },
+            metric.name(),
+            name,
+            ratio,
+            p_value
+        );
+        if regressed {
+            regressions.push(Regression {
+                metric: *metric,
+                name: name.clone(),
+                ratio,
+                p_value,
+            });
+        }
+    }
+    regressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: f64, expected: f64) {
+        assert!(
+            (value - expected).abs() < 1e-4,
+            "{} instead of {}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        assert_eq!(median(&[3., 1., 2.]), 2.);
+        assert_eq!(median(&[4., 1., 3., 2.]), 2.5);
+        assert_eq!(median(&[]), 0.);
+    }
+
+    #[test]
+    fn mann_whitney_known_values() {
+        // U = 0, no ties
+        assert_close(mann_whitney_greater(&[1., 2., 3.], &[4., 5., 6.]), 0.98545);
+        // U = 5, a tie of three 2s
+        assert_close(mann_whitney_greater(&[2., 2., 3.], &[1., 2.]), 0.16646);
+    }
+
+    #[test]
+    fn mann_whitney_minimum_p_of_five_against_five() {
+        let new = [6., 7., 8., 9., 10.];
+        let base = [1., 2., 3., 4., 5.];
+        let p_value = mann_whitney_greater(&new, &base);
+        // the exact test gives 1/252, the normal approximation a bit more
+        assert_close(p_value, 0.00609);
+        assert!(p_value < ALPHA);
+        assert!(mann_whitney_greater(&base, &new) > 1. - ALPHA);
+    }
+
+    #[test]
+    fn mann_whitney_with_only_ties() {
+        assert_eq!(mann_whitney_greater(&[1.; 5], &[1.; 5]), 1.);
+        assert_eq!(mann_whitney_greater(&[], &[1.]), 1.);
+    }
+
+    #[test]
+    fn compare_finds_the_significant_regressions() {
+        let base: Results = [
+            (
+                (Metric::Time, "slower".to_string()),
+                vec![1., 2., 3., 4., 5.],
+            ),
+            ((Metric::Time, "same".to_string()), vec![1., 2., 3., 4., 5.]),
+        ]
+        .into();
+        let new: Results = [
+            (
+                (Metric::Time, "slower".to_string()),
+                vec![6., 7., 8., 9., 10.],
+            ),
+            ((Metric::Time, "same".to_string()), vec![1., 2., 3., 4., 5.]),
+            ((Metric::Latency, "new".to_string()), vec![10.]),
+        ]
+        .into();
+        let regressions = compare(&base, &new, 0.1);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].metric, Metric::Time);
+        assert_eq!(regressions[0].name, "slower");
+        assert_close(regressions[0].ratio, 8. / 3.);
+        // beyond the threshold
+        assert!(compare(&base, &new, 2.).is_empty());
+    }
+
+    #[test]
+    fn store_then_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("baselines-{}.tsv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(load(&path).unwrap().is_empty());
+        let first: Results = [
+            ((Metric::Time, "group/bench".to_string()), vec![1.5, 2.25]),
+            (
+                (Metric::Bandwidth, "less_cpu/honest".to_string()),
+                vec![1e9],
+            ),
+        ]
+        .into();
+        let second: Results = [((Metric::Latency, "hybrid/churn".to_string()), vec![0.125])].into();
+        store(&path, "abc", &first).unwrap();
+        store(&path, "def", &second).unwrap();
+        assert_eq!(
+            load(&path).unwrap(),
+            [
+                ("abc".to_string(), first.clone()),
+                ("def".to_string(), second)
+            ]
+        );
+        // storing a revision again replaces it
+        store(&path, "def", &first).unwrap();
+        let stored = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            stored,
+            [
+                ("abc".to_string(), first.clone()),
+                ("def".to_string(), first)
+            ]
+        );
+    }
+}
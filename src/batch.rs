@@ -0,0 +1,199 @@
+//! Size limits of the batches of operation ids, for the batches we send and
+//! the ones we receive
+
+use crate::operation::{by_priority, OperationFees};
+use crate::rng::HashSet;
+use std::time::{Duration, Instant};
+
+/// What we do with a received batch bigger than `max_batch_size`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OversizedPolicy {
+    /// Ignore the whole batch
+    Reject,
+    /// Keep the `max_batch_size` most prioritary ids
+    #[default]
+    Truncate,
+}
+
+/// Split the `op_ids` in batches of at most `max_batch_size` ids, a size of 0
+/// is taken as 1
+pub fn split_batch<I: IntoIterator<Item = u64>>(
+    op_ids: I,
+    max_batch_size: usize,
+) -> Vec<HashSet<u64>> {
+    let op_ids: Vec<u64> = op_ids.into_iter().collect();
+    op_ids
+        .chunks(max_batch_size.max(1))
+        .map(|chunk| chunk.iter().cloned().collect())
+        .collect()
+}
+
+/// Apply the `policy` to a received batch, return None if the batch is
+/// rejected.
+pub fn limit_batch(
+    op_batch: HashSet<u64>,
+    max_batch_size: usize,
+    policy: OversizedPolicy,
+    fees: &OperationFees,
+) -> Option<HashSet<u64>> {
+    if op_batch.len() <= max_batch_size {
+        return Some(op_batch);
+    }
+    match policy {
+        OversizedPolicy::Reject => None,
+        OversizedPolicy::Truncate => Some(
+            by_priority(op_batch, fees)
+                .into_iter()
+                .take(max_batch_size)
+                .collect(),
+        ),
+    }
+}
+
+/// Announcements waiting to be sent to a peer, flushed when `max_batch_size`
+/// ids are buffered or when the oldest one waited long enough.
+#[derive(Debug, Default)]
+pub struct AnnounceBuffer {
+    pub op_ids: Vec<u64>,
+    /// When the oldest buffered id was pushed
+    pub since: Option<Instant>,
+}
+
+impl AnnounceBuffer {
+    /// Buffer the `op_ids` and return the batches ready to be sent: the full
+    /// ones, and the rest if it waited more than `delay`
+    pub fn push<I: IntoIterator<Item = u64>>(
+        &mut self,
+        op_ids: I,
+        max_batch_size: usize,
+        delay: Duration,
+        now: Instant,
+    ) -> Vec<HashSet<u64>> {
+        self.op_ids.extend(op_ids);
+        if self.op_ids.is_empty() {
+            return vec![];
+        }
+        self.since.get_or_insert(now);
+        let full = self.op_ids.len() - self.op_ids.len() % max_batch_size.max(1);
+        let mut batches = split_batch(self.op_ids.drain(..full), max_batch_size);
+        if full > 0 {
+            // less than a batch was buffered, the ids left are all new ones
+            self.since = (!self.op_ids.is_empty()).then_some(now);
+        }
+        batches.extend(self.take_due(delay, now));
+        batches
+    }
+
+    /// Take the buffered ids if the oldest one waited more than `delay`
+    pub fn take_due(&mut self, delay: Duration, now: Instant) -> Option<HashSet<u64>> {
+        match self.since {
+            Some(since) if now.saturating_duration_since(since) >= delay => {
+                self.since = None;
+                Some(self.op_ids.drain(..).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How full are the announcement batches we send
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchFill {
+    pub batches: u64,
+    pub op_ids: u64,
+}
+
+impl BatchFill {
+    pub fn record(&mut self, batch: &HashSet<u64>) {
+        self.batches += 1;
+        self.op_ids += batch.len() as u64;
+    }
+
+    /// Average number of ids by batch
+    pub fn average(&self) -> f64 {
+        if self.batches == 0 {
+            return 0.;
+        }
+        self.op_ids as f64 / self.batches as f64
+    }
+
+    pub fn print(&self, max_batch_size: usize) {
+        println!(
+            "Announcements: {} batches, {:.1} ids on average ({:.1}% of max_batch_size)",
+            self.batches,
+            self.average(),
+            100. * self.average() / max_batch_size as f64
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_batch_keeps_every_id() {
+        let batches = split_batch(0..250, 100);
+        let sizes: Vec<usize> = batches.iter().map(HashSet::len).collect();
+        assert_eq!(sizes, [100, 100, 50]);
+        let ids: HashSet<u64> = batches.into_iter().flatten().collect();
+        assert_eq!(ids, (0..250).collect());
+        assert!(split_batch(0..0, 100).is_empty());
+    }
+
+    #[test]
+    fn split_batch_of_zero_size() {
+        assert_eq!(split_batch(0..3, 0).len(), 3);
+    }
+
+    #[test]
+    fn limit_batch_rejects_oversized() {
+        let fees = OperationFees::default();
+        let batch: HashSet<u64> = (0..10).collect();
+        assert_eq!(
+            limit_batch(batch.clone(), 10, OversizedPolicy::Reject, &fees),
+            Some(batch.clone())
+        );
+        assert_eq!(limit_batch(batch, 9, OversizedPolicy::Reject, &fees), None);
+    }
+
+    #[test]
+    fn limit_batch_truncates_to_the_best_fees() {
+        let fees: OperationFees = (0..10).map(|op_id| (op_id, op_id * 10)).collect();
+        let batch: HashSet<u64> = (0..10).collect();
+        assert_eq!(
+            limit_batch(batch, 3, OversizedPolicy::Truncate, &fees),
+            Some([7, 8, 9].into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn announce_buffer_sends_full_batches_then_the_rest() {
+        let mut buffer = AnnounceBuffer::default();
+        let delay = Duration::from_millis(5);
+        let now = Instant::now();
+        let batches = buffer.push(0..25, 10, delay, now);
+        assert_eq!(batches.len(), 2);
+        assert!(batches.iter().all(|batch| batch.len() == 10));
+        assert!(buffer.take_due(delay, now).is_none());
+        let rest = buffer.take_due(delay, now + delay).unwrap();
+        assert_eq!(rest, (20..25).collect());
+        assert!(buffer.push(25..26, 0, delay, now).len() == 1);
+    }
+
+    #[test]
+    fn announce_buffer_times_the_ids_left_after_a_full_batch() {
+        let mut buffer = AnnounceBuffer::default();
+        let delay = Duration::from_millis(5);
+        let now = Instant::now();
+        assert!(buffer.push(0..5, 10, delay, now).is_empty());
+        let later = now + Duration::from_millis(3);
+        let batches = buffer.push(5..18, 10, delay, later);
+        assert_eq!(batches, [(0..10).collect()]);
+        assert_eq!(buffer.since, Some(later));
+        assert!(buffer.take_due(delay, now + delay).is_none());
+        let rest = buffer.take_due(delay, later + delay).unwrap();
+        assert_eq!(rest, (10..18).collect());
+        assert_eq!(buffer.since, None);
+    }
+}
@@ -0,0 +1,304 @@
+//! Captures of the operation announcements received by a real node, imported
+//! as a [Trace] to replay them into the algorithms instead of the shuffled
+//! orders of the scenarios.
+//!
+//! A capture is a CSV file, one announcement by line:
+//!
+//! ```text
+//! timestamp_ms,peer,op_ids,sizes
+//! 1650000000000,N12LaPL...,O1xy...;O1zt...,215;302
+//! ```
+//!
+//! - `timestamp_ms`: when the announcement was received, in milliseconds from
+//!   any origin (unix time in the logs of a node)
+//! - `peer`: id of the node that sent it, any string without a comma
+//! - `op_ids`: ids of the announced operations, separated by `;`
+//! - `sizes`: size in bytes of each operation, in the order of `op_ids`
+//!
+//! The header line is optional, the empty lines and the lines starting with
+//! `#` are skipped.
+
+use crate::{
+    batch::split_batch,
+    harness::MAX_BATCH_SIZE,
+    operation::OPERATION_ID_SIZE,
+    rng::{HashMap, HashSet},
+    trace::{Answers, Event, Trace},
+};
+use std::{fs, io, path::Path, time::Duration};
+
+type NodeId = u64;
+type OperationId = u64;
+
+const HEADER: &str = "timestamp_ms,peer,op_ids,sizes";
+
+/// How the events missing from a capture are made up
+#[derive(Clone, Copy, Debug)]
+pub struct ImportConfig {
+    /// Delay between an ask and its answer. A capture only has the
+    /// announcements, the asks depend on the algorithm: a node answers the
+    /// asked operations it announced before.
+    pub answer_delay: Duration,
+    /// Period of the ticks of the send loop and of the asking loop
+    pub tick: Duration,
+    /// The bigger announcements of a real node are replayed as several
+    /// batches, like the simulated nodes send them
+    pub max_batch_size: usize,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            answer_delay: Duration::from_millis(50),
+            tick: Duration::from_millis(1),
+            max_batch_size: MAX_BATCH_SIZE,
+        }
+    }
+}
+
+/// Announcement of a capture, with the ids of the simulator
+struct Announcement {
+    at: Duration,
+    node_id: NodeId,
+    op_ids: Vec<OperationId>,
+}
+
+/// What an imported capture holds
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Capture {
+    pub peers: usize,
+    pub announcements: usize,
+    pub operations: usize,
+    /// Ids announced, counted once by announcement
+    pub announced_ids: usize,
+    /// Bytes of the operations, counted once each
+    pub operation_bytes: u64,
+    pub duration: Duration,
+}
+
+impl Capture {
+    pub fn print(&self) {
+        println!(
+            "{} peers, {} announcements of {} ids ({} bytes), {} operations ({} bytes, {:.1} announcements each), over {:?}",
+            self.peers,
+            self.announcements,
+            self.announced_ids,
+            self.announced_ids * OPERATION_ID_SIZE,
+            self.operations,
+            self.operation_bytes,
+            self.announced_ids as f64 / self.operations.max(1) as f64,
+            self.duration
+        );
+    }
+}
+
+fn invalid(path: &Path, line: usize, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path.display(), line, reason),
+    )
+}
+
+/// Read the capture at `path` and convert it to the inputs of a run: the
+/// peers are connected at their first announcement and each announcement is
+/// split in batches of [ImportConfig::max_batch_size] ids. On replay, the asks are answered [ImportConfig::answer_delay]
+/// later with operations of their captured size, and the loops tick every
+/// [ImportConfig::tick] (see [Trace::tick] and [Trace::answers]). The peers
+/// and the operations are numbered in the order they appear.
+pub fn import(path: &Path, config: ImportConfig) -> io::Result<(Trace, Capture)> {
+    let content = fs::read_to_string(path)?;
+    let mut peers: HashMap<String, NodeId> = HashMap::default();
+    let mut operations: HashMap<String, OperationId> = HashMap::default();
+    let mut sizes: HashMap<OperationId, usize> = HashMap::default();
+    let mut lines = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (index == 0 && line == HEADER) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 4 {
+            return Err(invalid(path, index + 1, "expected 4 fields"));
+        }
+        let timestamp: u64 = fields[0]
+            .trim()
+            .parse()
+            .map_err(|_| invalid(path, index + 1, "invalid timestamp"))?;
+        let peer = fields[1].trim();
+        let next_node_id = peers.len() as NodeId;
+        let node_id = *peers.entry(peer.to_string()).or_insert(next_node_id);
+        let op_ids: Vec<&str> = fields[2].split(';').map(str::trim).collect();
+        let op_sizes: Vec<&str> = fields[3].split(';').map(str::trim).collect();
+        if op_ids.len() != op_sizes.len() {
+            return Err(invalid(path, index + 1, "as many sizes as ids expected"));
+        }
+        let mut announced = Vec::with_capacity(op_ids.len());
+        for (op_id, size) in op_ids.into_iter().zip(op_sizes) {
+            if op_id.is_empty() {
+                continue;
+            }
+            let size: usize = size
+                .parse()
+                .map_err(|_| invalid(path, index + 1, "invalid size"))?;
+            let next_op_id = operations.len() as OperationId;
+            let op_id = *operations.entry(op_id.to_string()).or_insert(next_op_id);
+            sizes.entry(op_id).or_insert(size);
+            announced.push(op_id);
+        }
+        lines.push((timestamp, node_id, announced));
+    }
+
+    // the logs of several threads can be a bit out of order
+    lines.sort_by_key(|(timestamp, _, _)| *timestamp);
+    let origin = lines.first().map_or(0, |(timestamp, _, _)| *timestamp);
+    let announcements: Vec<Announcement> = lines
+        .into_iter()
+        .map(|(timestamp, node_id, op_ids)| Announcement {
+            at: Duration::from_millis(timestamp - origin),
+            node_id,
+            op_ids,
+        })
+        .collect();
+
+    let mut capture = Capture {
+        peers: peers.len(),
+        announcements: announcements.len(),
+        operations: operations.len(),
+        operation_bytes: sizes.values().map(|size| *size as u64).sum(),
+        ..Capture::default()
+    };
+    let mut events = vec![];
+    let mut connected = HashSet::default();
+    for announcement in announcements.iter() {
+        if connected.insert(announcement.node_id) {
+            events.push((announcement.at, Event::Connected(announcement.node_id)));
+        }
+        capture.announced_ids += announcement.op_ids.len();
+        for batch in split_batch(announcement.op_ids.iter().cloned(), config.max_batch_size) {
+            events.push((announcement.at, Event::Batch(announcement.node_id, batch)));
+        }
+    }
+    capture.duration = events.last().map(|(at, _)| *at).unwrap_or_default();
+    let trace = Trace {
+        events,
+        tick: Some(config.tick).filter(|tick| !tick.is_zero()),
+        answers: Some(Answers {
+            delay: config.answer_delay,
+            sizes,
+        }),
+    };
+    Ok((trace, capture))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Import `content` written in a file of the temporary directory
+    fn import_str(name: &str, content: &str, config: ImportConfig) -> io::Result<Trace> {
+        let path =
+            std::env::temp_dir().join(format!("capture-{}-{}.csv", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let imported = import(&path, config);
+        fs::remove_file(&path).unwrap();
+        imported.map(|(trace, _)| trace)
+    }
+
+    #[test]
+    fn capture_is_imported_and_written_back() {
+        let content = "timestamp_ms,peer,op_ids,sizes
+# a comment
+
+1000,alice,Oa;Ob,215;302
+1010,bob,Ob;Oc,302;100
+";
+        let trace = import_str("small", content, ImportConfig::default()).unwrap();
+        let events: Vec<(Duration, Event)> = vec![
+            (Duration::ZERO, Event::Connected(0)),
+            (
+                Duration::ZERO,
+                Event::Batch(0, [0, 1].into_iter().collect()),
+            ),
+            (Duration::from_millis(10), Event::Connected(1)),
+            (
+                Duration::from_millis(10),
+                Event::Batch(1, [1, 2].into_iter().collect()),
+            ),
+        ];
+        assert_eq!(trace.events, events);
+        let answers = trace.answers.clone().unwrap();
+        assert_eq!(
+            answers.sizes,
+            [(0, 215), (1, 302), (2, 100)].into_iter().collect()
+        );
+
+        let path = std::env::temp_dir().join(format!("capture-{}.trace", std::process::id()));
+        trace.write(&path).unwrap();
+        let read = Trace::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.events, events);
+        assert_eq!(read.tick, trace.tick);
+        assert_eq!(read.answers, Some(answers));
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let config = ImportConfig::default();
+        for (name, content) in [
+            ("fields", "1000,alice,Oa\n"),
+            ("timestamp", "soon,alice,Oa,215\n"),
+            ("size", "1000,alice,Oa,big\n"),
+            ("counts", "1000,alice,Oa;Ob,215\n"),
+        ] {
+            let err = import_str(name, content, config).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", name);
+        }
+    }
+
+    #[test]
+    fn announcements_are_sorted_by_timestamp() {
+        let content = "1020,alice,Oc,1\n1000,bob,Oa,1\n1010,alice,Ob,1\n";
+        let trace = import_str("unsorted", content, ImportConfig::default()).unwrap();
+        let batches: Vec<(Duration, NodeId)> = trace
+            .events
+            .iter()
+            .filter_map(|(at, event)| match event {
+                Event::Batch(node_id, _) => Some((*at, *node_id)),
+                _ => None,
+            })
+            .collect();
+        // the peers and the operations are numbered in the order of the file
+        assert_eq!(
+            batches,
+            [
+                (Duration::ZERO, 1),
+                (Duration::from_millis(10), 0),
+                (Duration::from_millis(20), 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn big_announcements_are_split_in_batches() {
+        let op_ids: Vec<String> = (0..25).map(|op_id| format!("O{}", op_id)).collect();
+        let sizes = vec!["1"; 25];
+        let content = format!("1000,alice,{},{}\n", op_ids.join(";"), sizes.join(";"));
+        let config = ImportConfig {
+            max_batch_size: 10,
+            ..ImportConfig::default()
+        };
+        let trace = import_str("big", &content, config).unwrap();
+        let batches: Vec<&HashSet<OperationId>> = trace
+            .events
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::Batch(_, op_ids) => Some(op_ids),
+                _ => None,
+            })
+            .collect();
+        let sizes: Vec<usize> = batches.iter().map(|batch| batch.len()).collect();
+        assert_eq!(sizes, [10, 10, 5]);
+        let announced: HashSet<OperationId> = batches.into_iter().flatten().cloned().collect();
+        assert_eq!(announced, (0..25).collect());
+    }
+}
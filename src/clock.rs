@@ -0,0 +1,53 @@
+//! Time read by the hooks. The measurements run on the wall clock, a replay
+//! moves a virtual clock to the time of each event instead of sleeping, so
+//! the hooks see the recorded delays whatever the speed of the replay.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The wall clock by default. The clones of a virtual clock share its time.
+#[derive(Clone, Debug, Default)]
+pub struct Clock(Option<Arc<Mutex<Instant>>>);
+
+impl Clock {
+    /// A virtual clock stopped at the current time
+    pub fn stopped() -> Self {
+        Self(Some(Arc::new(Mutex::new(Instant::now()))))
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn now(&self) -> Instant {
+        match &self.0 {
+            Some(now) => *now.lock().unwrap(),
+            None => Instant::now(),
+        }
+    }
+
+    /// Move a virtual clock forward by `delay`, sleep on the wall clock
+    pub fn advance(&self, delay: Duration) {
+        match &self.0 {
+            Some(now) => *now.lock().unwrap() += delay,
+            None => std::thread::sleep(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_the_virtual_time() {
+        let clock = Clock::stopped();
+        let hook = clock.clone();
+        let start = hook.now();
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(hook.now() - start, Duration::from_secs(3600));
+        assert_eq!(hook.now(), clock.now());
+    }
+}
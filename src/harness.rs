@@ -0,0 +1,770 @@
+//! Measurement of an algorithm with a scenario, the same for every
+//! algorithm: the remote nodes, the loops of the measured node, the queues
+//! between them and the report. An algorithm only gives its hooks and its
+//! queues, see [Algorithm].
+
+use crate::{
+    adversary::FAKE_IDS,
+    clock::Clock,
+    memory::{MemorySamples, MemoryUsage},
+    metrics::{print_churn_impact, print_latency_by_priority, Report},
+    operation::{Fee, Operation, OperationFees, OPERATION_ID_SIZE},
+    peers::{PeerInfo, Peers},
+    pull::{Puller, RecentIds},
+    rate_limit::{AskQuota, ThrottlePolicy, ThrottleStats},
+    rng::{HashMap, HashSet, SimRng, Stream},
+    scenario::{Churn, Scenario},
+    score::{PeerScore, ScoreConfig, ScoreWeights},
+    short_id::ShortIdLinks,
+    trace::{Answers, Event, Hooks, Recorder, ReplayReport, Timer, Trace},
+    validation::{sign, OpValidation},
+};
+use rand::{seq::SliceRandom, Rng};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+type NodeId = u64;
+type OperationId = u64;
+type OperationIds = HashSet<OperationId>;
+type OperationMap = HashMap<OperationId, Operation>;
+/// Answer of a node to an ask, None for the ids it doesn't give
+pub type Answer = HashMap<OperationId, Option<Operation>>;
+
+pub const MAX_BATCH_SIZE: usize = 100;
+/// How long an announcement waits for its batch to fill
+pub const ANNOUNCE_DELAY: Duration = Duration::from_millis(5);
+/// Verification of the received operations, that's the dominant cost in a real node
+pub const OP_VALIDATION: OpValidation = OpValidation::Hash { rounds: 64 };
+/// Fees of the operations are picked in `0..MAX_FEE`
+pub const MAX_FEE: Fee = 1_000;
+/// Quota of the asks of each remote node
+pub const ASK_QUOTA: AskQuota = AskQuota {
+    max_ids: 200,
+    max_bytes: 200 * 32,
+    period: Duration::from_millis(10),
+    policy: ThrottlePolicy::Defer,
+};
+/// Scoring of the remote nodes
+pub const SCORING: ScoreConfig = ScoreConfig {
+    ask_timeout: Duration::from_secs(1),
+    ban_threshold: -100,
+    weights: ScoreWeights::DEFAULT,
+};
+/// Time between two announcement rounds of [simulate]
+pub const SIMULATED_ROUND: Duration = Duration::from_millis(1);
+/// Period of the loops of the node in [simulate]
+pub const SIMULATED_TICK: Duration = Duration::from_millis(1);
+/// Time taken by a node to answer an ask in [simulate]
+pub const SIMULATED_ANSWER_DELAY: Duration = Duration::from_micros(500);
+pub const T: usize = 25; // Numer of nodes
+pub const N: usize = 10_000; // Number of operations
+
+/// When each operation has been announced/received for the first time
+type Timestamps = Arc<Mutex<HashMap<OperationId, Instant>>>;
+/// Bytes exchanged by the measured node
+type Bandwidth = Arc<AtomicUsize>;
+const ID_SIZE: usize = OPERATION_ID_SIZE;
+
+const _: () = {
+    if T == 0 {
+        panic!("We need a number of nodes > 0");
+    }
+    if !N.is_multiple_of(MAX_BATCH_SIZE) {
+        panic!("For the test N should be a multiple of MAX_BATCH_SIZE");
+    }
+};
+
+/// An algorithm measured by [process] and replayed by [replay]: the hooks
+/// of its node and the queues where they push what they send
+pub trait Algorithm: Sized + Send + 'static {
+    /// Name in the reports and in the traces
+    const NAME: &'static str;
+    /// The asks are sent by an asking loop, not with the batches
+    const ASKING_LOOP: bool;
+    /// The answers carry the ids the remote node doesn't give
+    const ANSWERS_MISSING: bool = false;
+
+    /// A node with the configuration of the measurements
+    fn new_protocol() -> Self;
+    /// Configure the measured node for `scenario`
+    fn prepare(&mut self, scenario: &Scenario, recorder: Recorder);
+    /// The remote nodes don't push in the queues
+    fn set_measured(&mut self, is_measured: bool);
+    /// Time read by the hooks
+    fn set_clock(&mut self, clock: Clock);
+    fn recorder(&self) -> &Recorder;
+    fn received(&self) -> &OperationMap;
+    fn memory(&self) -> MemoryUsage;
+    /// What the algorithm counted during the run
+    fn print_stats(&self, scenario: &Scenario);
+
+    /// Remove what a previous run could have left in the queues
+    fn clear_queues();
+    /// Next ask sent by the measured node
+    fn pop_ask(rng: &mut SimRng) -> Option<(NodeId, OperationIds)>;
+    /// Every ask sent by the measured node
+    fn drain_asks() -> Vec<(NodeId, OperationIds)>;
+    /// Next batch announced by the measured node, in a random order
+    fn pop_announce(rng: &mut SimRng) -> Option<(NodeId, OperationIds)>;
+    /// Next node asked for a digest by the measured node
+    fn pop_pull() -> Option<NodeId>;
+
+    /// Time taken by a remote node to answer an ask
+    fn answer_delay(rng: &mut SimRng) -> Duration {
+        const MIN_SLEEP: u64 = 300;
+        const MAX_SLEEP: u64 = 600;
+        Duration::from_nanos(rng.gen_range(MIN_SLEEP..MAX_SLEEP))
+    }
+
+    fn on_node_connected(&mut self, node_id: NodeId);
+    fn on_node_disconnected(&mut self, node_id: NodeId);
+    fn on_fees_received(&mut self, fees: OperationFees);
+    /// A batch announced by `node_id`, returns the ids asked right away
+    fn on_batch_received(&mut self, node_id: NodeId, op_ids: OperationIds) -> OperationIds;
+    fn on_operations(&mut self, node_id: NodeId, answer: Answer);
+    fn on_ask_received(&mut self, node_id: NodeId, op_ids: OperationIds);
+    fn on_digest_received(&mut self, node_id: NodeId, digest: OperationIds);
+    fn on_send_tick(&mut self);
+    fn on_asking_tick(&mut self) {}
+
+    /// The batches of a round of `link`, after its fees
+    fn on_round(&mut self, batches: Vec<OperationIds>, link: &mut Link) {
+        for batch in batches {
+            let batch = link.announce(batch);
+            let node_id = link.node_id();
+            self.recorder()
+                .record(|| Event::Batch(node_id, batch.clone()));
+            self.on_batch_received(node_id, batch);
+        }
+    }
+
+    /// What a remote node asks us back when we announce `op_ids` to it
+    fn remote_asks(remote: &mut Self, op_ids: OperationIds) -> OperationIds {
+        remote.on_batch_received(0 /* not needed because it's a mock */, op_ids)
+    }
+}
+
+/// Connection of the measured node with a remote node, counts the bytes
+pub struct Link<'a> {
+    node_id: NodeId,
+    links: &'a Mutex<ShortIdLinks>,
+    bandwidth: &'a AtomicUsize,
+}
+
+impl Link<'_> {
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// Announce `op_ids`, returns the ids as the measured node gets them
+    pub fn announce(&mut self, op_ids: OperationIds) -> OperationIds {
+        let (op_ids, bytes) = self.links.lock().unwrap().announce(self.node_id, op_ids);
+        self.send(bytes);
+        op_ids
+    }
+
+    /// The node knows `op_ids` without announcing them, see
+    /// [ShortIdLinks::share]
+    pub fn share(&mut self, op_ids: &OperationIds) {
+        self.links.lock().unwrap().share(self.node_id, op_ids);
+    }
+
+    /// Count `bytes` of another message
+    pub fn send(&mut self, bytes: usize) {
+        self.bandwidth.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Remote nodes currently disconnected, the ids asked to them that will
+/// never be answered, and the ids each node received to answer the pulls
+#[derive(Default)]
+struct Remotes {
+    offline: HashSet<NodeId>,
+    lost_in_flight: OperationIds,
+    recent: Vec<RecentIds>,
+}
+
+/// Every operation must have been asked, unless the `scenario` can lose
+/// some of them
+fn print_output(measures: Vec<(NodeId, OperationIds)>, scenario: &Scenario) {
+    println!("Total batches required: {}", measures.len());
+    println!(
+        "We asked an average of {} times each node",
+        measures.len() / T
+    );
+    let mut ids = OperationIds::default();
+    let mut nodes = vec![0; T + 1];
+    for (node_id, operation_ids) in measures.iter() {
+        *nodes.get_mut(*node_id as usize).unwrap() += 1;
+        ids.extend(operation_ids.iter().filter(|&&op_id| op_id < FAKE_IDS));
+    }
+    println!("Asking table by nodes:\n{:?}", nodes);
+    println!("Correctly processed: {}", ids.len() == N);
+    if scenario.loses_nothing() {
+        assert_eq!(ids.len(), N, "operations never asked");
+    }
+}
+
+/// Print what the measured node kept about the remote nodes
+pub fn print_peers<I: PeerInfo>(
+    scenario: &Scenario,
+    node_infos: &Peers<I>,
+    banned: &HashMap<NodeId, PeerScore>,
+    throttle_stats: &ThrottleStats,
+    pull: &Puller,
+) {
+    let rejected: u64 = node_infos
+        .values()
+        .map(|info| info.score().invalid)
+        .chain(banned.values().map(|score| score.invalid))
+        .sum();
+    println!("Rejected operations: {}", rejected);
+    let mut banned: Vec<_> = banned.iter().collect();
+    banned.sort_unstable_by_key(|(node_id, _)| **node_id);
+    for (node_id, score) in banned {
+        println!(
+            "Banned node {} ({:?}): {:?}",
+            node_id,
+            scenario.adversaries.behavior(*node_id),
+            score
+        );
+    }
+    println!("Throttled asks: {:?}", throttle_stats);
+    if scenario.pull.is_some() {
+        println!("Pulls: {:?}", pull.stats);
+    }
+    node_infos.print_known_ops();
+}
+
+pub fn process<A: Algorithm>(scenario: &Scenario) -> Report {
+    println!(
+        "=== {}, {} scenario, seed {} ===",
+        A::NAME,
+        scenario.name,
+        scenario.seed
+    );
+    A::clear_queues();
+    let start = Instant::now();
+    let fees = Arc::new(draw_fees(scenario));
+    let announced = Timestamps::default();
+    let received = Timestamps::default();
+    let bandwidth = Bandwidth::default();
+    let remotes = Arc::new(Mutex::new(Remotes {
+        recent: (0..=T).map(|_| RecentIds::default()).collect(),
+        ..Default::default()
+    }));
+    let links = Arc::new(Mutex::new(ShortIdLinks::new(
+        scenario.short_ids,
+        scenario.rng(Stream::ShortIds),
+    )));
+    let recorder = Recorder::new(scenario);
+    let mut measured_protocol = A::new_protocol();
+    measured_protocol.prepare(scenario, recorder.clone());
+    let protocol = Arc::new(Mutex::new(measured_protocol));
+    let sig_stop = Arc::new(AtomicBool::new(true));
+    let batch_sender = run_batch_sender(
+        protocol.clone(),
+        fees.clone(),
+        announced.clone(),
+        scenario.clone(),
+        bandwidth.clone(),
+        remotes.clone(),
+        links.clone(),
+    );
+    let batch_receiver = run_operations_asked_receiver(
+        protocol.clone(),
+        fees.clone(),
+        received.clone(),
+        scenario.clone(),
+        bandwidth.clone(),
+        remotes.clone(),
+        links.clone(),
+    );
+    let operation_asker = run_operation_asker(
+        protocol.clone(),
+        sig_stop.clone(),
+        scenario.rng(Stream::OperationAsker),
+    );
+    let memory_sampler = run_memory_sampler(protocol.clone(), sig_stop.clone());
+    let send_loop = run_loop(
+        protocol.clone(),
+        sig_stop.clone(),
+        scenario.rng(Stream::SendLoop),
+        Timer::Send,
+    );
+    let asking_loop = A::ASKING_LOOP.then(|| {
+        run_loop(
+            protocol.clone(),
+            sig_stop.clone(),
+            scenario.rng(Stream::AskingLoop),
+            Timer::Asking,
+        )
+    });
+    batch_sender.join().unwrap();
+    // keep asking until we received everything
+    let operations_asked = batch_receiver.join().unwrap();
+    sig_stop.store(false, Ordering::Relaxed);
+    let memory = memory_sampler.join().unwrap();
+    operation_asker.join().unwrap();
+    send_loop.join().unwrap();
+    if let Some(asking_loop) = asking_loop {
+        asking_loop.join().unwrap();
+    }
+    recorder.save(A::NAME, scenario).expect("write the trace");
+    print_output(operations_asked, scenario);
+    protocol.lock().unwrap().print_stats(scenario);
+    let links = links.lock().unwrap();
+    if links.is_enabled() {
+        println!("Short ids: {:?}", links.stats);
+    }
+    std::mem::drop(links);
+    print_latency_by_priority(
+        &fees,
+        MAX_FEE,
+        &announced.lock().unwrap(),
+        &received.lock().unwrap(),
+    );
+    if !scenario.churn.is_empty() {
+        print_churn_impact(
+            &remotes.lock().unwrap().lost_in_flight,
+            &announced.lock().unwrap(),
+            &received.lock().unwrap(),
+        );
+    }
+    memory.print();
+    println!("Processed in {:?} ({:?})", start.elapsed(), OP_VALIDATION);
+    let report = Report {
+        seed: scenario.seed,
+        ..Report::new(
+            N,
+            &announced.lock().unwrap(),
+            &received.lock().unwrap(),
+            bandwidth.load(Ordering::Relaxed),
+            &memory,
+        )
+    };
+    report.print();
+    report
+}
+
+/// Feed the inputs of `trace` to the hooks of a new measured node, see
+/// [crate::trace]. The batches go through [Algorithm::on_round] like in
+/// [process], the asks of the node are answered by the node asked. The node
+/// reads a virtual clock moved to the time of each event.
+pub fn replay<A: Algorithm>(trace: &Trace) -> ReplayReport {
+    replay_into(&mut A::new_protocol(), trace)
+}
+
+/// [replay] into `protocol`, its clock is replaced by the virtual one
+pub fn replay_into<A: Algorithm>(protocol: &mut A, trace: &Trace) -> ReplayReport {
+    replay_on(protocol, trace, Clock::stopped())
+}
+
+/// Run `scenario` on a single thread and a virtual clock: the announcements
+/// that [process] sends for the seed of the scenario are fed like a trace
+/// into a new measured node, a round every [SIMULATED_ROUND]. The asks are
+/// answered [SIMULATED_ANSWER_DELAY] later by the node asked, with the
+/// operations it announced, the loops tick every [SIMULATED_TICK]. The same
+/// seed sends the same messages, written in the trace of the run if the
+/// scenario records. The answers are honest and there is no pull.
+pub fn simulate<A: Algorithm>(scenario: &Scenario) -> ReplayReport {
+    let clock = Clock::stopped();
+    let recorder = Recorder::on_clock(scenario, clock.clone());
+    let mut protocol = A::new_protocol();
+    protocol.prepare(scenario, recorder.clone());
+    let report = replay_on(&mut protocol, &announcements(scenario), clock);
+    recorder.save(A::NAME, scenario).expect("write the trace");
+    report
+}
+
+/// The fees, the batches and the churn sent by [run_batch_sender] for
+/// `scenario`, as the inputs of a trace
+fn announcements(scenario: &Scenario) -> Trace {
+    let fees = draw_fees(scenario);
+    let mut rng = scenario.rng(Stream::BatchSender);
+    let orders = draw_orders(&mut rng);
+    let mut previous: Vec<Option<OperationIds>> = vec![None; T + 1];
+    let mut adversaries = scenario.adversaries.clone();
+    let mut offline = HashSet::default();
+    let mut events = vec![];
+    for round in 0..N / MAX_BATCH_SIZE {
+        let at = SIMULATED_ROUND * round as u32;
+        for churn in scenario.churn_at(round) {
+            match churn {
+                Churn::Join(node_id) => {
+                    offline.remove(&node_id);
+                    events.push((at, Event::Connected(node_id)));
+                }
+                Churn::Leave(node_id) => {
+                    offline.insert(node_id);
+                    events.push((at, Event::Disconnected(node_id)));
+                }
+            }
+        }
+        for (node_id, order) in orders.iter().enumerate() {
+            let node_id = node_id as NodeId;
+            if offline.contains(&node_id) {
+                continue;
+            }
+            let batch: OperationIds = order
+                .iter()
+                .skip(round * MAX_BATCH_SIZE)
+                .take(MAX_BATCH_SIZE)
+                .map(|&op_id| op_id as OperationId)
+                .collect();
+            let batch_fees = batch.iter().map(|op_id| (*op_id, fees[op_id])).collect();
+            let batches = adversaries.announce(
+                node_id,
+                batch.clone(),
+                previous[node_id as usize].as_ref(),
+                MAX_BATCH_SIZE,
+            );
+            previous[node_id as usize] = Some(batch);
+            if rng.gen_bool(scenario.announce_loss) {
+                continue;
+            }
+            events.push((at, Event::Fees(batch_fees)));
+            for batch in batches {
+                events.push((at, Event::Batch(node_id, batch)));
+            }
+        }
+    }
+    Trace {
+        events,
+        tick: Some(SIMULATED_TICK),
+        answers: Some(Answers {
+            delay: SIMULATED_ANSWER_DELAY,
+            sizes: HashMap::default(),
+        }),
+    }
+}
+
+/// [replay_into] on `clock`, a virtual clock
+fn replay_on<A: Algorithm>(protocol: &mut A, trace: &Trace, clock: Clock) -> ReplayReport {
+    A::clear_queues();
+    protocol.set_clock(clock.clone());
+    let hooks = Hooks {
+        connected: A::on_node_connected,
+        disconnected: A::on_node_disconnected,
+        fees: A::on_fees_received,
+        batch: |protocol: &mut A, node_id, op_ids| {
+            // a round of that batch alone, with the full ids
+            let links = Mutex::new(ShortIdLinks::new(None, Stream::ShortIds.rng(0)));
+            let bandwidth = AtomicUsize::new(0);
+            let mut link = Link {
+                node_id,
+                links: &links,
+                bandwidth: &bandwidth,
+            };
+            protocol.on_round(vec![op_ids], &mut link);
+            bandwidth.into_inner()
+        },
+        operations: |protocol: &mut A, node_id, operations| {
+            let answer = operations
+                .into_iter()
+                .map(|(op_id, op)| (op_id, Some(op)))
+                .collect();
+            protocol.on_operations(node_id, answer);
+        },
+        ask_received: A::on_ask_received,
+        digest: A::on_digest_received,
+        send_tick: A::on_send_tick,
+        asking_tick: A::ASKING_LOOP.then_some(A::on_asking_tick as fn(&mut A)),
+        asks: A::drain_asks,
+        received: |protocol: &A| protocol.received().len(),
+    };
+    let report = trace.replay(protocol, &clock, &hooks);
+    A::clear_queues();
+    report
+}
+
+/// Fee of each operation
+fn draw_fees(scenario: &Scenario) -> OperationFees {
+    let mut rng = scenario.rng(Stream::Fees);
+    (0..N as OperationId)
+        .map(|op_id| (op_id, rng.gen_range(0..MAX_FEE)))
+        .collect()
+}
+
+/// Order of the operations announced by each node
+fn draw_orders(rng: &mut SimRng) -> Vec<[usize; N + 1]> {
+    let mut orders = vec![[0; N + 1]; T + 1];
+    for n in 0..N {
+        for order in orders.iter_mut() {
+            order[n] = n; // init an order to shuffle later
+        }
+    }
+    for order in orders.iter_mut() {
+        order.shuffle(rng);
+    }
+    orders
+}
+
+fn run_batch_sender<A: Algorithm>(
+    protocol: Arc<Mutex<A>>,
+    fees: Arc<OperationFees>,
+    announced: Timestamps,
+    scenario: Scenario,
+    bandwidth: Bandwidth,
+    remotes: Arc<Mutex<Remotes>>,
+    links: Arc<Mutex<ShortIdLinks>>,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut p = 0;
+        let mut rng = scenario.rng(Stream::BatchSender);
+        let orders = draw_orders(&mut rng);
+        // last batch of each node, replayed by the misbehaving ones
+        let mut previous: Vec<Option<OperationIds>> = vec![None; T + 1];
+        let mut adversaries = scenario.adversaries.clone();
+        while p < N {
+            for churn in scenario.churn_at(p / MAX_BATCH_SIZE) {
+                let mut guard = protocol.lock().unwrap();
+                let mut remotes = remotes.lock().unwrap();
+                match churn {
+                    Churn::Join(node_id) => {
+                        remotes.offline.remove(&node_id);
+                        guard.recorder().record(|| Event::Connected(node_id));
+                        guard.on_node_connected(node_id);
+                    }
+                    Churn::Leave(node_id) => {
+                        remotes.offline.insert(node_id);
+                        guard.recorder().record(|| Event::Disconnected(node_id));
+                        guard.on_node_disconnected(node_id);
+                    }
+                }
+            }
+            for (node_id, order) in orders.iter().enumerate() {
+                if remotes.lock().unwrap().offline.contains(&(node_id as u64)) {
+                    continue;
+                }
+                let mut batch = OperationIds::default();
+                let mut batch_fees = OperationFees::default();
+                let now = Instant::now();
+                let mut announced = announced.lock().unwrap();
+                for &op_id in order.iter().skip(p).take(MAX_BATCH_SIZE) {
+                    batch.insert(op_id as u64);
+                    batch_fees.insert(op_id as u64, fees[&(op_id as u64)]);
+                    announced.entry(op_id as u64).or_insert(now);
+                }
+                std::mem::drop(announced);
+                let batches = adversaries.announce(
+                    node_id as u64,
+                    batch.clone(),
+                    previous[node_id].as_ref(),
+                    MAX_BATCH_SIZE,
+                );
+                remotes.lock().unwrap().recent[node_id].push(batch.iter().cloned(), now);
+                previous[node_id] = Some(batch);
+                if rng.gen_bool(scenario.announce_loss) {
+                    // lost with its fees, only another node or a pull can
+                    // tell us about these operations
+                    continue;
+                }
+                let mut guard = protocol.lock().unwrap();
+                guard.recorder().record(|| Event::Fees(batch_fees.clone()));
+                guard.on_fees_received(batch_fees);
+                let mut link = Link {
+                    node_id: node_id as u64,
+                    links: &links,
+                    bandwidth: &bandwidth,
+                };
+                guard.on_round(batches, &mut link);
+            }
+            p += MAX_BATCH_SIZE;
+        }
+    })
+}
+
+/// Ici nous avons la simulation de nos demande d'operations,
+/// ces demandes sont fait suite a des receptions de batch de la part des
+/// autres noeuds.
+///
+/// On reserve en cache la liste des demandes de notre noeuds pour
+/// l'analyser en valeur de retour à la fin du process de mesure.
+///
+///
+/// Observation suite aux mesures:
+/// On remarque que plus on va mettre du temps à recevoir des operations,
+/// plus on redemandera de batch mais pas toujours aux mêmes.
+fn run_operations_asked_receiver<A: Algorithm>(
+    protocol: Arc<Mutex<A>>,
+    fees: Arc<OperationFees>,
+    received: Timestamps,
+    scenario: Scenario,
+    bandwidth: Bandwidth,
+    remotes: Arc<Mutex<Remotes>>,
+    links: Arc<Mutex<ShortIdLinks>>,
+) -> JoinHandle<Vec<(NodeId, OperationIds)>> {
+    std::thread::spawn(move || {
+        let mut cache = vec![];
+        let mut rng = scenario.rng(Stream::AskedReceiver);
+        let start = Instant::now();
+        // stop when we have everything, or when we won't have more
+        while protocol.lock().unwrap().received().len() < N && start.elapsed() < scenario.deadline {
+            // little sleep that simulate the time of a communication
+            // for the example it's not needed to be very big
+            std::thread::sleep(A::answer_delay(&mut rng));
+            if let Some(pull) = scenario.pull {
+                // the pulls of the measured node
+                let opt_pull = A::pop_pull();
+                let mut guard = remotes.lock().unwrap();
+                if let Some(node_id) = opt_pull.filter(|node_id| !guard.offline.contains(node_id)) {
+                    let digest = guard.recent[node_id as usize].digest(&pull, Instant::now());
+                    std::mem::drop(guard);
+                    let (digest, bytes) = links.lock().unwrap().announce(node_id, digest);
+                    bandwidth.fetch_add(bytes, Ordering::Relaxed);
+                    let mut guard = protocol.lock().unwrap();
+                    guard
+                        .recorder()
+                        .record(|| Event::Digest(node_id, digest.clone()));
+                    guard.on_digest_received(node_id, digest);
+                }
+            }
+            if let Some((node_id, operation_ids)) = A::pop_ask(&mut rng) {
+                cache.push((node_id, operation_ids.clone()));
+                // what the node understood of the ask
+                let (asked, ask_size) = links.lock().unwrap().ask(node_id, &operation_ids);
+                bandwidth.fetch_add(ask_size, Ordering::Relaxed);
+                let mut guard = remotes.lock().unwrap();
+                if guard.offline.contains(&node_id) {
+                    // the node left, nobody will answer
+                    guard.lost_in_flight.extend(operation_ids);
+                    continue;
+                }
+                std::mem::drop(guard);
+                let answer: Answer =
+                    match scenario
+                        .adversaries
+                        .answer(node_id, &asked, |id| Operation {
+                            fee: fees[&id],
+                            content: sign(id),
+                            wire_size: None,
+                        }) {
+                        Some(answer) => answer.into_iter().collect(),
+                        None => continue, // never answered
+                    };
+                bandwidth.fetch_add(
+                    answer
+                        .values()
+                        .map(|op| match op {
+                            Some(op) => ID_SIZE + op.size(),
+                            None if A::ANSWERS_MISSING => ID_SIZE,
+                            None => 0,
+                        })
+                        .sum(),
+                    Ordering::Relaxed,
+                );
+                let mut guard = protocol.lock().unwrap();
+                guard.recorder().record(|| {
+                    let operations = answer
+                        .iter()
+                        .filter_map(|(id, op)| op.clone().map(|op| (*id, op)))
+                        .collect();
+                    Event::Operations(node_id, operations)
+                });
+                guard.on_operations(node_id, answer);
+                let now = Instant::now();
+                let mut received = received.lock().unwrap();
+                for id in asked.iter() {
+                    if guard.received().contains_key(id) {
+                        received.entry(*id).or_insert(now);
+                    }
+                }
+                // the operations come with their full ids
+                let learned: Vec<OperationId> = asked
+                    .iter()
+                    .filter(|id| guard.received().contains_key(id))
+                    .cloned()
+                    .collect();
+                std::mem::drop(guard);
+                links.lock().unwrap().learn(learned);
+            }
+        }
+        cache
+    })
+}
+
+/// Ici je souhaite simuler les boucles de notre noeud local, `on_send_loop`
+/// et la boucle de demande. On va aller bcp plus vite que dans la réalité
+/// mais on va essayer de rendre ça un peu plus lent que le reste quand même.
+///
+/// On ne les lance que pour notre noeud local. Les boucles des autres sont
+/// simulées dans [run_operation_asker]. En effet, a leur facon ils
+/// redemandent à chaque tour de boucle a notre noeud local des operations.
+fn run_loop<A: Algorithm>(
+    protocol: Arc<Mutex<A>>,
+    stop: Arc<AtomicBool>,
+    mut rng: SimRng,
+    timer: Timer,
+) -> JoinHandle<()> {
+    let (min_sleep, max_sleep) = match timer {
+        Timer::Send => (50, 200),
+        Timer::Asking => (20, 100),
+    };
+    std::thread::spawn(move || {
+        while stop.load(Ordering::Relaxed) {
+            let rand_sleep = Duration::from_nanos(rng.gen_range(min_sleep..max_sleep));
+            std::thread::sleep(rand_sleep);
+            let mut guard = protocol.lock().unwrap();
+            guard.recorder().record(|| Event::Tick(timer));
+            match timer {
+                Timer::Send => guard.on_send_tick(),
+                Timer::Asking => guard.on_asking_tick(),
+            }
+        }
+    })
+}
+
+/// Le noeud envoie des batches et c'est dans ce thread qu'onva gere ce que
+/// les noeuds demande en retour.
+fn run_operation_asker<A: Algorithm>(
+    protocol: Arc<Mutex<A>>,
+    stop: Arc<AtomicBool>,
+    mut rng: SimRng,
+) -> JoinHandle<()> {
+    const MIN_SLEEP: u64 = 2;
+    const MAX_SLEEP: u64 = 20;
+    std::thread::spawn(move || {
+        let mut protocols = (0..=T)
+            .map(|_| {
+                let mut protocol = A::new_protocol();
+                protocol.set_measured(false);
+                protocol
+            })
+            .collect::<Vec<A>>();
+        while stop.load(Ordering::Relaxed) {
+            let rand_sleep = Duration::from_nanos(rng.gen_range(MIN_SLEEP..MAX_SLEEP));
+            std::thread::sleep(rand_sleep);
+            if let Some((node_id, operation_ids)) = A::pop_announce(&mut rng) {
+                let ask_set = A::remote_asks(&mut protocols[node_id as usize], operation_ids);
+                let mut guard = protocol.lock().unwrap();
+                guard
+                    .recorder()
+                    .record(|| Event::AskReceived(node_id, ask_set.clone()));
+                guard.on_ask_received(node_id, ask_set);
+            }
+        }
+    })
+}
+
+/// Sample the memory used by the measured node until the end of the run
+fn run_memory_sampler<A: Algorithm>(
+    protocol: Arc<Mutex<A>>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<MemorySamples> {
+    const SAMPLE_PERIOD: Duration = Duration::from_millis(10);
+    std::thread::spawn(move || {
+        let mut samples = MemorySamples::default();
+        while stop.load(Ordering::Relaxed) {
+            std::thread::sleep(SAMPLE_PERIOD);
+            samples.push(protocol.lock().unwrap().memory());
+        }
+        samples
+    })
+}
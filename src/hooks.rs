@@ -0,0 +1,250 @@
+//! The hooks that are the same in `less_cpu`, `hybrid` and `reconcile`. The
+//! three serve the asks right away, check the batches and ban the nodes the
+//! same way, they only differ in what they do with the ids announced (see
+//! [crate::wishlist] for the part shared by `hybrid` and `reconcile`).
+//! `more_cpu` serves the asks from its send loop, it keeps its own hooks.
+
+use crate::batch::limit_batch;
+use crate::clock::Clock;
+use crate::operation::{Operation, OperationFees};
+use crate::peers::{PeerInfo, Peers};
+use crate::pull::Puller;
+use crate::rate_limit::{AskQuota, ThrottleStats};
+use crate::rng::{HashMap, HashSet, SimRng};
+use crate::score::{PeerScore, ScoreConfig};
+use crate::trace::Recorder;
+use crate::{batch::OversizedPolicy, validation::OpValidation};
+use std::cmp::Reverse;
+
+type NodeId = u64;
+type OperationId = u64;
+type OperationIds = HashSet<OperationId>;
+type OperationMap = HashMap<OperationId, Operation>;
+
+/// The fields of a [Protocol] read or changed by the shared hooks
+pub struct Shared<'a, I> {
+    pub node_infos: &'a mut Peers<I>,
+    pub received: &'a mut OperationMap,
+    pub op_fees: &'a mut OperationFees,
+    pub banned: &'a mut HashMap<NodeId, PeerScore>,
+    pub throttle_stats: &'a mut ThrottleStats,
+    pub pull: &'a mut Puller,
+    pub rng: &'a mut SimRng,
+    pub recorder: &'a Recorder,
+    pub clock: &'a Clock,
+    pub max_batch_size: usize,
+    pub oversized_policy: OversizedPolicy,
+    pub op_validation: OpValidation,
+    pub ask_quota: Option<AskQuota>,
+    pub scoring: Option<ScoreConfig>,
+    pub is_measured: bool,
+}
+
+/// The `FakeProtocol` of an algorithm, as seen by the shared hooks
+pub trait Protocol {
+    type Info: PeerInfo;
+
+    fn shared(&mut self) -> Shared<'_, Self::Info>;
+
+    /// What we asked to `node_id` will be asked to another node
+    fn forget_asks_to(&mut self, node_id: NodeId);
+
+    /// Push the operations sent to `node_id` in the queue of the algorithm
+    fn send_operations(node_id: NodeId, operations: OperationMap);
+
+    /// Push the digest asked to `node_id` in the queue of the algorithm
+    fn ask_digest(node_id: NodeId);
+}
+
+/// Fees are announced with the batches, in the simulation the sender
+/// call this function before `on_batch_received`. The fee of an operation is
+/// kept until we receive it or stop wanting it.
+pub fn on_fees_received<P: Protocol>(
+    fees: OperationFees,
+    protocol: &mut P, /* self simulation */
+) {
+    let shared = protocol.shared();
+    let received = &shared.received;
+    shared.op_fees.extend(
+        fees.into_iter()
+            .filter(|(op_id, _)| !received.contains_key(op_id)),
+    );
+}
+
+/// The batch of `node_id` that we process, None if the node is banned or the
+/// batch rejected. A batch bigger than `max_batch_size` counts against the
+/// node, and is cut by the `oversized_policy`.
+pub(crate) fn admit_batch<P: Protocol>(
+    op_batch: OperationIds,
+    node_id: NodeId,
+    protocol: &mut P, /* self simulation */
+) -> Option<OperationIds> {
+    let shared = protocol.shared();
+    if shared.banned.contains_key(&node_id) {
+        return None;
+    }
+    if op_batch.len() > shared.max_batch_size {
+        if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+            node_info.score_mut().oversized += 1;
+        }
+        if ban_if_needed(node_id, protocol) {
+            return None;
+        }
+    }
+    let shared = protocol.shared();
+    limit_batch(
+        op_batch,
+        shared.max_batch_size,
+        shared.oversized_policy,
+        shared.op_fees,
+    )
+}
+
+/// False if `node_id` has a bad score, what it announces is asked to it
+/// only if nobody better knows it
+pub(crate) fn is_trusted<P: Protocol>(node_id: NodeId, protocol: &mut P) -> bool {
+    let shared = protocol.shared();
+    let weights = crate::score::weights(shared.scoring);
+    shared
+        .node_infos
+        .get(&node_id)
+        .map(|node_info| node_info.score().value(&weights) >= 0)
+        .unwrap_or(true)
+}
+
+/// Disconnect the node if its score is under the threshold, return true if
+/// the node is banned.
+pub(crate) fn ban_if_needed<P: Protocol>(
+    node_id: NodeId,
+    protocol: &mut P, /* self simulation */
+) -> bool {
+    let shared = protocol.shared();
+    let scoring = match shared.scoring {
+        Some(scoring) => scoring,
+        None => return false,
+    };
+    match shared.node_infos.get(&node_id) {
+        Some(node_info) if node_info.score().value(&scoring.weights) < scoring.ban_threshold => {
+            let node_info = shared.node_infos.remove(&node_id).unwrap();
+            shared.banned.insert(node_id, *node_info.score());
+            protocol.forget_asks_to(node_id);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Serve the operations asked by `node_id`, the most prioritary first and
+/// within its [AskQuota]
+pub fn on_ask_received<P: Protocol>(
+    node_id: NodeId,
+    op_ids: OperationIds,
+    protocol: &mut P, /* self simulation */
+) {
+    let shared = protocol.shared();
+    if shared.banned.contains_key(&node_id) {
+        return;
+    }
+    if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+        for op_ids in op_ids.iter() {
+            node_info.known_op_mut().remove(op_ids);
+        }
+    }
+    // serve the most prioritary operations first
+    let mut asked: Vec<OperationId> = op_ids.into_iter().collect();
+    asked.sort_unstable_by_key(|op_id| {
+        (Reverse(shared.received.get(op_id).map(|op| op.fee)), *op_id)
+    });
+    let now = shared.clock.now();
+    let received = &shared.received;
+    let held = |op_id| received.contains_key(&op_id);
+    let quota = match (shared.ask_quota, shared.node_infos.get_mut(&node_id)) {
+        (Some(quota), Some(node_info)) => {
+            asked =
+                node_info
+                    .quota_mut()
+                    .admit_ids(asked, held, &quota, shared.throttle_stats, now);
+            Some((quota, node_info.quota_mut()))
+        }
+        _ => None,
+    };
+    let mut operations: Vec<(OperationId, Operation)> = asked
+        .iter()
+        .filter_map(|op_id| shared.received.get(op_id).map(|op| (*op_id, op.clone())))
+        .collect();
+    if let Some((quota, peer_quota)) = quota {
+        operations.retain(|(op_id, op)| {
+            peer_quota.admit_bytes(*op_id, op.size(), &quota, shared.throttle_stats, now)
+        });
+    }
+    if shared.is_measured {
+        // just for the measurement, remove that on the definitive implementation
+        for chunk in operations.chunks(shared.max_batch_size) {
+            P::send_operations(node_id, chunk.iter().cloned().collect());
+        }
+    }
+}
+
+/// Serve the asks deferred by the quota, once the period of the node is over
+pub(crate) fn serve_deferred_asks<P: Protocol>(protocol: &mut P /* self simulation */) {
+    let shared = protocol.shared();
+    if let Some(quota) = shared.ask_quota {
+        let now = shared.clock.now();
+        let deferred: Vec<(NodeId, OperationIds)> = shared
+            .node_infos
+            .iter_mut()
+            .map(|(node_id, node_info)| {
+                (*node_id, node_info.quota_mut().take_deferred(&quota, now))
+            })
+            .filter(|(_, op_ids)| !op_ids.is_empty())
+            .collect();
+        for (node_id, op_ids) in deferred {
+            on_ask_received(node_id, op_ids, protocol);
+        }
+    }
+}
+
+/// Ask a digest of its recent operations to a random node, each pull period
+pub(crate) fn pull_if_needed<P: Protocol>(protocol: &mut P /* self simulation */) {
+    let shared = protocol.shared();
+    let node_ids = shared.node_infos.keys().cloned();
+    if let Some(node_id) = shared
+        .pull
+        .due_peer(node_ids, shared.rng, shared.clock.now())
+    {
+        if shared.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            P::ask_digest(node_id);
+        }
+    }
+}
+
+/// The ids of the digest of `node_id` that we miss, in batches to process
+/// like an announcement of that node, none if the node is banned.
+pub(crate) fn missing_ids<P: Protocol>(
+    node_id: NodeId,
+    digest: OperationIds,
+    protocol: &mut P, /* self simulation */
+) -> Vec<OperationIds> {
+    let shared = protocol.shared();
+    if shared.banned.contains_key(&node_id) {
+        return vec![];
+    }
+    let received = &shared.received;
+    shared.pull.missing(
+        digest,
+        |op_id| received.contains_key(op_id),
+        shared.op_fees,
+        shared.max_batch_size,
+    )
+}
+
+/// A node left, forget what it knows. What we asked to it will be asked to
+/// another node.
+pub fn on_node_disconnected<P: Protocol>(
+    node_id: NodeId,
+    protocol: &mut P, /* self simulation */
+) {
+    protocol.shared().node_infos.remove(&node_id);
+    protocol.forget_asks_to(node_id);
+}
@@ -0,0 +1,280 @@
+use super::types::*;
+use crate::hooks::{serve_deferred_asks, Protocol, Shared};
+use crate::trace::{Event, Recorder};
+use crate::wishlist::{receive_operations, Wishlist, WishlistProtocol};
+
+pub use crate::hooks::{on_ask_received, on_fees_received, on_node_disconnected};
+pub use crate::wishlist::{on_asking_loop, on_batch_received, on_digest_received};
+
+/***************************************************************************************** */
+/* Things that must be in all the algorithms                                               */
+// - send_batch(node_id, operations_ids): Send batch to a node
+// - on_batch_received(): Receive a batch of operation ids
+// - ask_operations(node_id, operations_ids): Ask operations to a node, on_ask_received
+// - on_ask_received ...
+// - send_operations(node_id, operations_ids): Ask operations to a node
+// - on_operation_received ...
+/* *************************************************************************************** */
+
+fn send_batch(_to_node_id: NodeId, _batch: OperationIds) {
+    //#[cfg(feature = "measurements")]
+    super::BATCH_SEND_QUEUE
+        .lock()
+        .unwrap()
+        .push((_to_node_id, _batch));
+}
+
+fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds, _recorder: &Recorder) {
+    //#[cfg(feature = "measurements")]
+    _recorder.record(|| Event::AskSent(_to_node_id, _op_ids.clone()));
+    super::ASK_BATCH_QUEUE
+        .lock()
+        .unwrap()
+        .push_back((_to_node_id, _op_ids));
+}
+
+fn ask_digest(_to_node_id: NodeId) {
+    //#[cfg(feature = "measurements")]
+    super::DIGEST_ASK_QUEUE
+        .lock()
+        .unwrap()
+        .push_back(_to_node_id);
+}
+
+fn send_operations(_to_node_id: NodeId, _op_ids: OperationMap) {
+    //#[cfg(feature = "measurements")]
+    super::SEND_OPERATION
+        .lock()
+        .unwrap()
+        .push_back((_to_node_id, _op_ids));
+}
+
+impl Protocol for FakeProtocol {
+    type Info = NodeInfo;
+
+    fn shared(&mut self) -> Shared<'_, NodeInfo> {
+        self.split().0
+    }
+
+    fn forget_asks_to(&mut self, node_id: NodeId) {
+        crate::wishlist::forget_asks_to(node_id, self);
+    }
+
+    fn send_operations(node_id: NodeId, operations: OperationMap) {
+        send_operations(node_id, operations);
+    }
+
+    fn ask_digest(node_id: NodeId) {
+        ask_digest(node_id);
+    }
+}
+
+impl WishlistProtocol for FakeProtocol {
+    fn split(&mut self) -> (Shared<'_, NodeInfo>, Wishlist<'_>) {
+        let shared = Shared {
+            node_infos: &mut self.node_infos,
+            received: &mut self.received,
+            op_fees: &mut self.op_fees,
+            banned: &mut self.banned,
+            throttle_stats: &mut self.throttle_stats,
+            pull: &mut self.pull,
+            rng: &mut self.rng,
+            recorder: &self.recorder,
+            clock: &self.clock,
+            max_batch_size: self.max_batch_size,
+            oversized_policy: self.oversized_policy,
+            op_validation: self.op_validation,
+            ask_quota: self.ask_quota,
+            scoring: self.scoring,
+            is_measured: self.is_measured,
+        };
+        let wishlist = Wishlist {
+            in_flight: &mut self.in_flight,
+            wishlist: &mut self.wishlist,
+            retry_period: self.retry_period,
+        };
+        (shared, wishlist)
+    }
+
+    fn ask_operations(node_id: NodeId, op_ids: OperationIds, recorder: &Recorder) {
+        ask_operations(node_id, op_ids, recorder);
+    }
+}
+
+pub fn on_operation_received(
+    node_id: NodeId,
+    operations: OperationMap,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    let operations = match receive_operations(node_id, operations, protocol) {
+        Some(operations) => operations,
+        None => return,
+    };
+    let now = protocol.clock.now();
+    for (node_id, node_info) in protocol.node_infos.iter_mut() {
+        let op_ids = operations
+            .keys()
+            .filter(|&&op_id| node_info.known_op.insert(op_id))
+            .cloned();
+        for batch in node_info.announce.push(
+            op_ids,
+            protocol.max_batch_size,
+            protocol.announce_delay,
+            now,
+        ) {
+            protocol.batch_fill.record(&batch);
+            if protocol.is_measured {
+                // just for the measurement, remove that on the definitive implementation
+                send_batch(*node_id, batch);
+            }
+        }
+    }
+}
+
+/// Send the announcements that waited `announce_delay` for their batch to fill
+fn flush_announcements(protocol: &mut FakeProtocol /* self simulation */) {
+    let now = protocol.clock.now();
+    for (node_id, node_info) in protocol.node_infos.iter_mut() {
+        if let Some(batch) = node_info.announce.take_due(protocol.announce_delay, now) {
+            protocol.batch_fill.record(&batch);
+            if protocol.is_measured {
+                // just for the measurement, remove that on the definitive implementation
+                send_batch(*node_id, batch);
+            }
+        }
+    }
+}
+
+/// A node connected to us, we announce it all the operations we have
+pub fn on_node_connected(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let node_info = protocol.node_infos.connect(node_id);
+    let op_ids = protocol
+        .received
+        .keys()
+        .filter(|&&op_id| node_info.known_op.insert(op_id))
+        .cloned();
+    for batch in node_info.announce.push(
+        op_ids,
+        protocol.max_batch_size,
+        protocol.announce_delay,
+        protocol.clock.now(),
+    ) {
+        protocol.batch_fill.record(&batch);
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            send_batch(node_id, batch);
+        }
+    }
+}
+
+/// Flush the announcements and serve the asks deferred by the quota
+pub fn on_send_loop(protocol: &mut FakeProtocol /* self simulation */) {
+    flush_announcements(protocol);
+    serve_deferred_asks(protocol);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::validation::sign;
+    use std::time::Duration;
+
+    const RETRY: Duration = Duration::from_millis(100);
+
+    /// A protocol on a virtual clock that doesn't push in the queues of the
+    /// measurements
+    fn protocol(nodes: usize) -> FakeProtocol {
+        let mut protocol = FakeProtocol::new(nodes, 10, RETRY);
+        protocol.clock = Clock::stopped();
+        protocol.is_measured = false;
+        protocol
+    }
+
+    fn operation(op_id: OperationId) -> OperationMap {
+        [(
+            op_id,
+            Operation {
+                fee: 0,
+                content: sign(op_id),
+                wire_size: None,
+            },
+        )]
+        .into_iter()
+        .collect()
+    }
+
+    fn asked_to(op_id: OperationId, protocol: &FakeProtocol) -> Option<NodeId> {
+        protocol.in_flight.get(&op_id).map(|(_, node_id)| *node_id)
+    }
+
+    #[test]
+    fn in_flight_id_is_asked_again_after_the_retry_period() {
+        let mut protocol = protocol(2);
+        assert_eq!(
+            on_batch_received([1].into_iter().collect(), 0, &mut protocol),
+            [1].into_iter().collect()
+        );
+        // already in flight, wished
+        assert!(on_batch_received([1].into_iter().collect(), 1, &mut protocol).is_empty());
+        assert_eq!(protocol.wishlist, [1].into_iter().collect());
+        on_asking_loop(&mut protocol);
+        assert_eq!(asked_to(1, &protocol), Some(0));
+        protocol.clock.advance(RETRY + Duration::from_millis(1));
+        on_asking_loop(&mut protocol);
+        assert_eq!(asked_to(1, &protocol), Some(1));
+        on_operation_received(1, operation(1), &mut protocol);
+        assert!(protocol.wishlist.is_empty());
+        assert!(protocol.in_flight.is_empty());
+        // nothing left to ask
+        protocol.clock.advance(RETRY + Duration::from_millis(1));
+        on_asking_loop(&mut protocol);
+        assert!(protocol.in_flight.is_empty());
+    }
+
+    #[test]
+    fn timed_out_ask_is_penalized_and_asked_to_another_node() {
+        let mut protocol = protocol(2);
+        protocol.retry_period = Duration::from_secs(60);
+        protocol.scoring = Some(ScoreConfig {
+            ask_timeout: Duration::from_millis(50),
+            ban_threshold: -10,
+            weights: ScoreWeights::DEFAULT,
+        });
+        on_batch_received([1].into_iter().collect(), 0, &mut protocol);
+        on_batch_received([1].into_iter().collect(), 1, &mut protocol);
+        protocol.clock.advance(Duration::from_millis(51));
+        on_asking_loop(&mut protocol);
+        assert_eq!(protocol.node_infos[&0].score.timeouts, 1);
+        assert_eq!(asked_to(1, &protocol), Some(1));
+    }
+
+    #[test]
+    fn untrusted_announcement_is_asked_to_a_better_node() {
+        let mut protocol = protocol(2);
+        protocol.node_infos.get_mut(&0).unwrap().score.invalid = 1;
+        assert!(on_batch_received([1].into_iter().collect(), 0, &mut protocol).is_empty());
+        assert_eq!(protocol.wishlist, [1].into_iter().collect());
+        assert!(protocol.in_flight.is_empty());
+        on_batch_received([2].into_iter().collect(), 1, &mut protocol);
+        protocol.node_infos.get_mut(&1).unwrap().known_op.insert(1);
+        on_asking_loop(&mut protocol);
+        assert_eq!(asked_to(1, &protocol), Some(1));
+    }
+
+    #[test]
+    fn asks_to_a_leaving_node_go_back_to_the_wishlist() {
+        let mut protocol = protocol(2);
+        on_batch_received([1, 2].into_iter().collect(), 0, &mut protocol);
+        on_batch_received([2].into_iter().collect(), 1, &mut protocol);
+        on_node_disconnected(0, &mut protocol);
+        assert_eq!(protocol.wishlist, [1, 2].into_iter().collect());
+        // right away, the ids aren't in flight anymore
+        on_asking_loop(&mut protocol);
+        assert_eq!(asked_to(2, &protocol), Some(1));
+        assert_eq!(asked_to(1, &protocol), None);
+    }
+}
@@ -0,0 +1,200 @@
+//! Invertible Bloom lookup table over operation ids. Two peers build a table
+//! of their sets with the same number of cells, the difference of the tables
+//! gives back the ids that only one of them knows, whatever the size of the
+//! common part.
+
+use crate::rng::HashSet;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// Number of cells where each id is inserted
+const HASH_COUNT: usize = 3;
+/// Bytes of a cell on the wire: count, xor of the ids, xor of their checksums
+pub const CELL_SIZE: usize = 4 + 8 + 4;
+/// Cells needed by id of the difference to decode it most of the time
+pub const CELLS_BY_DIFF: f64 = 1.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Cell {
+    count: i32,
+    id_sum: u64,
+    hash_sum: u32,
+}
+
+impl Cell {
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.id_sum == 0 && self.hash_sum == 0
+    }
+
+    /// Only one id left in the cell
+    fn is_pure(&self) -> bool {
+        (self.count == 1 || self.count == -1) && checksum(self.id_sum) == self.hash_sum
+    }
+}
+
+fn hash(op_id: u64, seed: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (seed, op_id).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn checksum(op_id: u64) -> u32 {
+    hash(op_id, HASH_COUNT) as u32
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Iblt {
+    cells: Vec<Cell>,
+}
+
+impl Iblt {
+    /// A table of at least `capacity` cells. The cells are split in one part
+    /// by hash function so an id never falls twice in the same cell.
+    pub fn new(capacity: usize) -> Self {
+        let part = capacity.div_ceil(HASH_COUNT).max(1);
+        Self {
+            cells: vec![Cell::default(); part * HASH_COUNT],
+        }
+    }
+
+    pub fn from_ids<I: IntoIterator<Item = u64>>(op_ids: I, capacity: usize) -> Self {
+        let mut iblt = Self::new(capacity);
+        for op_id in op_ids {
+            iblt.insert(op_id);
+        }
+        iblt
+    }
+
+    /// Number of cells
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Bytes of the table on the wire
+    pub fn size(&self) -> usize {
+        self.cells.len() * CELL_SIZE
+    }
+
+    fn indexes(&self, op_id: u64) -> impl Iterator<Item = usize> {
+        let part = self.cells.len() / HASH_COUNT;
+        (0..HASH_COUNT).map(move |seed| seed * part + (hash(op_id, seed) % part as u64) as usize)
+    }
+
+    fn update(&mut self, op_id: u64, count: i32) {
+        let hash_sum = checksum(op_id);
+        for index in self.indexes(op_id).collect::<Vec<_>>() {
+            let cell = &mut self.cells[index];
+            cell.count += count;
+            cell.id_sum ^= op_id;
+            cell.hash_sum ^= hash_sum;
+        }
+    }
+
+    pub fn insert(&mut self, op_id: u64) {
+        self.update(op_id, 1);
+    }
+
+    pub fn remove(&mut self, op_id: u64) {
+        self.update(op_id, -1);
+    }
+
+    /// Table of the ids in `self` and not in `other`, and the opposite with
+    /// negative counts. None if the tables don't have the same size.
+    pub fn subtract(&self, other: &Iblt) -> Option<Iblt> {
+        if self.len() != other.len() {
+            return None;
+        }
+        let cells = self
+            .cells
+            .iter()
+            .zip(other.cells.iter())
+            .map(|(cell, other)| Cell {
+                count: cell.count - other.count,
+                id_sum: cell.id_sum ^ other.id_sum,
+                hash_sum: cell.hash_sum ^ other.hash_sum,
+            })
+            .collect();
+        Some(Iblt { cells })
+    }
+
+    /// Ids inserted and ids removed from the table, None if there are too
+    /// many of them to be decoded. The table comes from a peer, a decodable
+    /// one never needs more peelings than it has cells.
+    pub fn decode(mut self) -> Option<(HashSet<u64>, HashSet<u64>)> {
+        let mut inserted = HashSet::default();
+        let mut removed = HashSet::default();
+        let mut pure: Vec<usize> = (0..self.len())
+            .filter(|i| self.cells[*i].is_pure())
+            .collect();
+        let mut peelings = 0;
+        while let Some(index) = pure.pop() {
+            let cell = self.cells[index];
+            if !cell.is_pure() {
+                // emptied by a previous peeling
+                continue;
+            }
+            if peelings == self.len() {
+                return None;
+            }
+            peelings += 1;
+            if cell.count == 1 {
+                inserted.insert(cell.id_sum);
+            } else {
+                removed.insert(cell.id_sum);
+            }
+            self.update(cell.id_sum, -cell.count);
+            pure.extend(
+                self.indexes(cell.id_sum)
+                    .filter(|i| self.cells[*i].is_pure()),
+            );
+        }
+        if self.cells.iter().all(Cell::is_empty) {
+            Some((inserted, removed))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_the_difference() {
+        let local = Iblt::from_ids(0..1_000, 30);
+        let remote = Iblt::from_ids((5..1_000).chain(2_000..2_005), 30);
+        let (remote_only, local_only) = remote.subtract(&local).unwrap().decode().unwrap();
+        assert_eq!(remote_only, (2_000..2_005).collect());
+        assert_eq!(local_only, (0..5).collect());
+    }
+
+    #[test]
+    fn too_small_to_decode() {
+        let local = Iblt::from_ids(0..100, 6);
+        let remote = Iblt::from_ids(100..200, 6);
+        assert_eq!(remote.subtract(&local).unwrap().decode(), None);
+        assert_eq!(remote.subtract(&Iblt::new(30)), None);
+    }
+
+    #[test]
+    fn crafted_table_is_bounded() {
+        // every cell claims the same id, the peeling never empties them
+        let op_id = 42;
+        let cell = Cell {
+            count: 1,
+            id_sum: op_id,
+            hash_sum: checksum(op_id),
+        };
+        let iblt = Iblt {
+            cells: vec![cell; 3 * HASH_COUNT],
+        };
+        assert_eq!(iblt.decode(), None);
+    }
+}
@@ -0,0 +1,253 @@
+//! Ids known by a peer, we don't announce them to it. The exact set grows
+//! with every operation, times the number of peers, the Bloom filters keep a
+//! fixed size but can be wrong.
+
+use crate::memory::set_memory;
+use crate::rng::HashSet;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    mem::size_of,
+};
+
+/// config representation of the known ids of each peer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KnownOpsRepr {
+    /// Every id in a `HashSet`
+    #[default]
+    Exact,
+    /// Two generations of Bloom filters of `bits` bits. When the current one
+    /// holds `capacity` ids it replaces the previous one, the oldest ids are
+    /// forgotten and the false positive rate stays bounded. With
+    /// `accuracy_stats`, the ids are also kept in a set to count the errors
+    /// of the filters in [KnownOps::stats], and that set is in the memory.
+    AgingBloom {
+        capacity: usize,
+        bits: usize,
+        hashes: usize,
+        accuracy_stats: bool,
+    },
+}
+
+/// Errors of the filters, counted against the exact ids when
+/// `accuracy_stats` is set
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KnownOpsStats {
+    /// Ids never inserted but reported known, in `on_operation_received`
+    /// that's an operation we don't announce to the peer
+    pub false_positives: u64,
+    /// Ids removed but still in the filters
+    pub not_removed: u64,
+    /// Ids inserted but forgotten by the aging, announced twice
+    pub forgotten: u64,
+}
+
+impl KnownOpsStats {
+    pub fn add(&mut self, other: &KnownOpsStats) {
+        self.false_positives += other.false_positives;
+        self.not_removed += other.not_removed;
+        self.forgotten += other.forgotten;
+    }
+}
+
+#[derive(Default)]
+struct Bloom {
+    /// Allocated with the first id
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl Bloom {
+    fn indexes(op_id: u64, bits: usize, hashes: usize) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        op_id.hash(&mut hasher);
+        let hash = hasher.finish();
+        // double hashing, see Kirsch and Mitzenmacher
+        let (h1, h2) = (hash & u32::MAX as u64, (hash >> 32) | 1);
+        (0..hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits as u64) as usize)
+    }
+
+    fn insert(&mut self, op_id: u64, bits: usize, hashes: usize) {
+        if self.bits.is_empty() {
+            self.bits = vec![0; bits.div_ceil(64)];
+        }
+        for index in Self::indexes(op_id, bits, hashes) {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+        self.len += 1;
+    }
+
+    fn contains(&self, op_id: u64, bits: usize, hashes: usize) -> bool {
+        !self.bits.is_empty()
+            && Self::indexes(op_id, bits, hashes)
+                .all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+}
+
+#[derive(Default)]
+pub struct KnownOps {
+    repr: KnownOpsRepr,
+    /// The ids with [KnownOpsRepr::Exact]. With the filters, only kept with
+    /// `accuracy_stats` to count their errors.
+    exact: HashSet<u64>,
+    /// Ids removed from `exact` but not from the filters
+    removed: HashSet<u64>,
+    current: Bloom,
+    previous: Bloom,
+    pub stats: KnownOpsStats,
+}
+
+impl KnownOps {
+    pub fn new(repr: KnownOpsRepr) -> Self {
+        if let KnownOpsRepr::AgingBloom { bits, hashes, .. } = repr {
+            // the indexes are taken modulo `bits`, and without hash every id
+            // would be known
+            assert!(bits > 0, "bits must be > 0");
+            assert!(hashes > 0, "hashes must be > 0");
+        }
+        Self {
+            repr,
+            ..Default::default()
+        }
+    }
+
+    pub fn contains(&self, op_id: &u64) -> bool {
+        match self.repr {
+            KnownOpsRepr::Exact => self.exact.contains(op_id),
+            KnownOpsRepr::AgingBloom { bits, hashes, .. } => {
+                self.current.contains(*op_id, bits, hashes)
+                    || self.previous.contains(*op_id, bits, hashes)
+            }
+        }
+    }
+
+    /// The ids are kept in `exact`
+    fn is_exact(&self) -> bool {
+        match self.repr {
+            KnownOpsRepr::Exact => true,
+            KnownOpsRepr::AgingBloom { accuracy_stats, .. } => accuracy_stats,
+        }
+    }
+
+    fn add(&mut self, op_id: u64) {
+        if self.is_exact() {
+            self.exact.insert(op_id);
+            self.removed.remove(&op_id);
+        }
+        if let KnownOpsRepr::AgingBloom {
+            capacity,
+            bits,
+            hashes,
+            ..
+        } = self.repr
+        {
+            if !self.contains(&op_id) {
+                if self.current.len >= capacity {
+                    self.previous = std::mem::take(&mut self.current);
+                }
+                self.current.insert(op_id, bits, hashes);
+            }
+        }
+    }
+
+    /// Return true if the peer didn't know `op_id`, we have to announce it
+    pub fn insert(&mut self, op_id: u64) -> bool {
+        let known = self.contains(&op_id);
+        if !self.is_exact() {
+            self.add(op_id);
+            return !known;
+        }
+        let exact_known = self.exact.contains(&op_id);
+        let removed = self.removed.contains(&op_id);
+        self.add(op_id);
+        match (known, exact_known) {
+            (true, false) if removed => self.stats.not_removed += 1,
+            (true, false) => self.stats.false_positives += 1,
+            (false, true) => self.stats.forgotten += 1,
+            _ => {}
+        }
+        !known
+    }
+
+    /// The filters can't remove an id, it's only forgotten with its
+    /// generation
+    pub fn remove(&mut self, op_id: &u64) {
+        if self.exact.remove(op_id) && self.repr != KnownOpsRepr::Exact {
+            self.removed.insert(*op_id);
+        }
+    }
+
+    /// Approximate bytes on the heap, with the sets of `accuracy_stats`
+    pub fn memory(&self) -> usize {
+        self.filters_memory() + set_memory(&self.exact) + set_memory(&self.removed)
+    }
+
+    /// Approximate bytes on the heap of the Bloom filters alone
+    pub fn filters_memory(&self) -> usize {
+        (self.current.bits.capacity() + self.previous.bits.capacity()) * size_of::<u64>()
+    }
+
+    /// Approximate bytes on the heap of the same ids in an exact set, 0 with
+    /// the filters without `accuracy_stats`
+    pub fn exact_memory(&self) -> usize {
+        set_memory(&self.exact)
+    }
+}
+
+impl Extend<u64> for KnownOps {
+    fn extend<I: IntoIterator<Item = u64>>(&mut self, op_ids: I) {
+        for op_id in op_ids {
+            self.add(op_id);
+        }
+    }
+}
+
+impl<'a> Extend<&'a u64> for KnownOps {
+    fn extend<I: IntoIterator<Item = &'a u64>>(&mut self, op_ids: I) {
+        self.extend(op_ids.into_iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bloom(accuracy_stats: bool) -> KnownOpsRepr {
+        KnownOpsRepr::AgingBloom {
+            capacity: 100,
+            bits: 64,
+            hashes: 2,
+            accuracy_stats,
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "bits")]
+    fn zero_bits_is_rejected() {
+        KnownOps::new(KnownOpsRepr::AgingBloom {
+            capacity: 100,
+            bits: 0,
+            hashes: 2,
+            accuracy_stats: false,
+        });
+    }
+
+    #[test]
+    fn exact_set_only_with_accuracy_stats() {
+        let mut known_ops = KnownOps::new(bloom(false));
+        known_ops.extend(0..1_000);
+        assert_eq!(known_ops.exact_memory(), 0);
+        assert!(known_ops.memory() <= 2 * size_of::<u64>());
+        // the filter of 64 bits is full, every id is a false positive
+        assert!(!known_ops.insert(5_000));
+        assert_eq!(known_ops.stats.false_positives, 0);
+
+        let mut known_ops = KnownOps::new(bloom(true));
+        known_ops.extend(0..1_000);
+        assert!(known_ops.exact_memory() > 0);
+        assert!(known_ops.memory() > known_ops.exact_memory());
+        assert!(!known_ops.insert(5_000));
+        assert_eq!(known_ops.stats.false_positives, 1);
+    }
+}
@@ -1,6 +1,15 @@
 use super::types::*;
+use crate::batch::split_batch;
+use crate::hooks::{
+    admit_batch, ban_if_needed, is_trusted, missing_ids, pull_if_needed, serve_deferred_asks,
+    Protocol, Shared,
+};
+use crate::operation::by_priority;
+use crate::trace::{Event, Recorder};
 use std::time::{Duration, Instant};
 
+pub use crate::hooks::{on_ask_received, on_fees_received, on_node_disconnected};
+
 /***************************************************************************************** */
 /* Things that must be in the both algorithms                                              */
 // - send_batch(node_id, operations_ids): Send batch to a node
@@ -19,14 +28,23 @@ fn send_batch(_to_node_id: NodeId, _batch: OperationIds) {
         .push((_to_node_id, _batch));
 }
 
-fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds) {
+fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds, _recorder: &Recorder) {
     //#[cfg(feature = "measurements")]
+    _recorder.record(|| Event::AskSent(_to_node_id, _op_ids.clone()));
     super::ASK_BATCH_QUEUE
         .lock()
         .unwrap()
         .push_back((_to_node_id, _op_ids));
 }
 
+fn ask_digest(_to_node_id: NodeId) {
+    //#[cfg(feature = "measurements")]
+    super::DIGEST_ASK_QUEUE
+        .lock()
+        .unwrap()
+        .push_back(_to_node_id);
+}
+
 // difer from the other algo
 fn send_operations(_to_node_id: NodeId, _op_ids: OperationMap) {
     //#[cfg(feature = "measurements")]
@@ -36,6 +54,42 @@ fn send_operations(_to_node_id: NodeId, _op_ids: OperationMap) {
         .push_back((_to_node_id, _op_ids));
 }
 
+impl Protocol for FakeProtocol {
+    type Info = NodeInfo;
+
+    fn shared(&mut self) -> Shared<'_, NodeInfo> {
+        Shared {
+            node_infos: &mut self.node_infos,
+            received: &mut self.received,
+            op_fees: &mut self.op_fees,
+            banned: &mut self.banned,
+            throttle_stats: &mut self.throttle_stats,
+            pull: &mut self.pull,
+            rng: &mut self.rng,
+            recorder: &self.recorder,
+            clock: &self.clock,
+            max_batch_size: self.max_batch_size,
+            oversized_policy: self.oversized_policy,
+            op_validation: self.op_validation,
+            ask_quota: self.ask_quota,
+            scoring: self.scoring,
+            is_measured: self.is_measured,
+        }
+    }
+
+    fn forget_asks_to(&mut self, node_id: NodeId) {
+        forget_asks_to(node_id, self);
+    }
+
+    fn send_operations(node_id: NodeId, operations: OperationMap) {
+        send_operations(node_id, operations);
+    }
+
+    fn ask_digest(node_id: NodeId) {
+        ask_digest(node_id);
+    }
+}
+
 ///```py
 ///def process_op_batch(op_batch, node_id):
 ///    ask_set = void HashSet<OperationId>
@@ -63,10 +117,16 @@ pub fn on_batch_received(
     node_id: NodeId,
     protocol: &mut FakeProtocol, /* self simulation */
 ) -> OperationIds {
-    let mut ask_set = OperationIds::with_capacity(op_batch.len());
-    let mut future_set = OperationIds::with_capacity(op_batch.len());
+    let op_batch = match admit_batch(op_batch, node_id, protocol) {
+        Some(op_batch) => op_batch,
+        None => return OperationIds::default(),
+    };
+    // we ask a node with a bad score only if nobody else has been asked
+    let trusted = is_trusted(node_id, protocol);
+    let mut ask_set = OperationIds::with_capacity_and_hasher(op_batch.len(), Default::default());
+    let mut future_set = OperationIds::with_capacity_and_hasher(op_batch.len(), Default::default());
     // exactitude isn't important, we want to have a now for that function call
-    let now = Instant::now();
+    let now = protocol.clock.now();
     for op_id in op_batch {
         if protocol.received.contains_key(&op_id) {
             // Should I manage here the prune of `wanted`, `op_batch_buffer` etc?
@@ -82,7 +142,7 @@ pub fn on_batch_received(
             }
             None => None,
         };
-        if wish.is_some() && wish.unwrap().0 > now {
+        if wish.is_some() && (wish.unwrap().0 > now || !trusted) {
             future_set.insert(op_id);
         } else {
             ask_set.insert(op_id);
@@ -91,75 +151,261 @@ pub fn on_batch_received(
                 .insert(op_id, (now, vec![node_id]));
         }
     }
-    if protocol.op_batch_buffer.len() < protocol.op_batch_buf_capacity {
-        protocol.op_batch_buffer.push_back((
-            now + Duration::from_millis(protocol.op_batch_proc_period),
-            node_id,
-            future_set,
-        ));
-    }
+    buffer_future_set(
+        node_id,
+        future_set,
+        now + Duration::from_millis(protocol.op_batch_proc_period),
+        protocol,
+    );
     if protocol.is_measured {
         // just for the measurement, remove that on the definitive implementation
-        ask_operations(node_id, ask_set.clone());
+        // ask the most prioritary operations first
+        let asked = by_priority(ask_set.iter().cloned(), &protocol.op_fees);
+        for chunk in asked.chunks(protocol.max_batch_size) {
+            ask_operations(node_id, chunk.iter().cloned().collect(), &protocol.recorder);
+        }
     }
     ask_set
 }
 
+/// Keep the `future_set` of `node_id` in the [FakeProtocol::op_batch_buffer]
+/// until `deadline`. When the buffer is full, the
+/// [FakeProtocol::buffer_overflow] policy applies.
+fn buffer_future_set(
+    node_id: NodeId,
+    mut future_set: OperationIds,
+    deadline: Instant,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    if future_set.is_empty() {
+        return;
+    }
+    let buffer = &mut protocol.op_batch_buffer;
+    let stats = &mut protocol.buffer_stats;
+    if buffer.len() < protocol.op_batch_buf_capacity {
+        buffer.push(deadline, (node_id, future_set));
+        return;
+    }
+    match protocol.buffer_overflow {
+        BufferOverflowPolicy::DropNewest => {
+            stats.dropped_entries += 1;
+            stats.dropped_ids += future_set.len() as u64;
+        }
+        BufferOverflowPolicy::DropOldest => {
+            buffer.push(deadline, (node_id, future_set));
+            if let Some((_, (_, oldest))) = buffer.pop_first() {
+                stats.dropped_entries += 1;
+                stats.dropped_ids += oldest.len() as u64;
+            }
+        }
+        policy => {
+            if policy == BufferOverflowPolicy::CoalesceByOpId {
+                let len = future_set.len();
+                for (_, (_, op_ids)) in buffer.iter() {
+                    future_set.retain(|op_id| !op_ids.contains(op_id));
+                }
+                stats.coalesced_ids += (len - future_set.len()) as u64;
+                if future_set.is_empty() {
+                    return;
+                }
+            }
+            let last_of_node = buffer
+                .iter()
+                .rev()
+                .find(|(_, (buffered, _))| *buffered == node_id)
+                .map(|(key, _)| key);
+            match last_of_node.and_then(|key| buffer.get_mut(key)) {
+                Some((_, op_ids)) => {
+                    stats.merged_ids += future_set.len() as u64;
+                    op_ids.extend(future_set);
+                }
+                None => {
+                    buffer.push(deadline, (node_id, future_set));
+                }
+            }
+        }
+    }
+}
+
 /* We can prune the buffer from the informations received in another future.
  */
 
+/// Verify the operations received from `node_id`, update the score of the
+/// node and return only the valid operations. The invalid ones are no longer
+/// wanted from anybody, their next announcement is asked.
+fn filter_valid_operations(
+    node_id: NodeId,
+    mut operations: OperationMap,
+    protocol: &mut FakeProtocol, /* self simulation */
+) -> OperationMap {
+    let validation = protocol.op_validation;
+    let len = operations.len();
+    let wanted = &mut protocol.wanted_alias_asked_ops;
+    operations.retain(|op_id, operation| {
+        let valid = crate::validation::validate(*op_id, &operation.content, validation);
+        if !valid {
+            // rejected operations can be asked again, maybe to another node
+            wanted.remove(op_id);
+        }
+        valid
+    });
+    let invalid = (len - operations.len()) as u64;
+    if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+        node_info.score.invalid += invalid;
+        node_info.score.delivered += operations.len() as u64;
+    }
+    operations
+}
+
 pub fn on_operation_received(
     node_id: NodeId,
     operations: OperationMap,
     protocol: &mut FakeProtocol, /* self simulation */
 ) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let operations = filter_valid_operations(node_id, operations, protocol);
+    ban_if_needed(node_id, protocol);
+    for op_id in operations.keys() {
+        protocol.op_fees.remove(op_id);
+    }
     protocol.received.extend(operations.clone());
     if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
         node_info.known_op.extend(operations.keys());
     }
+    let now = protocol.clock.now();
     for (node_id, node_info) in protocol.node_infos.iter_mut() {
-        let mut batch = OperationIds::default();
-        batch.extend(
-            operations
-                .keys()
-                .filter(|&&op_id| node_info.known_op.insert(op_id)),
-        );
-        if protocol.is_measured {
-            // just for the measurement, remove that on the definitive implementation
-            send_batch(*node_id, batch);
+        let op_ids = operations
+            .keys()
+            .filter(|&&op_id| node_info.known_op.insert(op_id))
+            .cloned();
+        for batch in node_info.announce.push(
+            op_ids,
+            protocol.max_batch_size,
+            protocol.announce_delay,
+            now,
+        ) {
+            protocol.batch_fill.record(&batch);
+            if protocol.is_measured {
+                // just for the measurement, remove that on the definitive implementation
+                send_batch(*node_id, batch);
+            }
+        }
+    }
+}
+
+/// Send the announcements that waited `announce_delay` for their batch to fill
+fn flush_announcements(protocol: &mut FakeProtocol /* self simulation */) {
+    let now = protocol.clock.now();
+    for (node_id, node_info) in protocol.node_infos.iter_mut() {
+        if let Some(batch) = node_info.announce.take_due(protocol.announce_delay, now) {
+            protocol.batch_fill.record(&batch);
+            if protocol.is_measured {
+                // just for the measurement, remove that on the definitive implementation
+                send_batch(*node_id, batch);
+            }
         }
     }
 }
 
-pub fn on_ask_received(
+/// `node_id` answered our pull with the ids it received recently, the ones
+/// we miss are processed like an announcement of that node.
+pub fn on_digest_received(
     node_id: NodeId,
-    op_ids: OperationIds,
+    digest: OperationIds,
     protocol: &mut FakeProtocol, /* self simulation */
 ) {
-    if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
-        for op_ids in op_ids.iter() {
-            node_info.known_op.remove(op_ids);
+    for batch in missing_ids(node_id, digest, protocol) {
+        on_batch_received(batch, node_id, protocol);
+    }
+}
+
+/// A node connected to us, we announce it all the operations we have
+pub fn on_node_connected(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let node_info = protocol.node_infos.connect(node_id);
+    let op_ids = protocol
+        .received
+        .keys()
+        .filter(|&&op_id| node_info.known_op.insert(op_id))
+        .cloned();
+    for batch in node_info.announce.push(
+        op_ids,
+        protocol.max_batch_size,
+        protocol.announce_delay,
+        protocol.clock.now(),
+    ) {
+        protocol.batch_fill.record(&batch);
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            send_batch(node_id, batch);
         }
     }
-    let mut operation_map = OperationMap::default();
-    for op_id in op_ids.iter() {
-        if let Some(op) = protocol.received.get(op_id) {
-            operation_map.insert(*op_id, op.clone());
+}
+
+/// The operations asked only to `node_id` will be asked to another node, and
+/// its buffered announcements won't be asked to it
+fn forget_asks_to(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    protocol.wanted_alias_asked_ops.retain(|_, (_, node_ids)| {
+        node_ids.retain(|asked| *asked != node_id);
+        !node_ids.is_empty()
+    });
+    protocol
+        .op_batch_buffer
+        .retain(|(buffered, _)| *buffered != node_id);
+}
+
+/// Take the due entries of the op_batch_buffer and reprocess on batch
+/// received, without the operations received in the meantime
+pub fn on_send_loop(protocol: &mut FakeProtocol /* self simulation */) {
+    let now = protocol.clock.now();
+    while let Some((_, (node_id, mut op_batch))) = protocol.op_batch_buffer.pop_due(now) {
+        op_batch.retain(|op_id| !protocol.received.contains_key(op_id));
+        if op_batch.is_empty() {
+            continue;
+        }
+        // a merged entry can be bigger than a batch
+        for op_batch in split_batch(op_batch, protocol.max_batch_size) {
+            on_batch_received(op_batch, node_id, protocol);
         }
     }
-    if protocol.is_measured {
-        // just for the measurement, remove that on the definitive implementation
-        send_operations(node_id, operation_map);
+    flush_announcements(protocol);
+    pull_if_needed(protocol);
+    if let Some(scoring) = protocol.scoring {
+        check_ask_timeouts(scoring, protocol);
     }
+    serve_deferred_asks(protocol);
 }
 
-/// Take the op_batch_buffer and reprocess on batch received
-pub fn on_send_loop(protocol: &mut FakeProtocol /* self simulation */) {
-    while !protocol.op_batch_buffer.is_empty()
-        && std::time::Instant::now() > protocol.op_batch_buffer.front().unwrap().0
-    {
-        let (_, node_id, op_batch) = protocol.op_batch_buffer.pop_front().unwrap();
-        on_batch_received(op_batch, node_id, protocol);
+/// The nodes asked for an operation we still don't have after
+/// `ask_timeout` are penalized. We forget that we asked the operation so the
+/// next announcement of it will be asked.
+fn check_ask_timeouts(
+    scoring: ScoreConfig,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    let now = protocol.clock.now();
+    let timed_out: Vec<(OperationId, Vec<NodeId>)> = protocol
+        .wanted_alias_asked_ops
+        .iter()
+        .filter(|(op_id, (asked_at, _))| {
+            now > *asked_at + scoring.ask_timeout && !protocol.received.contains_key(op_id)
+        })
+        .map(|(op_id, (_, node_ids))| (*op_id, node_ids.clone()))
+        .collect();
+    for (op_id, node_ids) in timed_out {
+        protocol.wanted_alias_asked_ops.remove(&op_id);
+        // expired, the fee comes again with the next announcement
+        protocol.op_fees.remove(&op_id);
+        for node_id in node_ids {
+            if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+                node_info.score.timeouts += 1;
+            }
+            ban_if_needed(node_id, protocol);
+        }
     }
 }
 
@@ -168,4 +414,150 @@ pub fn on_send_loop(protocol: &mut FakeProtocol /* self simulation */) {
 /// (let's don't prune for now, we will absolutly do that in the final implementation)
 pub fn _on_prune_asked_lifetime_loop(protocol: &mut FakeProtocol /* self simulation */) {
     protocol.wanted_alias_asked_ops.clear();
+    protocol.op_fees.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::sign;
+
+    /// A protocol that doesn't push in the queues of the measurements
+    fn protocol(nodes: usize) -> FakeProtocol {
+        let mut protocol = FakeProtocol::new(nodes, 10, 200, 100);
+        protocol.op_validation = OpValidation::Hash { rounds: 1 };
+        protocol.is_measured = false;
+        protocol
+    }
+
+    fn operation(op_id: OperationId, content: String) -> OperationMap {
+        [(
+            op_id,
+            Operation {
+                fee: 0,
+                content,
+                wire_size: None,
+            },
+        )]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn invalid_operation_is_asked_again() {
+        let mut protocol = protocol(2);
+        assert_eq!(
+            on_batch_received([1].into_iter().collect(), 0, &mut protocol),
+            [1].into_iter().collect()
+        );
+        on_operation_received(0, operation(1, "garbage".to_string()), &mut protocol);
+        assert!(!protocol.received.contains_key(&1));
+        assert!(!protocol.wanted_alias_asked_ops.contains_key(&1));
+        // asked right away to the next node, no need to wait for the buffer
+        assert_eq!(
+            on_batch_received([1].into_iter().collect(), 1, &mut protocol),
+            [1].into_iter().collect()
+        );
+        on_operation_received(1, operation(1, sign(1)), &mut protocol);
+        assert!(protocol.received.contains_key(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "max_batch_size")]
+    fn zero_batch_size_is_rejected() {
+        FakeProtocol::new(1, 0, 200, 100);
+    }
+
+    /// The operations are asked to a node that leaves, the future sets of two
+    /// untrusted nodes are the only way to ask them again. The buffer has
+    /// room for 2 of the 6 future sets.
+    #[test]
+    fn buffer_overflow_policies() {
+        const IDS: u64 = 300;
+        for policy in [
+            BufferOverflowPolicy::DropNewest,
+            BufferOverflowPolicy::DropOldest,
+            BufferOverflowPolicy::MergeByNode,
+            BufferOverflowPolicy::CoalesceByOpId,
+        ] {
+            let mut protocol = FakeProtocol::new(3, 100, 1, 2);
+            protocol.buffer_overflow = policy;
+            protocol.is_measured = false;
+            let batches = split_batch(0..IDS, 100);
+            for batch in batches.iter() {
+                on_batch_received(batch.clone(), 0, &mut protocol);
+            }
+            let deadline = protocol.clock.now();
+            for node_id in [1, 2] {
+                for batch in batches.iter() {
+                    buffer_future_set(node_id, batch.clone(), deadline, &mut protocol);
+                }
+            }
+            on_node_disconnected(0, &mut protocol);
+            let buffered: OperationIds = protocol
+                .op_batch_buffer
+                .iter()
+                .flat_map(|(_, (_, op_ids))| op_ids.iter().cloned())
+                .collect();
+            let lost = (0..IDS)
+                .filter(|op_id| {
+                    !buffered.contains(op_id)
+                        && !protocol.wanted_alias_asked_ops.contains_key(op_id)
+                })
+                .count() as u64;
+            let stats = protocol.buffer_stats;
+            match policy {
+                BufferOverflowPolicy::DropNewest | BufferOverflowPolicy::DropOldest => {
+                    assert!(lost > 0, "{:?}", policy);
+                    assert_eq!(stats.dropped_entries, 4, "{:?}", policy);
+                    assert!(stats.dropped_ids >= lost, "{:?}", policy);
+                }
+                _ => {
+                    assert_eq!(lost, 0, "{:?}", policy);
+                    assert_eq!(stats.dropped_entries, 0, "{:?}", policy);
+                }
+            }
+            // the buffered ids are asked again once due
+            on_send_loop(&mut protocol);
+            assert_eq!(
+                protocol.wanted_alias_asked_ops.len() as u64,
+                IDS - lost,
+                "{:?}",
+                policy
+            );
+        }
+    }
+
+    #[test]
+    fn fees_are_forgotten_once_received() {
+        let mut protocol = protocol(1);
+        on_fees_received([(1, 10), (2, 20)].into_iter().collect(), &mut protocol);
+        on_batch_received([1, 2].into_iter().collect(), 0, &mut protocol);
+        on_operation_received(0, operation(1, sign(1)), &mut protocol);
+        assert_eq!(protocol.op_fees, [(2, 20)].into_iter().collect());
+        // announced again by a late node
+        on_fees_received([(1, 10)].into_iter().collect(), &mut protocol);
+        assert_eq!(protocol.op_fees, [(2, 20)].into_iter().collect());
+    }
+
+    #[test]
+    fn banned_node_is_not_waited_for() {
+        let mut protocol = protocol(2);
+        protocol.scoring = Some(ScoreConfig {
+            ask_timeout: Duration::from_secs(60),
+            ban_threshold: -1,
+            weights: ScoreWeights::DEFAULT,
+        });
+        on_batch_received([1, 2].into_iter().collect(), 0, &mut protocol);
+        // untrusted, its ids wait in the buffer
+        protocol.node_infos.get_mut(&1).unwrap().score.timeouts = 1;
+        on_batch_received([1, 2].into_iter().collect(), 1, &mut protocol);
+        assert_eq!(protocol.op_batch_buffer.len(), 1);
+        on_operation_received(0, operation(1, "garbage".to_string()), &mut protocol);
+        assert!(protocol.banned.contains_key(&0));
+        assert!(protocol.wanted_alias_asked_ops.is_empty());
+        on_operation_received(1, operation(2, "garbage".to_string()), &mut protocol);
+        assert!(protocol.banned.contains_key(&1));
+        assert!(protocol.op_batch_buffer.is_empty());
+    }
 }
@@ -1,196 +1,155 @@
 use super::*;
-use rand::{seq::SliceRandom, Rng};
-use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+use crate::{
+    clock::Clock,
+    harness::{
+        self, Algorithm, Answer, ANNOUNCE_DELAY, ASK_QUOTA, MAX_BATCH_SIZE, N, OP_VALIDATION,
+        SCORING, T,
     },
-    thread::JoinHandle,
-    time::Duration,
+    memory::MemoryUsage,
+    metrics::Report,
+    rng::{SimRng, Stream},
+    scenario::Scenario,
+    trace::{Recorder, ReplayReport, Trace},
 };
+use rand::seq::SliceRandom;
 
-const MAX_BATCH_SIZE: usize = 100;
 const OP_BATCH_PROC_PERIOD: u64 = 200;
-const T: usize = 25; // Numer of nodes
-const N: usize = 10_000; // Number of operations
-                         // for this test we need to have the same buffer size as the input
-                         // operations number
+// for this test we need to have the same buffer size as the input
+// operations number
 const OP_BATCH_BUF_CAPACITY: usize = N;
 
-const _: () = {
-    if T == 0 {
-        panic!("We need a number of nodes > 0");
+impl Algorithm for FakeProtocol {
+    const NAME: &'static str = "less_cpu";
+    // the asks are sent with the batches, no asking loop
+    const ASKING_LOOP: bool = false;
+
+    fn new_protocol() -> Self {
+        let mut protocol = FakeProtocol::new(
+            T,
+            MAX_BATCH_SIZE,
+            OP_BATCH_PROC_PERIOD,
+            OP_BATCH_BUF_CAPACITY,
+        );
+        protocol.announce_delay = ANNOUNCE_DELAY;
+        protocol.op_validation = OP_VALIDATION;
+        protocol.ask_quota = Some(ASK_QUOTA);
+        protocol.scoring = Some(SCORING);
+        protocol
     }
-    if N % MAX_BATCH_SIZE != 0 {
-        panic!("For the test N should be a multiple of MAX_BATCH_SIZE");
+
+    fn prepare(&mut self, scenario: &Scenario, recorder: Recorder) {
+        self.pull = Puller::new(scenario.pull);
+        self.rng = scenario.rng(Stream::Protocol);
+        self.recorder = recorder;
+        self.node_infos.set_known_ops(scenario.known_ops);
     }
-};
 
-fn print_output(measures: Vec<(NodeId, OperationIds)>) {
-    println!("Total batches required: {}", measures.len());
-    println!(
-        "We asked an average of {} times each node",
-        measures.len() / T
-    );
-    let mut ids = OperationIds::new();
-    let mut nodes = vec![0; T + 1];
-    for (node_id, operation_ids) in measures.iter() {
-        *nodes.get_mut(*node_id as usize).unwrap() += 1;
-        ids.extend(operation_ids.clone());
-    }
-    println!("Asking table by nodes:\n{:?}", nodes);
-    println!("Correctly processed: {}", ids.len() == N);
-    assert_eq!(ids.len(), N);
-}
+    fn set_measured(&mut self, is_measured: bool) {
+        self.is_measured = is_measured;
+    }
 
-pub fn process() {
-    let protocol = Arc::new(Mutex::new(new_protocol()));
-    let sig_stop = Arc::new(AtomicBool::new(true));
-    let batch_sender = run_batch_sender(protocol.clone());
-    let batch_receiver = run_operations_asked_receiver(protocol.clone());
-    let operation_asker = run_operation_asker(protocol.clone(), sig_stop.clone());
-    let send_loop = run_send_loop(protocol, sig_stop.clone());
-    batch_sender.join().unwrap();
-    let operations_asked = batch_receiver.join().unwrap();
-    sig_stop.store(false, Ordering::Relaxed);
-    operation_asker.join().unwrap();
-    send_loop.join().unwrap();
-    print_output(operations_asked);
-}
+    fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
 
-fn new_protocol() -> FakeProtocol {
-    FakeProtocol::new(
-        T,
-        MAX_BATCH_SIZE,
-        OP_BATCH_PROC_PERIOD,
-        OP_BATCH_BUF_CAPACITY,
-    )
-}
+    fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    fn received(&self) -> &OperationMap {
+        &self.received
+    }
+
+    fn memory(&self) -> MemoryUsage {
+        FakeProtocol::memory(self)
+    }
+
+    fn print_stats(&self, scenario: &Scenario) {
+        harness::print_peers(
+            scenario,
+            &self.node_infos,
+            &self.banned,
+            &self.throttle_stats,
+            &self.pull,
+        );
+        println!("Buffer overflows: {:?}", self.buffer_stats);
+        self.batch_fill.print(MAX_BATCH_SIZE);
+    }
+
+    fn clear_queues() {
+        ASK_BATCH_QUEUE.lock().unwrap().clear();
+        BATCH_SEND_QUEUE.lock().unwrap().clear();
+        SEND_OPERATION.lock().unwrap().clear();
+        DIGEST_ASK_QUEUE.lock().unwrap().clear();
+    }
+
+    fn pop_ask(_rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        ASK_BATCH_QUEUE.lock().unwrap().pop_front()
+    }
+
+    fn drain_asks() -> Vec<(NodeId, OperationIds)> {
+        ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+    }
+
+    fn pop_announce(rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        let mut guard = BATCH_SEND_QUEUE.lock().unwrap();
+        guard.shuffle(rng);
+        guard.pop()
+    }
+
+    fn pop_pull() -> Option<NodeId> {
+        DIGEST_ASK_QUEUE.lock().unwrap().pop_front()
+    }
+
+    fn on_node_connected(&mut self, node_id: NodeId) {
+        on_node_connected(node_id, self);
+    }
+
+    fn on_node_disconnected(&mut self, node_id: NodeId) {
+        on_node_disconnected(node_id, self);
+    }
+
+    fn on_fees_received(&mut self, fees: OperationFees) {
+        on_fees_received(fees, self);
+    }
+
+    fn on_batch_received(&mut self, node_id: NodeId, op_ids: OperationIds) -> OperationIds {
+        on_batch_received(op_ids, node_id, self)
+    }
+
+    fn on_operations(&mut self, node_id: NodeId, answer: Answer) {
+        let operations = answer
+            .into_iter()
+            .filter_map(|(id, op)| op.map(|op| (id, op)))
+            .collect();
+        on_operation_received(node_id, operations, self);
+    }
 
-fn run_batch_sender(protocol: Arc<Mutex<FakeProtocol>>) -> JoinHandle<()> {
-    std::thread::spawn(move || {
-        let mut p = 0;
-        let mut orders = Box::new([[0; N + 1]; T + 1]); // Order of operations for each node
-        let mut operations = Box::new([0usize; N + 1]); // Table of operations
-        for n in 0..N {
-            for order in orders.iter_mut() {
-                order[n] = n; // init an order to shuffle later
-            }
-            operations[n] = rand::random();
-        }
-        let mut thread_rng = rand::thread_rng();
-        for order in orders.iter_mut() {
-            order.shuffle(&mut thread_rng);
-        }
-        while p < N {
-            for (node_id, order) in orders.iter().enumerate() {
-                let mut batch = OperationIds::default();
-                for &op_id in order.iter().skip(p).take(MAX_BATCH_SIZE) {
-                    batch.insert(op_id as u64);
-                }
-                on_batch_received(batch, node_id as u64, &mut protocol.lock().unwrap());
-            }
-            p += MAX_BATCH_SIZE;
-        }
-    })
+    fn on_ask_received(&mut self, node_id: NodeId, op_ids: OperationIds) {
+        on_ask_received(node_id, op_ids, self);
+    }
+
+    fn on_digest_received(&mut self, node_id: NodeId, digest: OperationIds) {
+        on_digest_received(node_id, digest, self);
+    }
+
+    fn on_send_tick(&mut self) {
+        on_send_loop(self);
+    }
 }
 
-/// Ici nous avons la simulation de nos demande d'operations,
-/// ces demandes sont fait suite a des receptions de batch de la part des
-/// autres noeuds.
-///
-/// On reserve en cache la liste des demandes de notre noeuds pour
-/// l'analyser en valeur de retour à la fin du process de mesure.
-///
-///
-/// Observation suite aux mesures:
-/// On remarque que plus on va mettre du temps à recevoir des operations,
-/// plus on redemandera de batch mais pas toujours aux mêmes.
-fn run_operations_asked_receiver(
-    protocol: Arc<Mutex<FakeProtocol>>,
-) -> JoinHandle<Vec<(NodeId, OperationIds)>> {
-    const MIN_SLEEP: u64 = 300;
-    const MAX_SLEEP: u64 = 600;
-    std::thread::spawn(move || {
-        let mut cache = vec![];
-        let mut diff_op = OperationIds::new();
-        let mut thread_rng = rand::thread_rng();
-        while diff_op.len() < N {
-            // little sleep that simulate the time of a communication
-            // for the example it's not needed to be very big
-            let rand_sleep = Duration::from_nanos(thread_rng.gen_range(MIN_SLEEP..MAX_SLEEP));
-            std::thread::sleep(rand_sleep);
-            let opt_asked = ASK_BATCH_QUEUE.lock().unwrap().pop_front();
-            if let Some((node_id, operation_ids)) = opt_asked {
-                cache.push((node_id, operation_ids.clone()));
-                diff_op.extend(operation_ids.clone());
-                let mut operations = OperationMap::new();
-                for id in operation_ids {
-                    operations.insert(id, String::new());
-                }
-                on_operation_received(node_id, operations, &mut protocol.lock().unwrap());
-            }
-        }
-        cache
-    })
+pub fn process(scenario: &Scenario) -> Report {
+    harness::process::<FakeProtocol>(scenario)
 }
 
-/// Ici je souhaite simuler la boucle qui process `on_send_loop`.
-/// On va aller bcp plus vite que dans la réalité mais on va essayer de rendre
-/// ça un peu plus lent que le reste quand même.
-///
-/// On ne le lance que pour notre noeud local. Cette loop simulée pour les
-/// autres dans [run_operation_asker]. En effet, a leur facon il redemande à
-/// chaque tour de boucle a notre noeud local des operations.
-fn run_send_loop(protocol: Arc<Mutex<FakeProtocol>>, stop: Arc<AtomicBool>) -> JoinHandle<()> {
-    const MIN_SLEEP: u64 = 50;
-    const MAX_SLEEP: u64 = 200;
-    std::thread::spawn(move || {
-        let mut thread_rng = rand::thread_rng();
-        while stop.load(Ordering::Relaxed) {
-            let rand_sleep = Duration::from_nanos(thread_rng.gen_range(MIN_SLEEP..MAX_SLEEP));
-            std::thread::sleep(rand_sleep);
-            on_send_loop(&mut protocol.lock().unwrap())
-        }
-    })
+/// Feed the inputs of `trace` to the hooks of a new measured node, see
+/// [harness::replay]
+pub fn replay(trace: &Trace) -> ReplayReport {
+    harness::replay::<FakeProtocol>(trace)
 }
 
-/// Le noeud envoie des batches et c'est dans ce thread qu'onva gere ce que
-/// les noeuds demande en retour.
-fn run_operation_asker(
-    protocol: Arc<Mutex<FakeProtocol>>,
-    stop: Arc<AtomicBool>,
-) -> JoinHandle<Vec<(NodeId, OperationIds)>> {
-    const MIN_SLEEP: u64 = 2;
-    const MAX_SLEEP: u64 = 20;
-    std::thread::spawn(move || {
-        let mut thread_rng = rand::thread_rng();
-        let mut cache = vec![];
-        let mut protocols = (0..N)
-            .map(|_| {
-                let mut protocol = new_protocol();
-                protocol.is_measured = false;
-                protocol
-            })
-            .collect::<Vec<FakeProtocol>>();
-        while stop.load(Ordering::Relaxed) {
-            let rand_sleep = Duration::from_nanos(thread_rng.gen_range(MIN_SLEEP..MAX_SLEEP));
-            std::thread::sleep(rand_sleep);
-            let opt_node_batch = {
-                let mut guard = BATCH_SEND_QUEUE.lock().unwrap();
-                guard.shuffle(&mut thread_rng);
-                guard.pop()
-            };
-            if let Some((node_id, operation_ids)) = opt_node_batch {
-                cache.push((node_id, operation_ids.clone()));
-                let ask_set = on_batch_received(
-                    operation_ids,
-                    0, /* not needed because it's a mock */
-                    &mut protocols[node_id as usize],
-                );
-                on_ask_received(node_id, ask_set, &mut protocol.lock().unwrap());
-            }
-        }
-        cache
-    })
+/// Run `scenario` on a single thread and a virtual clock, see
+/// [harness::simulate]
+pub fn simulate(scenario: &Scenario) -> ReplayReport {
+    harness::simulate::<FakeProtocol>(scenario)
 }
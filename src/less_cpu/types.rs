@@ -1,34 +1,116 @@
-use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    time::Instant,
-};
+use crate::clock::Clock;
+use crate::memory::{map_memory, operations_memory, set_memory, vec_memory, MemoryUsage};
+use crate::rng::{HashMap, HashSet, SimRng, Stream};
+use crate::scenario::DEFAULT_SEED;
+use crate::timer::TimerHeap;
+use crate::trace::Recorder;
+use std::time::{Duration, Instant};
+
+pub use crate::batch::{AnnounceBuffer, BatchFill, OversizedPolicy};
+pub use crate::known_ops::{KnownOps, KnownOpsRepr, KnownOpsStats};
+pub use crate::operation::{Fee, Operation, OperationFees};
+pub use crate::peers::{PeerInfo, Peers};
+pub use crate::pull::{PullConfig, PullStats, Puller};
+pub use crate::rate_limit::{AskQuota, PeerQuota, ThrottlePolicy, ThrottleStats};
+pub use crate::score::{PeerScore, ScoreConfig, ScoreWeights};
+pub use crate::sharded::{Shard, Sharded};
+pub use crate::validation::OpValidation;
 
 pub type OperationId = u64;
 pub type NodeId = u64;
-pub type Operation = String;
 pub type OperationMap = HashMap<OperationId, Operation>;
 pub type OperationIds = HashSet<OperationId>;
+/// [FakeProtocol] split by operation id, see [crate::sharded]
+pub type ShardedProtocol = Sharded<FakeProtocol>;
 
 /* ****  Following Difer from the algo A **** */
 /// Internal data structure describing the [Operation] we do want from which `NodeId`.
 pub type WantOperations = HashMap<OperationId, (Instant, Vec<NodeId>)>;
 
+/// What [crate::less_cpu::on_batch_received] does with a future set when
+/// [FakeProtocol::op_batch_buffer] is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BufferOverflowPolicy {
+    /// Drop the new future set
+    #[default]
+    DropNewest,
+    /// Drop the oldest entry of the buffer to make room
+    DropOldest,
+    /// Merge the future set in the last entry of the same node, it waits for
+    /// the deadline of that entry. A node without entry gets one over the
+    /// capacity, the buffer holds at most one more entry by node.
+    MergeByNode,
+    /// Like [BufferOverflowPolicy::MergeByNode], but the ids already waiting
+    /// in an entry are not buffered again
+    CoalesceByOpId,
+}
+
+/// What the overflows of [FakeProtocol::op_batch_buffer] did
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BufferStats {
+    /// Entries dropped by [BufferOverflowPolicy::DropNewest] and
+    /// [BufferOverflowPolicy::DropOldest], their ids may never be asked again
+    pub dropped_entries: u64,
+    pub dropped_ids: u64,
+    /// Ids merged in the entry of their node
+    pub merged_ids: u64,
+    /// Ids not buffered because they were already waiting in an entry
+    pub coalesced_ids: u64,
+}
+
 #[derive(Default)]
 pub struct NodeInfo {
-    pub known_op: OperationIds,
+    /// Operations we won't announce to the node
+    pub known_op: KnownOps,
     pub wishlist: OperationIds,
+    /// What the node did for us
+    pub score: PeerScore,
+    /// What the node consumed of its [AskQuota]
+    pub quota: PeerQuota,
+    /// Operations we will announce to the node
+    pub announce: AnnounceBuffer,
+}
+
+impl PeerInfo for NodeInfo {
+    fn known_op(&self) -> &KnownOps {
+        &self.known_op
+    }
+
+    fn known_op_mut(&mut self) -> &mut KnownOps {
+        &mut self.known_op
+    }
+
+    fn score(&self) -> &PeerScore {
+        &self.score
+    }
+
+    fn score_mut(&mut self) -> &mut PeerScore {
+        &mut self.score
+    }
+
+    fn quota_mut(&mut self) -> &mut PeerQuota {
+        &mut self.quota
+    }
+
+    fn fields_memory(&self) -> usize {
+        set_memory(&self.wishlist)
+            + set_memory(&self.quota.deferred)
+            + vec_memory(&self.announce.op_ids)
+    }
 }
 
 pub struct FakeProtocol {
-    pub node_infos: HashMap<NodeId, NodeInfo>,
+    pub node_infos: Peers<NodeInfo>,
     /// Wishlist converted to route to a specific NodeId
     pub wanted_alias_asked_ops: WantOperations,
     /// Map<OperationId, Operation> received!
     pub received: OperationMap,
+    /// Fees announced by the other nodes, used to ask the best operations first
+    pub op_fees: OperationFees,
 
     /* Specific structure for the algorithm */
-    /// Buffer for operations that we want later
-    pub op_batch_buffer: VecDeque<(Instant, NodeId, HashSet<OperationId>)>,
+    /// Buffer for operations that we want later, popped by deadline
+    pub op_batch_buffer: TimerHeap<(NodeId, OperationIds)>,
     /* following should be in a configuration object */
     /// config max_batch_size
     pub max_batch_size: usize,
@@ -36,6 +118,37 @@ pub struct FakeProtocol {
     pub op_batch_proc_period: u64,
     /// config buffer capacity limit [FakeProtocol::op_batch_buffer]
     pub op_batch_buf_capacity: usize,
+    /// config what we do with the future sets when the buffer is full
+    pub buffer_overflow: BufferOverflowPolicy,
+    /// Overflows of the [FakeProtocol::op_batch_buffer]
+    pub buffer_stats: BufferStats,
+
+    /// config how long an announcement can wait for a batch to fill,
+    /// zero to send it right away
+    pub announce_delay: Duration,
+    /// Fill of the announcement batches we sent
+    pub batch_fill: BatchFill,
+    /// config what we do with the received batches bigger than `max_batch_size`
+    pub oversized_policy: OversizedPolicy,
+    /// config verification done on each received operation
+    pub op_validation: OpValidation,
+    /// config limits of the asks of each node, unlimited if None
+    pub ask_quota: Option<AskQuota>,
+    /// Number of asks throttled by the [FakeProtocol::ask_quota]
+    pub throttle_stats: ThrottleStats,
+    /// config scoring of the nodes, no timeouts and no ban if None
+    pub scoring: Option<ScoreConfig>,
+    /// Nodes disconnected because of their score, with their last score
+    pub banned: HashMap<NodeId, PeerScore>,
+    /// config anti-entropy pull and what it brought us
+    pub pull: Puller,
+
+    /// Draws the random decisions of the node, seeded by the scenario
+    pub rng: SimRng,
+    /// Records the events of the node, see [crate::trace]
+    pub recorder: Recorder,
+    /// Time read by the hooks, see [crate::clock]
+    pub clock: Clock,
 
     /// used for measurement, if true, it's the one we measure (default = true)
     pub is_measured: bool,
@@ -48,19 +161,68 @@ impl FakeProtocol {
         op_batch_proc_period: u64,
         op_batch_buf_capacity: usize,
     ) -> Self {
-        let mut node_infos = HashMap::default();
-        for k in 0..nodes_number {
-            node_infos.insert(k as u64, NodeInfo::default());
-        }
+        // the batches are split by `max_batch_size`
+        assert!(max_batch_size > 0, "max_batch_size must be > 0");
         Self {
-            node_infos,
+            node_infos: Peers::new(nodes_number),
             wanted_alias_asked_ops: WantOperations::default(),
             received: OperationMap::default(),
-            op_batch_buffer: VecDeque::default(),
+            op_fees: OperationFees::default(),
+            op_batch_buffer: TimerHeap::default(),
             max_batch_size,
             op_batch_proc_period,
             op_batch_buf_capacity,
+            buffer_overflow: BufferOverflowPolicy::default(),
+            buffer_stats: BufferStats::default(),
+            announce_delay: Duration::ZERO,
+            batch_fill: BatchFill::default(),
+            oversized_policy: OversizedPolicy::default(),
+            op_validation: OpValidation::default(),
+            ask_quota: None,
+            throttle_stats: ThrottleStats::default(),
+            scoring: None,
+            banned: HashMap::default(),
+            pull: Puller::default(),
+            rng: Stream::Protocol.rng(DEFAULT_SEED),
+            recorder: Recorder::default(),
+            clock: Clock::default(),
             is_measured: true,
         }
     }
+
+    /// Approximate heap size of the structures, see [crate::memory]
+    pub fn memory(&self) -> MemoryUsage {
+        MemoryUsage::default()
+            .with("received", operations_memory(&self.received))
+            .with("node_infos", self.node_infos.memory())
+            .with(
+                "wanted_alias_asked_ops",
+                map_memory(&self.wanted_alias_asked_ops)
+                    + self
+                        .wanted_alias_asked_ops
+                        .values()
+                        .map(|(_, node_ids)| vec_memory(node_ids))
+                        .sum::<usize>(),
+            )
+            .with(
+                "op_batch_buffer",
+                self.op_batch_buffer.memory()
+                    + self
+                        .op_batch_buffer
+                        .iter()
+                        .map(|(_, (_, op_ids))| set_memory(op_ids))
+                        .sum::<usize>(),
+            )
+            .with("op_fees", map_memory(&self.op_fees))
+    }
+}
+
+impl Shard for FakeProtocol {
+    /// The node infos and the banned nodes
+    type Peers = (Peers<NodeInfo>, HashMap<NodeId, PeerScore>);
+
+    fn swap_peers(&mut self, peers: &mut Self::Peers) {
+        std::mem::swap(&mut self.node_infos, &mut peers.0);
+        std::mem::swap(&mut self.banned, &mut peers.1);
+    }
 }
@@ -1,2 +1,28 @@
+pub mod adversary;
+pub mod baseline;
+pub mod batch;
+pub mod capture;
+pub mod clock;
+pub mod harness;
+pub mod hooks;
+pub mod hybrid;
+pub mod iblt;
+pub mod known_ops;
 pub mod less_cpu;
+pub mod memory;
+pub mod metrics;
 pub mod more_cpu;
+pub mod operation;
+pub mod peers;
+pub mod pull;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod rng;
+pub mod scenario;
+pub mod score;
+pub mod sharded;
+pub mod short_id;
+pub mod timer;
+pub mod trace;
+pub mod validation;
+pub mod wishlist;
@@ -1,7 +1,321 @@
-mod less_cpu;
-mod more_cpu;
+use bench_sandbox::{
+    baseline::{self, Results, BASELINE_FILE},
+    capture::{self, ImportConfig},
+    hybrid, less_cpu,
+    metrics::{print_degradation, Report},
+    more_cpu, reconcile,
+    scenario::{Scenario, DEFAULT_SEED},
+    trace::{ReplayReport, Trace},
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Measurement of an algorithm with a scenario
+type Process = fn(&Scenario) -> Report;
+
+/// Every algorithm, run with each scenario
+const ALGORITHMS: [(&str, Process); 4] = [
+    ("less_cpu", less_cpu::measurements::process),
+    ("more_cpu", more_cpu::measurements::process),
+    ("hybrid", hybrid::measurements::process),
+    ("reconcile", reconcile::measurements::process),
+];
+
+/// Replay of a trace into an algorithm
+type Replay = fn(&Trace) -> ReplayReport;
+
+const REPLAYS: [(&str, Replay); 4] = [
+    ("less_cpu", less_cpu::measurements::replay),
+    ("more_cpu", more_cpu::measurements::replay),
+    ("hybrid", hybrid::measurements::replay),
+    ("reconcile", reconcile::measurements::replay),
+];
+
+/// Single-threaded run of a scenario on a virtual clock
+type Simulate = fn(&Scenario) -> ReplayReport;
+
+const SIMULATIONS: [(&str, Simulate); 4] = [
+    ("less_cpu", less_cpu::measurements::simulate),
+    ("more_cpu", more_cpu::measurements::simulate),
+    ("hybrid", hybrid::measurements::simulate),
+    ("reconcile", reconcile::measurements::simulate),
+];
+
+/// Runs of each scenario stored by `baseline`
+const RUNS: usize = 5;
+/// A benchmark regresses when its median is worse by more than that
+const THRESHOLD: f64 = 0.1;
+
+/// The scenarios compared to the honest one
+fn degraded_scenarios(seed: u64) -> [Scenario; 6] {
+    [
+        Scenario::adversarial(),
+        Scenario::churn(),
+        Scenario::short_ids(),
+        Scenario::lossy(),
+        Scenario::lossy_pull(),
+        Scenario::bloom(),
+    ]
+    .map(|scenario| scenario.with_seed(seed))
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: bench_sandbox [--seed SEED] [--record DIR]
+       bench_sandbox simulate [--seed SEED] [--record DIR] [--algorithm NAME]
+       bench_sandbox replay TRACE [--algorithm NAME]
+       bench_sandbox import CAPTURE [--output TRACE] [--answer-delay MS] [--tick MS] [--algorithm NAME]
+       bench_sandbox baseline record|check [--runs N] [--bench FILTER] [--against REVISION] [--threshold RATIO] [--seed SEED]
+
+Without argument, run every scenario with every algorithm and compare them,
+with a random seed unless one is given. The same seed draws the same fees,
+orders and losses, the rest of a run depends on the timings of the threads
+(see src/rng.rs), unless it's simulated. With --record, the events of each run are
+written in DIR/ALGORITHM-SCENARIO-SEED.trace.
+  simulate         run every scenario with each algorithm (or only NAME) on a
+                   single thread and a virtual clock: the same seed sends the
+                   same messages, the asks are answered by honest nodes
+  replay           feed the events of a trace to each algorithm (or only NAME)
+                   at their recorded times, and compare what they asked
+  import           convert a capture of the announcements of a real node (see
+                   src/capture.rs) to a trace, write it in TRACE if given and
+                   replay it; each ask is answered MS after it's sent by the
+                   node asked (50 by default), with the operations it
+                   announced, the loops tick every MS (1)
+  baseline record  run the benches and the scenarios, store the results of the
+                   current revision in {}
+  baseline check   run them again and compare with a stored revision (the last
+                   one recorded by default), exit with 1 on regression
+                   the run i of the scenarios uses the seed SEED + i ({} by
+                   default)",
+        BASELINE_FILE, DEFAULT_SEED
+    );
+    std::process::exit(2)
+}
 
 fn main() {
-    less_cpu::measurements::process();
-    more_cpu::measurements::process();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("baseline") => run_baseline(&args[1..]),
+        Some("simulate") => run_simulate(&args[1..]),
+        Some("replay") => run_replay(&args[1..]),
+        Some("import") => run_import(&args[1..]),
+        _ => run_scenarios(&args),
+    }
+}
+
+fn run_scenarios(args: &[String]) {
+    let mut seed = rand::random();
+    let mut record = None;
+    let mut options = args.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().unwrap_or_else(|| usage());
+        match option.as_str() {
+            "--seed" => seed = value.parse().unwrap_or_else(|_| usage()),
+            "--record" => record = Some(PathBuf::from(value)),
+            _ => usage(),
+        }
+    }
+    compare_scenarios(seed, record);
+}
+
+fn compare_scenarios(seed: u64, record: Option<PathBuf>) {
+    println!("Seed: {}", seed);
+    let honest_scenario = Scenario::honest()
+        .with_seed(seed)
+        .with_record(record.clone());
+    let honest: Vec<Report> = ALGORITHMS
+        .iter()
+        .map(|(_, process)| process(&honest_scenario))
+        .collect();
+    let mut degraded = vec![];
+    for scenario in degraded_scenarios(seed) {
+        let scenario = scenario.with_record(record.clone());
+        let reports: Vec<Report> = ALGORITHMS
+            .iter()
+            .map(|(_, process)| process(&scenario))
+            .collect();
+        degraded.push((scenario.name, reports));
+    }
+    for (name, reports) in degraded {
+        println!("=== degradation in the {} scenario ===", name);
+        for (((algorithm, _), reference), report) in ALGORITHMS.iter().zip(&honest).zip(&reports) {
+            print_degradation(algorithm, reference, report);
+        }
+    }
+    // the reconciliation replaces the announcements, compare it to them
+    println!("=== reconcile against the announcements, honest scenario ===");
+    let reconcile = ALGORITHMS
+        .iter()
+        .position(|(algorithm, _)| *algorithm == "reconcile")
+        .map(|index| &honest[index])
+        .expect("reconcile is one of the algorithms");
+    for ((algorithm, _), reference) in ALGORITHMS.iter().zip(&honest) {
+        if *algorithm != "reconcile" {
+            print_degradation(&format!("reconcile / {}", algorithm), reference, reconcile);
+        }
+    }
+}
+
+fn run_simulate(args: &[String]) {
+    let mut seed = rand::random();
+    let mut record = None;
+    let mut algorithm = None;
+    let mut options = args.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().unwrap_or_else(|| usage());
+        match option.as_str() {
+            "--seed" => seed = value.parse().unwrap_or_else(|_| usage()),
+            "--record" => record = Some(PathBuf::from(value)),
+            "--algorithm" => algorithm = Some(value.as_str()),
+            _ => usage(),
+        }
+    }
+    if algorithm.is_some_and(|algorithm| SIMULATIONS.iter().all(|(name, _)| *name != algorithm)) {
+        usage();
+    }
+    println!("Seed: {}", seed);
+    let scenarios =
+        std::iter::once(Scenario::honest().with_seed(seed)).chain(degraded_scenarios(seed));
+    for scenario in scenarios {
+        let scenario = scenario.with_record(record.clone());
+        println!("=== simulation of the {} scenario ===", scenario.name);
+        for (name, simulate) in SIMULATIONS.iter() {
+            if algorithm.is_some_and(|algorithm| algorithm != *name) {
+                continue;
+            }
+            // nothing recorded to compare with
+            simulate(&scenario).print(name, &Trace::default());
+        }
+    }
+}
+
+fn run_replay(args: &[String]) {
+    let path = args.first().unwrap_or_else(|| usage());
+    let algorithm = match &args[1..] {
+        [] => None,
+        [option, name] if option == "--algorithm" => Some(name.as_str()),
+        _ => usage(),
+    };
+    let trace = Trace::read(Path::new(path)).unwrap_or_else(|err| {
+        eprintln!("Cannot read the trace: {}", err);
+        std::process::exit(2)
+    });
+    println!("=== replay of {}, {} events ===", path, trace.events.len());
+    replay_trace(&trace, algorithm);
+}
+
+fn run_import(args: &[String]) {
+    let path = args.first().unwrap_or_else(|| usage());
+    let mut config = ImportConfig::default();
+    let mut output = None;
+    let mut algorithm = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let value = options.next().unwrap_or_else(|| usage());
+        let millis = || Duration::from_millis(value.parse().unwrap_or_else(|_| usage()));
+        match option.as_str() {
+            "--output" => output = Some(PathBuf::from(value)),
+            "--answer-delay" => config.answer_delay = millis(),
+            "--tick" => config.tick = millis(),
+            "--algorithm" => algorithm = Some(value.as_str()),
+            _ => usage(),
+        }
+    }
+    let (trace, capture) = capture::import(Path::new(path), config).unwrap_or_else(|err| {
+        eprintln!("Cannot import the capture: {}", err);
+        std::process::exit(2)
+    });
+    println!("=== capture {} ===", path);
+    capture.print();
+    if let Some(output) = output {
+        trace.write(&output).expect("write the trace");
+        println!("Trace written to {}", output.display());
+    }
+    println!("=== replay of {}, {} events ===", path, trace.events.len());
+    replay_trace(&trace, algorithm);
+}
+
+/// Replay `trace` into each algorithm, or only the one named `algorithm`
+fn replay_trace(trace: &Trace, algorithm: Option<&str>) {
+    let mut replayed = false;
+    for (name, replay) in REPLAYS.iter() {
+        if algorithm.is_some_and(|algorithm| algorithm != *name) {
+            continue;
+        }
+        replay(trace).print(name, trace);
+        replayed = true;
+    }
+    if !replayed {
+        usage();
+    }
+}
+
+fn run_baseline(args: &[String]) {
+    let mut runs = RUNS;
+    let mut filter = String::new();
+    let mut against = None;
+    let mut threshold = THRESHOLD;
+    let mut seed = DEFAULT_SEED;
+    let mut options = args.iter().skip(1);
+    while let Some(option) = options.next() {
+        let value = options.next().unwrap_or_else(|| usage());
+        match option.as_str() {
+            "--runs" => runs = value.parse().unwrap_or_else(|_| usage()),
+            "--bench" => filter = value.clone(),
+            "--against" => against = Some(value.clone()),
+            "--threshold" => threshold = value.parse().unwrap_or_else(|_| usage()),
+            "--seed" => seed = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+    let path = Path::new(BASELINE_FILE);
+    let revision = baseline::revision().expect("git revision");
+    match args.first().map(String::as_str) {
+        Some("record") => {
+            let results = measure(runs, seed, &filter);
+            baseline::store(path, &revision, &results).expect("store the baseline");
+            println!("Results of {} stored in {}", revision, BASELINE_FILE);
+        }
+        Some("check") => {
+            let stored = baseline::load(path).expect("load the baselines");
+            // the last revision recorded, if none is given
+            let base = match against {
+                Some(against) => stored.into_iter().find(|(rev, _)| *rev == against),
+                None => stored.into_iter().last(),
+            };
+            let (base_revision, base) = base.unwrap_or_else(|| {
+                eprintln!("No baseline stored in {}", BASELINE_FILE);
+                std::process::exit(2)
+            });
+            let results = measure(runs, seed, &filter);
+            println!("=== {} against {} ===", revision, base_revision);
+            let regressions = baseline::compare(&base, &results, threshold);
+            if !regressions.is_empty() {
+                println!("{} regressions", regressions.len());
+                std::process::exit(1);
+            }
+        }
+        _ => usage(),
+    }
+}
+
+/// Run the benches, then `runs` times each scenario with each algorithm, the
+/// run `i` with the seed `seed + i`
+fn measure(runs: usize, seed: u64, filter: &str) -> Results {
+    let mut results = Results::default();
+    baseline::run_benches(filter, &mut results).expect("run the benches");
+    for run in 0..runs {
+        let seed = seed.wrapping_add(run as u64);
+        let scenarios = std::iter::once(Scenario::honest().with_seed(seed));
+        for scenario in scenarios.chain(degraded_scenarios(seed)) {
+            for (algorithm, process) in ALGORITHMS.iter() {
+                let report = process(&scenario);
+                baseline::record_report(&mut results, algorithm, scenario.name, &report);
+            }
+        }
+    }
+    results
 }
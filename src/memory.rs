@@ -0,0 +1,105 @@
+//! Approximate heap size of the protocol state. We count the slots of the
+//! collections and what their elements own, not the allocator overhead.
+
+use crate::operation::Operation;
+use crate::rng::{HashMap, HashSet};
+use std::mem::size_of;
+
+/// A slot of a `HashSet` or a `HashMap` is the element and a control byte
+pub fn set_memory<T>(set: &HashSet<T>) -> usize {
+    set.capacity() * (size_of::<T>() + 1)
+}
+
+pub fn map_memory<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+pub fn vec_memory<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+/// The operations and their content
+pub fn operations_memory(operations: &HashMap<u64, Operation>) -> usize {
+    map_memory(operations)
+        + operations
+            .values()
+            .map(|operation| operation.content.capacity())
+            .sum::<usize>()
+}
+
+/// Heap size of each structure of a protocol, in bytes
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUsage {
+    pub structures: Vec<(&'static str, usize)>,
+}
+
+impl MemoryUsage {
+    pub fn with(mut self, name: &'static str, bytes: usize) -> Self {
+        self.structures.push((name, bytes));
+        self
+    }
+
+    pub fn total(&self) -> usize {
+        self.structures.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+/// Memory usage sampled during a run
+#[derive(Clone, Debug, Default)]
+pub struct MemorySamples {
+    samples: Vec<MemoryUsage>,
+}
+
+impl MemorySamples {
+    pub fn push(&mut self, usage: MemoryUsage) {
+        self.samples.push(usage);
+    }
+
+    /// The second half of the run, when the structures stopped growing
+    fn steady_samples(&self) -> &[MemoryUsage] {
+        &self.samples[self.samples.len() / 2..]
+    }
+
+    /// Highest total of the samples
+    pub fn peak(&self) -> usize {
+        self.samples
+            .iter()
+            .map(MemoryUsage::total)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Average total of the second half of the samples
+    pub fn steady(&self) -> usize {
+        let steady = self.steady_samples();
+        steady.iter().map(MemoryUsage::total).sum::<usize>() / steady.len().max(1)
+    }
+
+    /// Print the peak and the steady state of each structure
+    pub fn print(&self) {
+        let names = match self.samples.first() {
+            Some(usage) => usage.structures.iter().map(|(name, _)| *name),
+            None => {
+                println!("Memory: no sample");
+                return;
+            }
+        };
+        println!("Memory ({} samples, peak / steady):", self.samples.len());
+        let steady = self.steady_samples();
+        for (index, name) in names.enumerate() {
+            let peak = self
+                .samples
+                .iter()
+                .map(|usage| usage.structures[index].1)
+                .max()
+                .unwrap_or(0);
+            let average = steady
+                .iter()
+                .map(|usage| usage.structures[index].1)
+                .sum::<usize>()
+                / steady.len().max(1);
+            println!("  {}: {} / {} bytes", name, peak, average);
+        }
+        println!("  total: {} / {} bytes", self.peak(), self.steady());
+    }
+}
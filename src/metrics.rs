@@ -0,0 +1,165 @@
+//! Metrics printed at the end of the measurements, common to every algorithm
+
+use crate::{
+    memory::MemorySamples,
+    operation::{Fee, OperationFees},
+    rng::{HashMap, HashSet},
+};
+use std::time::{Duration, Instant};
+
+/// Number of buckets used to group the operations by fee
+pub const PRIORITY_BUCKETS: usize = 4;
+
+/// Bucket of an operation with a fee in `0..max_fee`, the higher bucket
+/// contains the most prioritary operations. Without fees (`max_fee` of 0)
+/// everything is in the first bucket.
+pub fn priority_bucket(fee: Fee, max_fee: Fee) -> usize {
+    if max_fee == 0 {
+        return 0;
+    }
+    ((fee.min(max_fee - 1) as u128 * PRIORITY_BUCKETS as u128) / max_fee as u128) as usize
+}
+
+/// Print the average time between the first announcement of an operation and
+/// its reception, grouped by priority bucket.
+pub fn print_latency_by_priority(
+    fees: &OperationFees,
+    max_fee: Fee,
+    announced: &HashMap<u64, Instant>,
+    received: &HashMap<u64, Instant>,
+) {
+    let mut buckets = [(Duration::ZERO, 0u32); PRIORITY_BUCKETS];
+    for (op_id, received_at) in received.iter() {
+        let (announced_at, fee) = match (announced.get(op_id), fees.get(op_id)) {
+            (Some(announced_at), Some(fee)) => (announced_at, fee),
+            _ => continue,
+        };
+        let bucket = &mut buckets[priority_bucket(*fee, max_fee)];
+        bucket.0 += received_at.saturating_duration_since(*announced_at);
+        bucket.1 += 1;
+    }
+    println!("Time to receive by priority bucket (low -> high):");
+    for (index, (total, count)) in buckets.iter().enumerate() {
+        if *count == 0 {
+            println!("  bucket {}: no operation", index);
+        } else {
+            println!(
+                "  bucket {}: {:?} on average ({} operations)",
+                index,
+                *total / *count,
+                count
+            );
+        }
+    }
+}
+
+/// Summary of a run, used to compare the scenarios between them
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Number of operations received at the end of the run
+    pub delivered: usize,
+    pub expected: usize,
+    /// Average time between the first announcement of an operation and its
+    /// reception
+    pub latency: Duration,
+    /// Bytes of the batches, asks and operations exchanged by the measured node
+    pub bandwidth: usize,
+    /// Approximate heap size of the state of the measured node, see
+    /// [MemorySamples]
+    pub peak_memory: usize,
+    pub steady_memory: usize,
+    /// Seed of the run, see [crate::rng]
+    pub seed: u64,
+}
+
+impl Report {
+    pub fn new(
+        expected: usize,
+        announced: &HashMap<u64, Instant>,
+        received: &HashMap<u64, Instant>,
+        bandwidth: usize,
+        memory: &MemorySamples,
+    ) -> Self {
+        let mut total = Duration::ZERO;
+        for (op_id, received_at) in received.iter() {
+            if let Some(announced_at) = announced.get(op_id) {
+                total += received_at.saturating_duration_since(*announced_at);
+            }
+        }
+        Self {
+            delivered: received.len(),
+            expected,
+            latency: total / received.len().max(1) as u32,
+            bandwidth,
+            peak_memory: memory.peak(),
+            steady_memory: memory.steady(),
+            seed: 0,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Delivered {}/{} operations, {:?} on average, {} bytes exchanged, {} bytes of memory at peak ({} steady), seed {}",
+            self.delivered,
+            self.expected,
+            self.latency,
+            self.bandwidth,
+            self.peak_memory,
+            self.steady_memory,
+            self.seed
+        );
+    }
+}
+
+/// Print how much `report` is worse than the `reference`
+pub fn print_degradation(name: &str, reference: &Report, report: &Report) {
+    println!(
+        "{}: latency x{:.2}, bandwidth x{:.2}, memory x{:.2}, {} operations lost",
+        name,
+        report.latency.as_secs_f64() / reference.latency.as_secs_f64().max(f64::EPSILON),
+        report.bandwidth as f64 / reference.bandwidth.max(1) as f64,
+        report.peak_memory as f64 / reference.peak_memory.max(1) as f64,
+        report.expected.saturating_sub(report.delivered)
+    );
+}
+
+/// Print what happened to the operations asked to nodes that left before
+/// answering
+pub fn print_churn_impact(
+    lost_in_flight: &HashSet<u64>,
+    announced: &HashMap<u64, Instant>,
+    received: &HashMap<u64, Instant>,
+) {
+    let mut delayed = Duration::ZERO;
+    let mut delivered = 0u32;
+    for op_id in lost_in_flight.iter() {
+        if let (Some(announced_at), Some(received_at)) = (announced.get(op_id), received.get(op_id))
+        {
+            delayed += received_at.saturating_duration_since(*announced_at);
+            delivered += 1;
+        }
+    }
+    println!(
+        "Churn: {} operations asked to leaving nodes, {} delivered later ({:?} on average), {} lost",
+        lost_in_flight.len(),
+        delivered,
+        delayed / delivered.max(1),
+        lost_in_flight.len() - delivered as usize
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_buckets() {
+        assert_eq!(priority_bucket(0, 1_000), 0);
+        assert_eq!(priority_bucket(999, 1_000), PRIORITY_BUCKETS - 1);
+        // over the maximum, in the last bucket
+        assert_eq!(priority_bucket(5_000, 1_000), PRIORITY_BUCKETS - 1);
+        assert_eq!(priority_bucket(Fee::MAX, Fee::MAX), PRIORITY_BUCKETS - 1);
+        assert_eq!(priority_bucket(0, 0), 0);
+        assert_eq!(priority_bucket(10, 0), 0);
+    }
+}
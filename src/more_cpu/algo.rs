@@ -1,4 +1,8 @@
 use super::types::*;
+use crate::batch::limit_batch;
+use crate::operation::by_priority;
+use crate::trace::{Event, Recorder};
+use std::cmp::Reverse;
 
 /***************************************************************************************** */
 /* Things that must be in the both algorithms                                              */
@@ -18,14 +22,23 @@ fn send_batch(_to_node_id: NodeId, _batch: OperationIds) {
         .push((_to_node_id, _batch));
 }
 
-fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds) {
+fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds, _recorder: &Recorder) {
     //#[cfg(feature = "measurements")]
+    _recorder.record(|| Event::AskSent(_to_node_id, _op_ids.clone()));
     super::ASK_BATCH_QUEUE
         .lock()
         .unwrap()
         .push((_to_node_id, _op_ids));
 }
 
+fn ask_digest(_to_node_id: NodeId) {
+    //#[cfg(feature = "measurements")]
+    super::DIGEST_ASK_QUEUE
+        .lock()
+        .unwrap()
+        .push_back(_to_node_id);
+}
+
 fn send_operations(_to_node_id: NodeId, _op_ids: AskedOperations) {
     // difer from the other algo
     //#[cfg(feature = "measurements")]
@@ -40,21 +53,29 @@ pub fn on_batch_received(
     node_id: NodeId,
     protocol: &mut FakeProtocol, /* self simulation */
 ) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    if op_batch.len() > protocol.max_batch_size {
+        protocol.node_infos.connect(node_id).score.oversized += 1;
+        if ban_if_needed(node_id, protocol) {
+            return;
+        }
+    }
+    let op_batch = match limit_batch(
+        op_batch,
+        protocol.max_batch_size,
+        protocol.oversized_policy,
+        &protocol.op_fees,
+    ) {
+        Some(op_batch) => op_batch,
+        None => return,
+    };
     for op_id in op_batch {
         if protocol.received.contains_key(&op_id) {
             continue;
         }
-        let node_info_opt = protocol.node_infos.get_mut(&node_id);
-        match node_info_opt {
-            Some(node_info) => {
-                node_info.known_op.insert(op_id);
-            }
-            None => {
-                let mut info = NodeInfo::default();
-                info.known_op.insert(op_id);
-                protocol.node_infos.insert(node_id, info);
-            }
-        }
+        protocol.node_infos.connect(node_id).known_op.insert(op_id);
         protocol.wishlist.insert(op_id);
     }
 }
@@ -63,7 +84,22 @@ pub fn on_batch_received(
 /// qu'on a dans la wishlist. Ajoute dans une structure
 /// `wanted` ce dont on a besoin.
 pub fn on_asking_loop(protocol: &mut FakeProtocol /* self simulation */) {
-    for op_id in protocol.wishlist.iter() {
+    if let Some(scoring) = protocol.scoring {
+        check_ask_timeouts(scoring, protocol);
+    }
+    pull_if_needed(protocol);
+    // the nodes with the best score are asked first
+    let mut node_ids: Vec<NodeId> = protocol.node_infos.keys().cloned().collect();
+    let weights = crate::score::weights(protocol.scoring);
+    node_ids.sort_unstable_by_key(|node_id| {
+        (
+            Reverse(protocol.node_infos[node_id].score.value(&weights)),
+            *node_id,
+        )
+    });
+    let now = protocol.clock.now();
+    // the most prioritary operations fill the `wanted` sets first
+    for op_id in by_priority(protocol.wishlist.iter().cloned(), &protocol.op_fees).iter() {
         if protocol.already_asked.contains(op_id) {
             // insert logic of retry after a while here
             continue;
@@ -72,17 +108,20 @@ pub fn on_asking_loop(protocol: &mut FakeProtocol /* self simulation */) {
             continue;
         }
         // Choose a node that know the operation. Can evolve.
-        for (node_id, node_info) in protocol.node_infos.iter() {
+        for node_id in node_ids.iter() {
+            let node_info = protocol.node_infos.get_mut(node_id).unwrap();
             if node_info.known_op.contains(op_id) {
                 match protocol.wanted.get_mut(node_id) {
                     Some(op_ids) => {
                         if op_ids.len() < protocol.max_batch_size {
                             protocol.already_asked.insert(*op_id); // Should I limit the wanted object?
                             op_ids.insert(*op_id);
+                            node_info.asked_since.get_or_insert(now);
                             break;
                         }
                     }
                     None => {
+                        node_info.asked_since.get_or_insert(now);
                         let mut set = OperationIds::default();
                         set.insert(*op_id);
                         protocol.wanted.insert(*node_id, set); // Also here, should I limit the wanted object?
@@ -92,10 +131,75 @@ pub fn on_asking_loop(protocol: &mut FakeProtocol /* self simulation */) {
         }
     }
     for (node_id, wanted) in protocol.wanted.iter() {
-        ask_operations(*node_id, wanted.clone());
+        ask_operations(*node_id, wanted.clone(), &protocol.recorder);
     }
 }
 
+/// The nodes that didn't deliver anything of what we asked them since
+/// `ask_timeout` are penalized, and we will ask their operations to other
+/// nodes.
+fn check_ask_timeouts(
+    scoring: ScoreConfig,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    let now = protocol.clock.now();
+    let timed_out: Vec<NodeId> = protocol
+        .node_infos
+        .iter()
+        .filter(|(_, node_info)| match node_info.asked_since {
+            Some(asked_since) => now > asked_since + scoring.ask_timeout,
+            None => false,
+        })
+        .map(|(node_id, _)| *node_id)
+        .collect();
+    for node_id in timed_out {
+        let wanted = protocol.wanted.remove(&node_id).unwrap_or_default();
+        for op_id in wanted.iter() {
+            protocol.already_asked.remove(op_id);
+        }
+        let node_info = protocol.node_infos.get_mut(&node_id).unwrap();
+        node_info.asked_since = None;
+        // the whole ask counts for one timeout, the node may just be slow
+        node_info.score.timeouts += 1;
+        ban_if_needed(node_id, protocol);
+    }
+}
+
+/// Disconnect the node if its score is under the threshold, return true if
+/// the node is banned.
+fn ban_if_needed(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) -> bool {
+    let scoring = match protocol.scoring {
+        Some(scoring) => scoring,
+        None => return false,
+    };
+    match protocol.node_infos.get(&node_id) {
+        Some(node_info) if node_info.score.value(&scoring.weights) < scoring.ban_threshold => {
+            let node_info = protocol.node_infos.remove(&node_id).unwrap();
+            protocol.banned.insert(node_id, node_info.score);
+            // what we asked to that node has to be asked to another one
+            for op_id in protocol.wanted.remove(&node_id).unwrap_or_default() {
+                protocol.already_asked.remove(&op_id);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Fees are announced with the batches, in the simulation the sender
+/// call this function before [on_batch_received]. The fee of an operation is
+/// kept until we receive it or stop wanting it.
+pub fn on_fees_received(
+    fees: OperationFees,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    let received = &protocol.received;
+    protocol.op_fees.extend(
+        fees.into_iter()
+            .filter(|(op_id, _)| !received.contains_key(op_id)),
+    );
+}
+
 /*  for a potential limitation of the wanted object I can remove the one that has
    always less op_id than the others.
 */
@@ -106,12 +210,31 @@ pub fn on_operation_received(
     asked_operation: AskedOperations,
     protocol: &mut FakeProtocol, /* self simulation */
 ) {
+    if protocol.banned.contains_key(&from_node_id) {
+        return;
+    }
+    // the node doesn't have what it announced
+    let missing: Vec<OperationId> = asked_operation
+        .iter()
+        .filter(|(_, opt)| opt.is_none())
+        .map(|(op_id, _)| *op_id)
+        .collect();
+    let validation = protocol.op_validation;
+    let mut invalid = vec![];
     let op_ids: OperationIds = asked_operation
         .iter()
-        .filter(|(_, opt)| opt.is_some())
+        .filter_map(|(op_id, opt)| opt.as_ref().map(|operation| (op_id, operation)))
+        .filter(|(op_id, operation)| {
+            let valid = crate::validation::validate(**op_id, &operation.content, validation);
+            if !valid {
+                invalid.push(**op_id);
+            }
+            valid
+        })
         .map(|(op_id, operation)| {
-            protocol.received.insert(*op_id, operation.clone().unwrap());
+            protocol.received.insert(*op_id, operation.clone());
             protocol.wishlist.remove(op_id);
+            protocol.op_fees.remove(op_id);
             for (_, list) in protocol.wanted.iter_mut() {
                 list.remove(op_id);
             }
@@ -121,14 +244,46 @@ pub fn on_operation_received(
         .collect();
     if let Some(info) = protocol.node_infos.get_mut(&from_node_id) {
         info.known_op.extend(op_ids.clone());
+        info.score.delivered += op_ids.len() as u64;
+        // the node sent us garbage, remember it
+        info.score.invalid += invalid.len() as u64;
+        // the whole answer counts for one timeout, like a whole ask in
+        // `check_ask_timeouts`
+        info.score.timeouts += !missing.is_empty() as u64;
+        for op_id in missing.iter() {
+            info.known_op.remove(op_id);
+        }
+    }
+    // rejected operations can be asked again, maybe to another node
+    for op_id in invalid.iter().chain(missing.iter()) {
+        protocol.already_asked.remove(op_id);
+        if let Some(wanted) = protocol.wanted.get_mut(&from_node_id) {
+            wanted.remove(op_id);
+        }
+    }
+    if let Some(info) = protocol.node_infos.get_mut(&from_node_id) {
+        // the node answered, we wait again for the remaining asks
+        info.asked_since = match protocol.wanted.get(&from_node_id) {
+            Some(wanted) if !wanted.is_empty() => Some(protocol.clock.now()),
+            _ => None,
+        };
     }
+    ban_if_needed(from_node_id, protocol);
+    let now = protocol.clock.now();
     for (node_id, node_info) in protocol.node_infos.iter_mut() {
-        let batch: OperationIds = op_ids
+        let op_ids = op_ids
             .iter()
             .filter(|&&op_id| node_info.known_op.insert(op_id))
-            .cloned()
-            .collect();
-        send_batch(*node_id, batch);
+            .cloned();
+        for batch in node_info.announce.push(
+            op_ids,
+            protocol.max_batch_size,
+            protocol.announce_delay,
+            now,
+        ) {
+            protocol.batch_fill.record(&batch);
+            send_batch(*node_id, batch);
+        }
     }
 }
 
@@ -140,35 +295,154 @@ pub fn on_ask_received(
     op_ids: OperationIds,
     protocol: &mut FakeProtocol, /* self simulation */
 ) {
-    match protocol.node_infos.get_mut(&node_id) {
-        Some(info) => info.wishlist.extend(op_ids),
-        None => {
-            // Is there a node info limiation?
-            let mut info = NodeInfo::default();
-            info.wishlist.extend(op_ids);
-            protocol.node_infos.insert(node_id, info);
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    // Is there a node info limiation?
+    let info = protocol.node_infos.connect(node_id);
+    let received = &protocol.received;
+    match protocol.ask_quota {
+        Some(quota) => {
+            let admitted = info.quota.admit_ids(
+                op_ids,
+                |op_id| received.contains_key(&op_id),
+                &quota,
+                &mut protocol.throttle_stats,
+                protocol.clock.now(),
+            );
+            info.wishlist.extend(admitted)
+        }
+        None => info.wishlist.extend(op_ids),
+    }
+}
+
+/// Ask a digest of its recent operations to a random node, each pull period
+fn pull_if_needed(protocol: &mut FakeProtocol /* self simulation */) {
+    let node_ids = protocol.node_infos.keys().cloned();
+    if let Some(node_id) = protocol
+        .pull
+        .due_peer(node_ids, &mut protocol.rng, protocol.clock.now())
+    {
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            ask_digest(node_id);
         }
     }
 }
 
+/// `node_id` answered our pull with the ids it received recently, the ones
+/// we miss are processed like an announcement of that node.
+pub fn on_digest_received(
+    node_id: NodeId,
+    digest: OperationIds,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let received = &protocol.received;
+    let batches = protocol.pull.missing(
+        digest,
+        |op_id| received.contains_key(op_id),
+        &protocol.op_fees,
+        protocol.max_batch_size,
+    );
+    for batch in batches {
+        on_batch_received(batch, node_id, protocol);
+    }
+}
+
+/// A node connected to us, we announce it all the operations we have
+pub fn on_node_connected(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let node_info = protocol.node_infos.connect(node_id);
+    let op_ids = protocol
+        .received
+        .keys()
+        .filter(|&&op_id| node_info.known_op.insert(op_id))
+        .cloned();
+    for batch in node_info.announce.push(
+        op_ids,
+        protocol.max_batch_size,
+        protocol.announce_delay,
+        protocol.clock.now(),
+    ) {
+        protocol.batch_fill.record(&batch);
+        send_batch(node_id, batch);
+    }
+}
+
+/// A node left, forget what it knows and what it asked us. What we asked
+/// to it will be asked to another node by the next [on_asking_loop].
+pub fn on_node_disconnected(
+    node_id: NodeId,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    protocol.node_infos.remove(&node_id);
+    for op_id in protocol.wanted.remove(&node_id).unwrap_or_default() {
+        protocol.already_asked.remove(&op_id);
+    }
+}
+
 /// Call on `send` timer?
 pub fn on_send_operation_loop(protocol: &mut FakeProtocol) {
+    let now = protocol.clock.now();
     for (node_id, node_info) in protocol.node_infos.iter_mut() {
+        // the announcements that waited enough for their batch to fill
+        if let Some(batch) = node_info.announce.take_due(protocol.announce_delay, now) {
+            protocol.batch_fill.record(&batch);
+            send_batch(*node_id, batch);
+        }
+        if let Some(quota) = protocol.ask_quota {
+            // the deferred asks go through the quota again
+            let deferred = node_info.quota.take_deferred(&quota, now);
+            let received = &protocol.received;
+            let admitted = node_info.quota.admit_ids(
+                deferred,
+                |op_id| received.contains_key(&op_id),
+                &quota,
+                &mut protocol.throttle_stats,
+                now,
+            );
+            node_info.wishlist.extend(admitted);
+        }
         let mut asked = AskedOperations::default();
-        node_info
-            .wishlist
-            .iter()
-            .cloned()
-            .take(protocol.max_batch_size)
-            .for_each(|op_id| {
-                match protocol.received.get(&op_id) {
-                    Some(operation) => asked.insert(op_id, Some(operation.clone())),
-                    None => asked.insert(op_id, None),
-                };
-            });
-        asked.iter().for_each(|(id, _)| {
-            node_info.wishlist.remove(id);
+        // serve the most prioritary operations first
+        let mut wishlist: Vec<OperationId> = node_info.wishlist.iter().cloned().collect();
+        wishlist.sort_unstable_by_key(|op_id| {
+            (
+                Reverse(protocol.received.get(op_id).map(|op| op.fee)),
+                *op_id,
+            )
         });
-        send_operations(*node_id, asked);
+        for op_id in wishlist.into_iter().take(protocol.max_batch_size) {
+            // served, dropped or deferred, it leaves the wishlist
+            node_info.wishlist.remove(&op_id);
+            match protocol.received.get(&op_id) {
+                Some(operation) => {
+                    let allowed = match protocol.ask_quota {
+                        Some(quota) => node_info.quota.admit_bytes(
+                            op_id,
+                            operation.size(),
+                            &quota,
+                            &mut protocol.throttle_stats,
+                            now,
+                        ),
+                        None => true,
+                    };
+                    if allowed {
+                        asked.insert(op_id, Some(operation.clone()));
+                    }
+                }
+                None => {
+                    asked.insert(op_id, None);
+                }
+            };
+        }
+        if !asked.is_empty() {
+            send_operations(*node_id, asked);
+        }
     }
 }
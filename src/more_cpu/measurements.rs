@@ -1,155 +1,161 @@
 use super::*;
-use rand::{seq::SliceRandom, Rng};
-use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
+use crate::{
+    clock::Clock,
+    harness::{
+        self, Algorithm, Answer, ANNOUNCE_DELAY, ASK_QUOTA, MAX_BATCH_SIZE, OP_VALIDATION, SCORING,
+        T,
     },
-    thread::JoinHandle,
-    time::Duration,
+    memory::MemoryUsage,
+    metrics::Report,
+    rng::{SimRng, Stream},
+    scenario::Scenario,
+    trace::{Recorder, ReplayReport, Trace},
 };
+use rand::{seq::SliceRandom, Rng};
+use std::time::Duration;
 
-const MAX_BATCH_SIZE: usize = 100;
-const T: usize = 25; // Numer of nodes
-const N: usize = 10_000; // Number of operations
-                         // for this test we need to have the same buffer size as the input
-                         // operations number
+impl Algorithm for FakeProtocol {
+    const NAME: &'static str = "more_cpu";
+    const ASKING_LOOP: bool = true;
+    const ANSWERS_MISSING: bool = true;
 
-const _: () = {
-    if T == 0 {
-        panic!("We need a number of nodes > 0");
+    fn new_protocol() -> Self {
+        let mut protocol = FakeProtocol::new(T, MAX_BATCH_SIZE);
+        protocol.announce_delay = ANNOUNCE_DELAY;
+        protocol.op_validation = OP_VALIDATION;
+        protocol.ask_quota = Some(ASK_QUOTA);
+        protocol.scoring = Some(SCORING);
+        protocol
     }
-    if N % MAX_BATCH_SIZE != 0 {
-        panic!("For the test N should be a multiple of MAX_BATCH_SIZE");
+
+    fn prepare(&mut self, scenario: &Scenario, recorder: Recorder) {
+        self.pull = Puller::new(scenario.pull);
+        self.rng = scenario.rng(Stream::Protocol);
+        self.recorder = recorder;
+        self.node_infos.set_known_ops(scenario.known_ops);
     }
-};
 
-fn print_output(measures: Vec<WantOperations>) {
-    let mut ids = OperationIds::new();
-    let mut nodes = vec![0; T + 1];
-    for wanted in measures.iter() {
-        for (node_id, operation_ids) in wanted.iter() {
-            *nodes.get_mut(*node_id as usize).unwrap() += 1;
-            ids.extend(operation_ids.clone());
-        }
-    }
-    println!("Asking table by nodes:\n{:?}", nodes);
-    println!("Correctly processed: {}", ids.len() == N);
-    assert_eq!(ids.len(), N);
-}
+    fn set_measured(&mut self, is_measured: bool) {
+        self.is_measured = is_measured;
+    }
 
-pub fn process() {
-    let protocol = Arc::new(Mutex::new(new_protocol()));
-    let sig_stop = Arc::new(AtomicBool::new(true));
-    let batch_sender = run_batch_sender(protocol.clone());
-    let asking_loop = run_asking_loop(protocol.clone(), sig_stop.clone());
-    let op_sender = run_operations_asked_receiver(protocol);
-    batch_sender.join().unwrap();
-    sig_stop.store(false, Ordering::Relaxed);
-    let operations_asked = asking_loop.join().unwrap();
-    op_sender.join().unwrap();
-    print_output(operations_asked);
-}
+    fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    fn received(&self) -> &OperationMap {
+        &self.received
+    }
+
+    fn memory(&self) -> MemoryUsage {
+        FakeProtocol::memory(self)
+    }
+
+    fn print_stats(&self, scenario: &Scenario) {
+        harness::print_peers(
+            scenario,
+            &self.node_infos,
+            &self.banned,
+            &self.throttle_stats,
+            &self.pull,
+        );
+        self.batch_fill.print(MAX_BATCH_SIZE);
+    }
+
+    fn clear_queues() {
+        ASK_BATCH_QUEUE.lock().unwrap().clear();
+        BATCH_SEND_QUEUE.lock().unwrap().clear();
+        SEND_OPERATION.lock().unwrap().clear();
+        DIGEST_ASK_QUEUE.lock().unwrap().clear();
+    }
+
+    fn pop_ask(rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        let mut batches = ASK_BATCH_QUEUE.lock().unwrap();
+        batches.shuffle(rng);
+        batches.pop()
+    }
+
+    fn drain_asks() -> Vec<(NodeId, OperationIds)> {
+        ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+    }
+
+    fn pop_announce(rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        let mut guard = BATCH_SEND_QUEUE.lock().unwrap();
+        guard.shuffle(rng);
+        guard.pop()
+    }
 
-fn new_protocol() -> FakeProtocol {
-    FakeProtocol::new(T, MAX_BATCH_SIZE)
+    fn pop_pull() -> Option<NodeId> {
+        DIGEST_ASK_QUEUE.lock().unwrap().pop_front()
+    }
+
+    fn answer_delay(rng: &mut SimRng) -> Duration {
+        const MIN_SLEEP: u64 = 1;
+        const MAX_SLEEP: u64 = 2;
+        Duration::from_millis(rng.gen_range(MIN_SLEEP..=MAX_SLEEP))
+    }
+
+    fn on_node_connected(&mut self, node_id: NodeId) {
+        on_node_connected(node_id, self);
+    }
+
+    fn on_node_disconnected(&mut self, node_id: NodeId) {
+        on_node_disconnected(node_id, self);
+    }
+
+    fn on_fees_received(&mut self, fees: OperationFees) {
+        on_fees_received(fees, self);
+    }
+
+    /// The asks are sent by the asking loop, never right away
+    fn on_batch_received(&mut self, node_id: NodeId, op_ids: OperationIds) -> OperationIds {
+        on_batch_received(op_ids, node_id, self);
+        OperationIds::default()
+    }
+
+    fn on_operations(&mut self, node_id: NodeId, answer: Answer) {
+        on_operation_received(node_id, answer, self);
+    }
+
+    fn on_ask_received(&mut self, node_id: NodeId, op_ids: OperationIds) {
+        on_ask_received(node_id, op_ids, self);
+    }
+
+    fn on_digest_received(&mut self, node_id: NodeId, digest: OperationIds) {
+        on_digest_received(node_id, digest, self);
+    }
+
+    fn on_send_tick(&mut self) {
+        on_send_operation_loop(self);
+    }
+
+    fn on_asking_tick(&mut self) {
+        on_asking_loop(self);
+    }
+
+    /// Les autres noeuds recoivent nos batches et nous demandent en retour
+    /// les operations qu'ils n'ont pas. Ici ils demandent tout ce qu'on annonce.
+    fn remote_asks(_remote: &mut Self, op_ids: OperationIds) -> OperationIds {
+        op_ids
+    }
 }
 
-fn run_batch_sender(protocol: Arc<Mutex<FakeProtocol>>) -> JoinHandle<()> {
-    //const MIN_SLEEP: u64 = 30;
-    //const MAX_SLEEP: u64 = 60;
-    std::thread::spawn(move || {
-        // Chaque noeud va envoyer les mêmes operations mais dans un ordre different
-        // N batches de MAX_BATCH_SIZE operations
-        // Reminder: on a T noeuds
-        let mut orders = Box::new([[0; N + 1]; T + 1]); // Order of operations for each node
-
-        // On conserve les operations ici
-        let mut operations = Box::new([0usize; N + 1]); // Table of operations
-        for n in 0..N {
-            for order in orders.iter_mut() {
-                order[n] = n; // init an order to shuffle later
-            }
-            operations[n] = rand::random(); // Generation de l'identifiant de l'operation
-        }
-        let mut thread_rng = rand::thread_rng();
-
-        // Voilà, ici chaque noeud definis dans quel ordre il va envoyer les opérations
-        // ... Puisque c'est possible et que c'est probablement le pire
-        // cas, on randomize l'ordre d'envoie des operations. On créé le batch après -->*ici
-        for order in orders.iter_mut() {
-            order.shuffle(&mut thread_rng);
-        }
-        let mut p = 0;
-
-        while p < N {
-            for (node_id, order) in orders.iter().enumerate() {
-                //let rand_sleep = Duration::from_nanos(thread_rng.gen_range(MIN_SLEEP..MAX_SLEEP));
-                //std::thread::sleep(rand_sleep);
-                let mut batch = OperationIds::default(); // *ici <--
-                for &op_id in order.iter().skip(p).take(MAX_BATCH_SIZE) {
-                    batch.insert(op_id as u64);
-                }
-                let mut guard = protocol.lock().unwrap();
-                on_batch_received(batch, node_id as u64, &mut guard);
-            }
-            // tant qu'on a pas envoyé N operations, on continue
-            p += MAX_BATCH_SIZE;
-        }
-    })
+pub fn process(scenario: &Scenario) -> Report {
+    harness::process::<FakeProtocol>(scenario)
 }
 
-/// Ici on s'occupe de lancer et relancer la boucle de demande.
-/// On va aller beaucoup plus vite que dans les cas reelles mais
-/// bon... les noeuds distants aussi iront très très vite!
-///
-/// On guard en cash ce qu'on demande
-fn run_asking_loop(
-    protocol: Arc<Mutex<FakeProtocol>>,
-    stop: Arc<AtomicBool>,
-) -> JoinHandle<Vec<WantOperations>> {
-    const MIN_SLEEP: u64 = 20;
-    const MAX_SLEEP: u64 = 100;
-    std::thread::spawn(move || {
-        let mut thread_rng = rand::thread_rng();
-        let mut cache = vec![];
-        while stop.load(Ordering::Relaxed) {
-            let rand_sleep = Duration::from_nanos(thread_rng.gen_range(MIN_SLEEP..MAX_SLEEP));
-            std::thread::sleep(rand_sleep);
-            let mut guard = protocol.lock().unwrap();
-            on_asking_loop(&mut guard);
-            cache.push(guard.wanted.clone());
-        }
-        cache
-    })
+/// Feed the inputs of `trace` to the hooks of a new measured node, see
+/// [harness::replay]
+pub fn replay(trace: &Trace) -> ReplayReport {
+    harness::replay::<FakeProtocol>(trace)
 }
 
-fn run_operations_asked_receiver(
-    protocol: Arc<Mutex<FakeProtocol>>,
-) -> JoinHandle<Vec<(NodeId, OperationIds)>> {
-    const MIN_SLEEP: u64 = 1;
-    const MAX_SLEEP: u64 = 2;
-    std::thread::spawn(move || {
-        let mut cache = vec![];
-        let mut diff_op = OperationIds::new();
-        let mut thread_rng = rand::thread_rng();
-        while diff_op.len() < N {
-            let rand_sleep = Duration::from_millis(thread_rng.gen_range(MIN_SLEEP..=MAX_SLEEP));
-            std::thread::sleep(rand_sleep);
-            let mut batches = ASK_BATCH_QUEUE.lock().unwrap();
-            batches.shuffle(&mut thread_rng);
-            let opt_asked = batches.pop();
-            std::mem::drop(batches);
-            if let Some((node_id, operation_ids)) = opt_asked {
-                cache.push((node_id, operation_ids.clone()));
-                diff_op.extend(operation_ids.clone());
-                let mut operations = AskedOperations::new();
-                for id in operation_ids {
-                    operations.insert(id, Some(String::new()));
-                }
-                on_operation_received(node_id, operations, &mut protocol.lock().unwrap());
-            }
-        }
-        cache
-    })
+/// Run `scenario` on a single thread and a virtual clock, see
+/// [harness::simulate]
+pub fn simulate(scenario: &Scenario) -> ReplayReport {
+    harness::simulate::<FakeProtocol>(scenario)
 }
@@ -14,4 +14,5 @@ lazy_static::lazy_static! {
     pub static ref ASK_BATCH_QUEUE: Arc<Mutex<Vec<(NodeId, OperationIds)>>> = Default::default();
     pub static ref BATCH_SEND_QUEUE: Arc<Mutex<Vec<(NodeId, OperationIds)>>> = Default::default();
     pub static ref SEND_OPERATION: Arc<Mutex<VecDeque<(NodeId, AskedOperations)>>> = Default::default();
+    pub static ref DIGEST_ASK_QUEUE: Arc<Mutex<VecDeque<NodeId>>> = Default::default();
 }
@@ -1,24 +1,77 @@
-use std::collections::{HashMap, HashSet};
+use crate::clock::Clock;
+use crate::memory::{map_memory, operations_memory, set_memory, vec_memory, MemoryUsage};
+use crate::rng::{HashMap, HashSet, SimRng, Stream};
+use crate::scenario::DEFAULT_SEED;
+use crate::trace::Recorder;
+use std::time::{Duration, Instant};
+
+pub use crate::batch::{AnnounceBuffer, BatchFill, OversizedPolicy};
+pub use crate::known_ops::{KnownOps, KnownOpsRepr, KnownOpsStats};
+pub use crate::operation::{Fee, Operation, OperationFees};
+pub use crate::peers::{PeerInfo, Peers};
+pub use crate::pull::{PullConfig, PullStats, Puller};
+pub use crate::rate_limit::{AskQuota, PeerQuota, ThrottlePolicy, ThrottleStats};
+pub use crate::score::{PeerScore, ScoreConfig, ScoreWeights};
+pub use crate::sharded::{Shard, Sharded};
+pub use crate::validation::OpValidation;
 
 pub type OperationId = u64;
 pub type NodeId = u64;
-pub type Operation = String;
 pub type OperationMap = HashMap<OperationId, Operation>;
 pub type OperationIds = HashSet<OperationId>;
+/// [FakeProtocol] split by operation id, see [crate::sharded]
+pub type ShardedProtocol = Sharded<FakeProtocol>;
 
-pub type AskedOperations = std::collections::HashMap<OperationId, Option<Operation>>;
+pub type AskedOperations = HashMap<OperationId, Option<Operation>>;
 /// Internal data structure describing the [Operation] we do want from which `NodeId`.
-pub type WantOperations = std::collections::HashMap<NodeId, HashSet<OperationId>>;
+pub type WantOperations = HashMap<NodeId, HashSet<OperationId>>;
 
 #[derive(Default)]
 pub struct NodeInfo {
-    pub known_op: OperationIds,
+    /// Operations we won't announce to the node
+    pub known_op: KnownOps,
     pub wishlist: OperationIds,
+    /// What the node did for us
+    pub score: PeerScore,
+    /// Since when we wait for the operations asked to that node
+    pub asked_since: Option<Instant>,
+    /// What the node consumed of its [AskQuota]
+    pub quota: PeerQuota,
+    /// Operations we will announce to the node
+    pub announce: AnnounceBuffer,
+}
+
+impl PeerInfo for NodeInfo {
+    fn known_op(&self) -> &KnownOps {
+        &self.known_op
+    }
+
+    fn known_op_mut(&mut self) -> &mut KnownOps {
+        &mut self.known_op
+    }
+
+    fn score(&self) -> &PeerScore {
+        &self.score
+    }
+
+    fn score_mut(&mut self) -> &mut PeerScore {
+        &mut self.score
+    }
+
+    fn quota_mut(&mut self) -> &mut PeerQuota {
+        &mut self.quota
+    }
+
+    fn fields_memory(&self) -> usize {
+        set_memory(&self.wishlist)
+            + set_memory(&self.quota.deferred)
+            + vec_memory(&self.announce.op_ids)
+    }
 }
 
 pub struct FakeProtocol {
     /// Remember that nodes know and have
-    pub node_infos: HashMap<NodeId, NodeInfo>,
+    pub node_infos: Peers<NodeInfo>,
     /// list of operation that the node wish
     pub wishlist: OperationIds,
     /// Wishlist converted to route to a specific NodeId
@@ -27,28 +80,92 @@ pub struct FakeProtocol {
     pub already_asked: OperationIds,
     /// Map<OperationId, Operation> received!
     pub received: OperationMap,
+    /// Fees announced by the other nodes, used to ask the best operations first
+    pub op_fees: OperationFees,
 
     /// config maximum size of a batch (number of operations)
     pub max_batch_size: usize,
 
+    /// config how long an announcement can wait for a batch to fill,
+    /// zero to send it right away
+    pub announce_delay: Duration,
+    /// Fill of the announcement batches we sent
+    pub batch_fill: BatchFill,
+    /// config what we do with the received batches bigger than `max_batch_size`
+    pub oversized_policy: OversizedPolicy,
+    /// config verification done on each received operation
+    pub op_validation: OpValidation,
+    /// config limits of the asks of each node, unlimited if None
+    pub ask_quota: Option<AskQuota>,
+    /// Number of asks throttled by the [FakeProtocol::ask_quota]
+    pub throttle_stats: ThrottleStats,
+    /// config scoring of the nodes, no timeouts and no ban if None
+    pub scoring: Option<ScoreConfig>,
+    /// Nodes disconnected because of their score, with their last score
+    pub banned: HashMap<NodeId, PeerScore>,
+    /// config anti-entropy pull and what it brought us
+    pub pull: Puller,
+
+    /// Draws the random decisions of the node, seeded by the scenario
+    pub rng: SimRng,
+    /// Records the events of the node, see [crate::trace]
+    pub recorder: Recorder,
+    /// Time read by the hooks, see [crate::clock]
+    pub clock: Clock,
+
     /// used for measurement, if true, it's the one we measure (default = true)
     pub is_measured: bool,
 }
 
 impl FakeProtocol {
     pub fn new(nodes_number: usize, max_batch_size: usize) -> Self {
-        let mut node_infos = HashMap::default();
-        for k in 0..nodes_number {
-            node_infos.insert(k as u64, NodeInfo::default());
-        }
+        // the batches are split by `max_batch_size`
+        assert!(max_batch_size > 0, "max_batch_size must be > 0");
         Self {
-            node_infos,
+            node_infos: Peers::new(nodes_number),
             wishlist: OperationIds::default(),
             wanted: WantOperations::default(),
             already_asked: OperationIds::default(),
             received: OperationMap::default(),
+            op_fees: OperationFees::default(),
             max_batch_size,
+            announce_delay: Duration::ZERO,
+            batch_fill: BatchFill::default(),
+            oversized_policy: OversizedPolicy::default(),
+            op_validation: OpValidation::default(),
+            ask_quota: None,
+            throttle_stats: ThrottleStats::default(),
+            scoring: None,
+            banned: HashMap::default(),
+            pull: Puller::default(),
+            rng: Stream::Protocol.rng(DEFAULT_SEED),
+            recorder: Recorder::default(),
+            clock: Clock::default(),
             is_measured: true,
         }
     }
+
+    /// Approximate heap size of the structures, see [crate::memory]
+    pub fn memory(&self) -> MemoryUsage {
+        MemoryUsage::default()
+            .with("received", operations_memory(&self.received))
+            .with("node_infos", self.node_infos.memory())
+            .with("wishlist", set_memory(&self.wishlist))
+            .with(
+                "wanted",
+                map_memory(&self.wanted) + self.wanted.values().map(set_memory).sum::<usize>(),
+            )
+            .with("already_asked", set_memory(&self.already_asked))
+            .with("op_fees", map_memory(&self.op_fees))
+    }
+}
+
+impl Shard for FakeProtocol {
+    /// The node infos and the banned nodes
+    type Peers = (Peers<NodeInfo>, HashMap<NodeId, PeerScore>);
+
+    fn swap_peers(&mut self, peers: &mut Self::Peers) {
+        std::mem::swap(&mut self.node_infos, &mut peers.0);
+        std::mem::swap(&mut self.banned, &mut peers.1);
+    }
 }
@@ -0,0 +1,64 @@
+//! Operation model shared by the algorithms
+
+use crate::rng::HashMap;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+pub type Fee = u64;
+
+/// Bytes of the id of an operation on the wire, the hash of the operation
+pub const OPERATION_ID_SIZE: usize = 32;
+/// Real id of an operation, what is sent and hashed into the short ids. The
+/// algorithms still work with the index of the operation (a `u64`) as their
+/// `OperationId`: moving them to the 32 bytes ids isn't done yet.
+pub type OperationHash = [u8; OPERATION_ID_SIZE];
+
+/// 32 bytes id of the operation of index `op_id`
+pub fn operation_hash(op_id: u64) -> OperationHash {
+    let mut hash = [0u8; OPERATION_ID_SIZE];
+    for (part, chunk) in hash.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (part, op_id).hash(&mut hasher);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    hash
+}
+
+/// An operation as it travels in the network. The content is what we verify
+/// on reception (see [crate::validation]), the fee gives its priority.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Operation {
+    pub fee: Fee,
+    pub content: String,
+    /// Size of the operation on the wire when it's known, from a capture of
+    /// a real node
+    pub wire_size: Option<usize>,
+}
+
+impl Operation {
+    /// Size of the operation on the wire in bytes, approximated from its
+    /// fee and its content when it's not known
+    pub fn size(&self) -> usize {
+        self.wire_size
+            .unwrap_or(std::mem::size_of::<Fee>() + self.content.len())
+    }
+}
+
+/// Fees announced with the operation ids, a node use them to know which
+/// operations it should ask first, before having the operations.
+pub type OperationFees = HashMap<u64, Fee>;
+
+/// Sort the `op_ids` by descending fee, ids without a known fee are the less
+/// prioritary. Ties are sorted by id so the order is deterministic.
+pub fn by_priority<I: IntoIterator<Item = u64>>(op_ids: I, fees: &OperationFees) -> Vec<u64> {
+    let mut op_ids: Vec<u64> = op_ids.into_iter().collect();
+    op_ids.sort_unstable_by_key(|op_id| {
+        (
+            std::cmp::Reverse(fees.get(op_id).cloned().unwrap_or_default()),
+            *op_id,
+        )
+    });
+    op_ids
+}
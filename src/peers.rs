@@ -0,0 +1,185 @@
+//! The node info of each peer. Every algorithm keeps its own [PeerInfo],
+//! they are created, configured and measured here.
+
+use crate::known_ops::{KnownOps, KnownOpsRepr, KnownOpsStats};
+use crate::memory::map_memory;
+use crate::rate_limit::PeerQuota;
+use crate::rng::HashMap;
+use crate::score::PeerScore;
+use std::ops::{Deref, DerefMut};
+
+type NodeId = u64;
+
+/// What an algorithm keeps about a peer
+pub trait PeerInfo: Default {
+    /// Operations we won't announce to the node
+    fn known_op(&self) -> &KnownOps;
+
+    fn known_op_mut(&mut self) -> &mut KnownOps;
+
+    /// What the node did for us
+    fn score(&self) -> &PeerScore;
+
+    fn score_mut(&mut self) -> &mut PeerScore;
+
+    /// What the node consumed of its [crate::rate_limit::AskQuota]
+    fn quota_mut(&mut self) -> &mut PeerQuota;
+
+    /// Approximate heap size of the other fields
+    fn fields_memory(&self) -> usize;
+
+    /// Approximate heap size of what we know about the node
+    fn memory(&self) -> usize {
+        self.known_op().memory() + self.fields_memory()
+    }
+}
+
+/// The [PeerInfo] of each `NodeId`, with the representation of their known
+/// ids
+#[derive(Default)]
+pub struct Peers<I> {
+    infos: HashMap<NodeId, I>,
+    known_ops: KnownOpsRepr,
+}
+
+impl<I: PeerInfo> Peers<I> {
+    /// The nodes `0..nodes_number`
+    pub fn new(nodes_number: usize) -> Self {
+        let mut peers = Self::default();
+        for node_id in 0..nodes_number as u64 {
+            peers.connect(node_id);
+        }
+        peers
+    }
+
+    fn new_info(known_ops: KnownOpsRepr) -> I {
+        let mut info = I::default();
+        *info.known_op_mut() = KnownOps::new(known_ops);
+        info
+    }
+
+    /// The info of `node_id`, created if it's a new node
+    pub fn connect(&mut self, node_id: NodeId) -> &mut I {
+        let known_ops = self.known_ops;
+        self.infos
+            .entry(node_id)
+            .or_insert_with(|| Self::new_info(known_ops))
+    }
+
+    /// config representation of [PeerInfo::known_op]
+    pub fn known_ops(&self) -> KnownOpsRepr {
+        self.known_ops
+    }
+
+    /// Change the representation, the ids known by the nodes are forgotten
+    pub fn set_known_ops(&mut self, known_ops: KnownOpsRepr) {
+        self.known_ops = known_ops;
+        for info in self.infos.values_mut() {
+            *info.known_op_mut() = KnownOps::new(known_ops);
+        }
+    }
+
+    /// Approximate heap size of the infos
+    pub fn memory(&self) -> usize {
+        map_memory(&self.infos) + self.infos.values().map(I::memory).sum::<usize>()
+    }
+
+    /// Print the memory and the errors of the known ids of all the nodes
+    pub fn print_known_ops(&self) {
+        let mut stats = KnownOpsStats::default();
+        for info in self.infos.values() {
+            stats.add(&info.known_op().stats);
+        }
+        let memory = |measure: fn(&KnownOps) -> usize| -> usize {
+            self.infos
+                .values()
+                .map(|info| measure(info.known_op()))
+                .sum()
+        };
+        match self.known_ops {
+            KnownOpsRepr::Exact => {
+                println!("Known ops: {} bytes", memory(KnownOps::memory))
+            }
+            KnownOpsRepr::AgingBloom { .. } => println!(
+                "Known ops: {} bytes ({} bytes with exact sets), {:?}",
+                memory(KnownOps::filters_memory),
+                memory(KnownOps::exact_memory),
+                stats
+            ),
+        }
+    }
+}
+
+impl<I> Deref for Peers<I> {
+    type Target = HashMap<NodeId, I>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.infos
+    }
+}
+
+impl<I> DerefMut for Peers<I> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.infos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Info {
+        known_op: KnownOps,
+        score: PeerScore,
+        quota: PeerQuota,
+    }
+
+    impl PeerInfo for Info {
+        fn known_op(&self) -> &KnownOps {
+            &self.known_op
+        }
+
+        fn known_op_mut(&mut self) -> &mut KnownOps {
+            &mut self.known_op
+        }
+
+        fn score(&self) -> &PeerScore {
+            &self.score
+        }
+
+        fn score_mut(&mut self) -> &mut PeerScore {
+            &mut self.score
+        }
+
+        fn quota_mut(&mut self) -> &mut PeerQuota {
+            &mut self.quota
+        }
+
+        fn fields_memory(&self) -> usize {
+            0
+        }
+    }
+
+    /// Memory of 2 nodes that know 1000 ids
+    fn memory(accuracy_stats: bool) -> usize {
+        let mut peers: Peers<Info> = Peers::new(2);
+        peers.set_known_ops(KnownOpsRepr::AgingBloom {
+            capacity: 1_000,
+            bits: 10_000,
+            hashes: 7,
+            accuracy_stats,
+        });
+        for info in peers.values_mut() {
+            info.known_op.extend(0..1_000);
+        }
+        peers.memory()
+    }
+
+    #[test]
+    fn memory_counts_the_exact_sets() {
+        let mut known_op = KnownOps::new(KnownOpsRepr::Exact);
+        known_op.extend(0..1_000);
+        assert_eq!(memory(true) - memory(false), 2 * known_op.memory());
+    }
+}
@@ -0,0 +1,140 @@
+//! Anti-entropy pull. The announcements are sent once, if one is lost we
+//! only learn the operation with the announcement of another node. With the
+//! pull, a node regularly asks a random peer the ids it received recently and
+//! asks the ones it misses.
+
+use crate::operation::{by_priority, OperationFees};
+use crate::rng::{HashSet, SimRng};
+use rand::seq::SliceRandom;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// config of the pull
+#[derive(Clone, Copy, Debug)]
+pub struct PullConfig {
+    /// Time between two pulls
+    pub period: Duration,
+    /// A digest contains the ids received during that window
+    pub window: Duration,
+    /// Maximum number of ids in a digest, the most recent are kept
+    pub max_ids: usize,
+}
+
+impl PullConfig {
+    /// True if it's time to pull again, `last_pull` is updated then
+    pub fn is_due(&self, last_pull: &mut Option<Instant>, now: Instant) -> bool {
+        match last_pull {
+            Some(last) if now < *last + self.period => false,
+            _ => {
+                *last_pull = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Counters of the pulls, for the measurements
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PullStats {
+    /// Digests asked
+    pub pulls: u64,
+    /// Ids received in the digests
+    pub digest_ids: u64,
+    /// Ids of the digests we didn't receive yet
+    pub missing: u64,
+}
+
+/// The pulls of a node, the same for every algorithm. The algorithm asks
+/// the digest and handles the missing ids like an announcement.
+#[derive(Default)]
+pub struct Puller {
+    /// config anti-entropy pull, no pull if None
+    pub config: Option<PullConfig>,
+    /// When we asked the last digest
+    pub last_pull: Option<Instant>,
+    /// What the pulls brought us
+    pub stats: PullStats,
+}
+
+impl Puller {
+    pub fn new(config: Option<PullConfig>) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// The peer to ask a digest, if it's time to pull
+    pub fn due_peer<I: Iterator<Item = u64>>(
+        &mut self,
+        node_ids: I,
+        rng: &mut SimRng,
+        now: Instant,
+    ) -> Option<u64> {
+        let config = self.config?;
+        if !config.is_due(&mut self.last_pull, now) {
+            return None;
+        }
+        let node_id = pick_peer(node_ids, rng)?;
+        self.stats.pulls += 1;
+        Some(node_id)
+    }
+
+    /// The ids of `digest` that aren't `received`, by priority, in batches of
+    /// `max_batch_size`
+    pub fn missing(
+        &mut self,
+        digest: HashSet<u64>,
+        received: impl Fn(&u64) -> bool,
+        op_fees: &OperationFees,
+        max_batch_size: usize,
+    ) -> Vec<HashSet<u64>> {
+        self.stats.digest_ids += digest.len() as u64;
+        let missing = by_priority(digest.into_iter().filter(|op_id| !received(op_id)), op_fees);
+        self.stats.missing += missing.len() as u64;
+        missing
+            .chunks(max_batch_size)
+            .map(|chunk| chunk.iter().cloned().collect())
+            .collect()
+    }
+}
+
+/// Random peer to pull, None if we have no peer. The ids are sorted first,
+/// the same `rng` picks the same peer whatever the order of `node_ids`.
+pub fn pick_peer<I: Iterator<Item = u64>>(node_ids: I, rng: &mut SimRng) -> Option<u64> {
+    let mut node_ids: Vec<u64> = node_ids.collect();
+    node_ids.sort_unstable();
+    node_ids.choose(rng).cloned()
+}
+
+/// Ids received by a node, to answer the pulls
+#[derive(Default)]
+pub struct RecentIds {
+    ids: VecDeque<(Instant, u64)>,
+}
+
+impl RecentIds {
+    pub fn push<I: IntoIterator<Item = u64>>(&mut self, op_ids: I, now: Instant) {
+        self.ids
+            .extend(op_ids.into_iter().map(|op_id| (now, op_id)));
+    }
+
+    /// Digest of the ids received during the `window`, the older ids are
+    /// forgotten
+    pub fn digest(&mut self, config: &PullConfig, now: Instant) -> HashSet<u64> {
+        while let Some((at, _)) = self.ids.front() {
+            if *at + config.window >= now {
+                break;
+            }
+            self.ids.pop_front();
+        }
+        self.ids
+            .iter()
+            .rev()
+            .take(config.max_ids)
+            .map(|(_, op_id)| *op_id)
+            .collect()
+    }
+}
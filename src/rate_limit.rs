@@ -0,0 +1,173 @@
+//! Per peer quotas on the asks we receive. Without them a node can ask us
+//! as many operations as it wants and we'll serve all of them.
+
+use crate::rng::HashSet;
+use std::time::{Duration, Instant};
+
+/// What we do with the ids asked over the quota
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThrottlePolicy {
+    /// Forget them, the peer will ask again
+    Drop,
+    /// Keep them and serve them in a next period
+    Defer,
+}
+
+/// config of the quota of each peer
+#[derive(Clone, Copy, Debug)]
+pub struct AskQuota {
+    /// Maximum number of ids a peer can ask during a period
+    pub max_ids: usize,
+    /// Maximum number of bytes we serve to a peer during a period
+    pub max_bytes: usize,
+    pub period: Duration,
+    pub policy: ThrottlePolicy,
+}
+
+/// Counters of the throttling, for the measurements
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThrottleStats {
+    /// Number of asks that reached a quota
+    pub throttled_asks: u64,
+    pub dropped_ids: u64,
+    pub deferred_ids: u64,
+}
+
+/// Consumption of the quota by a peer
+#[derive(Default)]
+pub struct PeerQuota {
+    period_start: Option<Instant>,
+    ids: usize,
+    bytes: usize,
+    /// Ids asked over the quota with the [ThrottlePolicy::Defer] policy
+    pub deferred: HashSet<u64>,
+}
+
+impl PeerQuota {
+    fn refresh(&mut self, quota: &AskQuota, now: Instant) {
+        match self.period_start {
+            Some(start) if now < start + quota.period => {}
+            _ => {
+                self.period_start = Some(now);
+                self.ids = 0;
+                self.bytes = 0;
+            }
+        }
+    }
+
+    fn reject(&mut self, op_id: u64, quota: &AskQuota, stats: &mut ThrottleStats) {
+        match quota.policy {
+            ThrottlePolicy::Drop => stats.dropped_ids += 1,
+            ThrottlePolicy::Defer => {
+                if self.deferred.insert(op_id) {
+                    stats.deferred_ids += 1;
+                }
+            }
+        }
+    }
+
+    /// Return the ids of `op_ids` allowed by the quota, the others are
+    /// dropped or deferred. The ids we don't hold are admitted without being
+    /// charged, we have nothing to send for them.
+    pub fn admit_ids<I: IntoIterator<Item = u64>>(
+        &mut self,
+        op_ids: I,
+        held: impl Fn(u64) -> bool,
+        quota: &AskQuota,
+        stats: &mut ThrottleStats,
+        now: Instant,
+    ) -> Vec<u64> {
+        self.refresh(quota, now);
+        let mut admitted = vec![];
+        let mut throttled = false;
+        for op_id in op_ids {
+            if !held(op_id) {
+                self.deferred.remove(&op_id);
+                admitted.push(op_id);
+            } else if self.ids < quota.max_ids {
+                self.ids += 1;
+                self.deferred.remove(&op_id);
+                admitted.push(op_id);
+            } else {
+                throttled = true;
+                self.reject(op_id, quota, stats);
+            }
+        }
+        if throttled {
+            stats.throttled_asks += 1;
+        }
+        admitted
+    }
+
+    /// Return true if we can still serve `bytes` to the peer during this
+    /// period, otherwise the operation `op_id` is dropped or deferred. An
+    /// operation bigger than `max_bytes` is served alone, at the start of a
+    /// period.
+    pub fn admit_bytes(
+        &mut self,
+        op_id: u64,
+        bytes: usize,
+        quota: &AskQuota,
+        stats: &mut ThrottleStats,
+        now: Instant,
+    ) -> bool {
+        self.refresh(quota, now);
+        if self.bytes + bytes <= quota.max_bytes || self.bytes == 0 {
+            self.bytes += bytes;
+            true
+        } else {
+            self.reject(op_id, quota, stats);
+            false
+        }
+    }
+
+    /// Take the deferred ids if a new period started, they have to be asked
+    /// again through the quota.
+    pub fn take_deferred(&mut self, quota: &AskQuota, now: Instant) -> HashSet<u64> {
+        match self.period_start {
+            Some(start) if now < start + quota.period => HashSet::default(),
+            _ => std::mem::take(&mut self.deferred),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: AskQuota = AskQuota {
+        max_ids: 2,
+        max_bytes: 100,
+        period: Duration::from_secs(60),
+        policy: ThrottlePolicy::Defer,
+    };
+
+    #[test]
+    fn ids_we_dont_hold_are_not_charged() {
+        let mut quota = PeerQuota::default();
+        let mut stats = ThrottleStats::default();
+        let now = Instant::now();
+        let held = |op_id| op_id >= 10;
+        let admitted = quota.admit_ids([1, 2, 3, 10, 11, 12], held, &QUOTA, &mut stats, now);
+        assert_eq!(admitted, [1, 2, 3, 10, 11]);
+        assert_eq!(quota.deferred, [12].into_iter().collect());
+        assert_eq!(stats.throttled_asks, 1);
+    }
+
+    #[test]
+    fn oversized_operation_is_served_alone() {
+        let mut quota = PeerQuota::default();
+        let mut stats = ThrottleStats::default();
+        let now = Instant::now();
+        assert!(quota.admit_bytes(1, 150, &QUOTA, &mut stats, now));
+        assert!(!quota.admit_bytes(2, 10, &QUOTA, &mut stats, now));
+        assert!(!quota.admit_bytes(3, 150, &QUOTA, &mut stats, now));
+        // served in the next period
+        let next = now + QUOTA.period;
+        assert_eq!(
+            quota.take_deferred(&QUOTA, next),
+            [2, 3].into_iter().collect()
+        );
+        assert!(quota.admit_bytes(3, 150, &QUOTA, &mut stats, next));
+    }
+}
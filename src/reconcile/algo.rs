@@ -0,0 +1,345 @@
+use super::types::*;
+use crate::batch::split_batch;
+use crate::hooks::{ban_if_needed, serve_deferred_asks, Protocol, Shared};
+use crate::iblt::{CELLS_BY_DIFF, CELL_SIZE};
+use crate::operation::OPERATION_ID_SIZE;
+use crate::trace::{Event, Recorder};
+use crate::wishlist::{receive_operations, Wishlist, WishlistProtocol};
+
+pub use crate::hooks::{on_ask_received, on_fees_received, on_node_disconnected};
+pub use crate::wishlist::{on_asking_loop, on_batch_received, on_digest_received};
+
+/***************************************************************************************** */
+/* Things that must be in all the algorithms                                               */
+// - send_batch(node_id, operations_ids): Send batch to a node
+// - on_batch_received(): Receive a batch of operation ids
+// - ask_operations(node_id, operations_ids): Ask operations to a node, on_ask_received
+// - on_ask_received ...
+// - send_operations(node_id, operations_ids): Ask operations to a node
+// - on_operation_received ...
+/* *************************************************************************************** */
+
+fn send_batch(_to_node_id: NodeId, _batch: OperationIds) {
+    //#[cfg(feature = "measurements")]
+    super::BATCH_SEND_QUEUE
+        .lock()
+        .unwrap()
+        .push((_to_node_id, _batch));
+}
+
+fn ask_operations(_to_node_id: NodeId, _op_ids: OperationIds, _recorder: &Recorder) {
+    //#[cfg(feature = "measurements")]
+    _recorder.record(|| Event::AskSent(_to_node_id, _op_ids.clone()));
+    super::ASK_BATCH_QUEUE
+        .lock()
+        .unwrap()
+        .push_back((_to_node_id, _op_ids));
+}
+
+fn ask_digest(_to_node_id: NodeId) {
+    //#[cfg(feature = "measurements")]
+    super::DIGEST_ASK_QUEUE
+        .lock()
+        .unwrap()
+        .push_back(_to_node_id);
+}
+
+fn send_operations(_to_node_id: NodeId, _op_ids: OperationMap) {
+    //#[cfg(feature = "measurements")]
+    super::SEND_OPERATION
+        .lock()
+        .unwrap()
+        .push_back((_to_node_id, _op_ids));
+}
+
+impl Protocol for FakeProtocol {
+    type Info = NodeInfo;
+
+    fn shared(&mut self) -> Shared<'_, NodeInfo> {
+        self.split().0
+    }
+
+    fn forget_asks_to(&mut self, node_id: NodeId) {
+        crate::wishlist::forget_asks_to(node_id, self);
+    }
+
+    fn send_operations(node_id: NodeId, operations: OperationMap) {
+        send_operations(node_id, operations);
+    }
+
+    fn ask_digest(node_id: NodeId) {
+        ask_digest(node_id);
+    }
+}
+
+impl WishlistProtocol for FakeProtocol {
+    fn split(&mut self) -> (Shared<'_, NodeInfo>, Wishlist<'_>) {
+        let shared = Shared {
+            node_infos: &mut self.node_infos,
+            received: &mut self.received,
+            op_fees: &mut self.op_fees,
+            banned: &mut self.banned,
+            throttle_stats: &mut self.throttle_stats,
+            pull: &mut self.pull,
+            rng: &mut self.rng,
+            recorder: &self.recorder,
+            clock: &self.clock,
+            max_batch_size: self.max_batch_size,
+            oversized_policy: self.oversized_policy,
+            op_validation: self.op_validation,
+            ask_quota: self.ask_quota,
+            scoring: self.scoring,
+            is_measured: self.is_measured,
+        };
+        let wishlist = Wishlist {
+            in_flight: &mut self.in_flight,
+            wishlist: &mut self.wishlist,
+            retry_period: self.retry_period,
+        };
+        (shared, wishlist)
+    }
+
+    fn ask_operations(node_id: NodeId, op_ids: OperationIds, recorder: &Recorder) {
+        ask_operations(node_id, op_ids, recorder);
+    }
+}
+
+/// `node_id` wants to reconcile its set of `remote_len` ids with ours. We
+/// choose the size of the table from the size of the two sets.
+pub fn on_recon_started(
+    node_id: NodeId,
+    remote_len: usize,
+    protocol: &mut FakeProtocol, /* self simulation */
+) -> ReconReply {
+    if protocol.banned.contains_key(&node_id) {
+        return ReconReply::Refused;
+    }
+    let mut remote_len = remote_len;
+    if remote_len > protocol.max_recon_size {
+        if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+            node_info.score.oversized += 1;
+        }
+        if ban_if_needed(node_id, protocol) || protocol.oversized_policy == OversizedPolicy::Reject
+        {
+            return ReconReply::Refused;
+        }
+        remote_len = protocol.max_recon_size;
+    }
+    let (local_len, recon_q) = match protocol.node_infos.get(&node_id) {
+        Some(node_info) => (
+            node_info.recon_set.len(),
+            node_info.recon_q.unwrap_or(protocol.recon_q),
+        ),
+        None => return ReconReply::Refused,
+    };
+    // the difference can't be known before, we estimate it
+    let diff = remote_len.abs_diff(local_len) as f64 + recon_q * remote_len.min(local_len) as f64;
+    let cells = (CELLS_BY_DIFF * diff).ceil() as usize + 1;
+    if cells * CELL_SIZE >= (remote_len + local_len) * OPERATION_ID_SIZE {
+        protocol.recon_stats.full_lists += 1;
+        send_recon_set(node_id, protocol);
+        return ReconReply::FullList;
+    }
+    ReconReply::Sketch(cells)
+}
+
+/// Table of the set of `node_id`, with the size we chose in
+/// [on_recon_started]. The ids that only the node knows are processed like
+/// a batch, we announce it the ones that only we know.
+///
+/// # Return
+///
+/// False if we failed to decode, the node has to send its full list. Ours
+/// has been sent.
+pub fn on_sketch_received(
+    node_id: NodeId,
+    sketch: Iblt,
+    protocol: &mut FakeProtocol, /* self simulation */
+) -> bool {
+    let local = match protocol.node_infos.get(&node_id) {
+        Some(node_info) => Iblt::from_ids(node_info.recon_set.iter().cloned(), sketch.len()),
+        None => return true,
+    };
+    protocol.recon_stats.sketches += 1;
+    let decoded = sketch.subtract(&local).and_then(|diff| diff.decode());
+    let (remote_only, local_only) = match decoded {
+        Some(decoded) => decoded,
+        None => {
+            protocol.recon_stats.failed += 1;
+            // the difference was bigger than expected
+            if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+                let recon_q = node_info.recon_q.unwrap_or(protocol.recon_q);
+                node_info.recon_q = Some((2. * recon_q).clamp(0.01, 1.));
+            }
+            send_recon_set(node_id, protocol);
+            return false;
+        }
+    };
+    if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+        // what the estimation should have been
+        let common = node_info.recon_set.len().saturating_sub(local_only.len());
+        let min = remote_only.len().min(local_only.len());
+        if common + min > 0 {
+            let recon_q = (2 * min) as f64 / (common + min) as f64;
+            node_info.recon_q = Some(recon_q.clamp(0.01, 1.));
+        }
+        // the common part is known by both, no need to announce it
+        node_info.recon_set.clear();
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            for batch in split_batch(local_only, protocol.max_batch_size) {
+                send_batch(node_id, batch);
+            }
+        }
+    }
+    for batch in split_batch(remote_only, protocol.max_batch_size) {
+        on_batch_received(batch, node_id, protocol);
+    }
+    true
+}
+
+/// Announce to the node everything we received since the last
+/// reconciliation
+fn send_recon_set(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    if let Some(node_info) = protocol.node_infos.get_mut(&node_id) {
+        let recon_set = std::mem::take(&mut node_info.recon_set);
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            for batch in split_batch(recon_set, protocol.max_batch_size) {
+                send_batch(node_id, batch);
+            }
+        }
+    }
+}
+
+pub fn on_operation_received(
+    node_id: NodeId,
+    operations: OperationMap,
+    protocol: &mut FakeProtocol, /* self simulation */
+) {
+    let operations = match receive_operations(node_id, operations, protocol) {
+        Some(operations) => operations,
+        None => return,
+    };
+    // no announcement, the nodes will know them at the next reconciliation
+    for node_info in protocol.node_infos.values_mut() {
+        let op_ids = operations
+            .keys()
+            .filter(|&&op_id| node_info.known_op.insert(op_id));
+        node_info.recon_set.extend(op_ids);
+    }
+}
+
+/// A node connected to us, we announce it all the operations we have
+pub fn on_node_connected(node_id: NodeId, protocol: &mut FakeProtocol /* self simulation */) {
+    if protocol.banned.contains_key(&node_id) {
+        return;
+    }
+    let node_info = protocol.node_infos.connect(node_id);
+    let op_ids = protocol
+        .received
+        .keys()
+        .filter(|&&op_id| node_info.known_op.insert(op_id))
+        .cloned();
+    for batch in split_batch(op_ids, protocol.max_batch_size) {
+        if protocol.is_measured {
+            // just for the measurement, remove that on the definitive implementation
+            send_batch(node_id, batch);
+        }
+    }
+}
+
+/// Serve the asks deferred by the quota
+pub fn on_send_loop(protocol: &mut FakeProtocol /* self simulation */) {
+    serve_deferred_asks(protocol);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol(nodes: usize) -> FakeProtocol {
+        let mut protocol = FakeProtocol::new(nodes, 10, std::time::Duration::from_millis(1));
+        protocol.is_measured = false;
+        protocol
+    }
+
+    #[test]
+    fn recon_set_is_limited_by_max_recon_size() {
+        let mut protocol = protocol(1);
+        // two batches, like a node that got them since the last reconciliation
+        assert_ne!(on_recon_started(0, 20, &mut protocol), ReconReply::Refused);
+        assert_eq!(protocol.node_infos[&0].score.oversized, 0);
+        on_recon_started(0, protocol.max_recon_size + 1, &mut protocol);
+        assert_eq!(protocol.node_infos[&0].score.oversized, 1);
+    }
+
+    #[test]
+    fn sketch_with_more_local_only_than_recon_set() {
+        let mut protocol = protocol(1);
+        protocol
+            .node_infos
+            .get_mut(&0)
+            .unwrap()
+            .recon_set
+            .extend(0..5);
+        // the remote table claims to be missing ids that we never sent it
+        let mut sketch = Iblt::new(30);
+        for op_id in 0..5 {
+            sketch.insert(op_id);
+        }
+        for op_id in 100..110 {
+            sketch.remove(op_id);
+        }
+        assert!(on_sketch_received(0, sketch, &mut protocol));
+    }
+
+    #[test]
+    fn undecodable_sketch_falls_back_to_the_full_lists() {
+        let mut protocol = protocol(1);
+        let node_info = protocol.node_infos.get_mut(&0).unwrap();
+        node_info.recon_set.extend(0..20);
+        node_info.recon_q = Some(0.1);
+        // a difference of 40 ids in a table sized for a few
+        let remote: OperationIds = (100..120).collect();
+        let sketch = Iblt::from_ids(remote.iter().cloned(), 8);
+        assert!(!on_sketch_received(0, sketch, &mut protocol));
+        assert_eq!(protocol.recon_stats.failed, 1);
+        // ours was sent, the estimation of the node is doubled
+        let node_info = &protocol.node_infos[&0];
+        assert!(node_info.recon_set.is_empty());
+        assert_eq!(node_info.recon_q, Some(0.2));
+        // the full list of the node is processed like batches
+        for batch in split_batch(remote, protocol.max_batch_size) {
+            on_batch_received(batch, 0, &mut protocol);
+        }
+        assert_eq!(protocol.in_flight.len(), 20);
+    }
+
+    #[test]
+    fn replayed_batches_are_reconciled() {
+        use crate::harness::{replay_into, Algorithm};
+        use crate::trace::{Answers, Event, Trace};
+        use std::time::Duration;
+        // two nodes announce the same ids in a different order
+        let mut events = vec![];
+        for round in 0..5u64 {
+            let at = Duration::from_millis(10 * round);
+            let ids = round * 10..round * 10 + 10;
+            events.push((at, Event::Batch(1, ids.clone().collect())));
+            events.push((at, Event::Batch(2, ids.rev().collect())));
+        }
+        let trace = Trace {
+            events,
+            tick: Some(Duration::from_millis(1)),
+            answers: Some(Answers {
+                delay: Duration::from_millis(2),
+                sizes: Default::default(),
+            }),
+        };
+        let mut protocol = <FakeProtocol as Algorithm>::new_protocol();
+        let report = replay_into(&mut protocol, &trace);
+        assert!(protocol.recon_stats.sketches > 0);
+        assert_eq!(report.received, 50);
+    }
+}
@@ -0,0 +1,181 @@
+use super::*;
+use crate::{
+    clock::Clock,
+    harness::{
+        self, Algorithm, Answer, Link, ASK_QUOTA, MAX_BATCH_SIZE, OP_VALIDATION, SCORING, T,
+    },
+    memory::MemoryUsage,
+    metrics::Report,
+    rng::{SimRng, Stream},
+    scenario::Scenario,
+    trace::{Event, Recorder, ReplayReport, Trace},
+};
+use rand::seq::SliceRandom;
+use std::time::Duration;
+
+/// An operation still in flight after that is asked to another node
+const RETRY_PERIOD: Duration = Duration::from_millis(200);
+/// Part of the smaller set expected in the difference of the sets
+const RECON_Q: f64 = 0.1;
+
+impl Algorithm for FakeProtocol {
+    const NAME: &'static str = "reconcile";
+    const ASKING_LOOP: bool = true;
+
+    fn new_protocol() -> Self {
+        let mut protocol = FakeProtocol::new(T, MAX_BATCH_SIZE, RETRY_PERIOD);
+        protocol.recon_q = RECON_Q;
+        protocol.op_validation = OP_VALIDATION;
+        protocol.ask_quota = Some(ASK_QUOTA);
+        protocol.scoring = Some(SCORING);
+        protocol
+    }
+
+    fn prepare(&mut self, scenario: &Scenario, recorder: Recorder) {
+        self.pull = Puller::new(scenario.pull);
+        self.rng = scenario.rng(Stream::Protocol);
+        self.recorder = recorder;
+        self.node_infos.set_known_ops(scenario.known_ops);
+    }
+
+    fn set_measured(&mut self, is_measured: bool) {
+        self.is_measured = is_measured;
+    }
+
+    fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    fn recorder(&self) -> &Recorder {
+        &self.recorder
+    }
+
+    fn received(&self) -> &OperationMap {
+        &self.received
+    }
+
+    fn memory(&self) -> MemoryUsage {
+        FakeProtocol::memory(self)
+    }
+
+    fn print_stats(&self, scenario: &Scenario) {
+        harness::print_peers(
+            scenario,
+            &self.node_infos,
+            &self.banned,
+            &self.throttle_stats,
+            &self.pull,
+        );
+        println!("Reconciliations: {:?}", self.recon_stats);
+    }
+
+    fn clear_queues() {
+        ASK_BATCH_QUEUE.lock().unwrap().clear();
+        BATCH_SEND_QUEUE.lock().unwrap().clear();
+        SEND_OPERATION.lock().unwrap().clear();
+        DIGEST_ASK_QUEUE.lock().unwrap().clear();
+    }
+
+    fn pop_ask(_rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        ASK_BATCH_QUEUE.lock().unwrap().pop_front()
+    }
+
+    fn drain_asks() -> Vec<(NodeId, OperationIds)> {
+        ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+    }
+
+    fn pop_announce(rng: &mut SimRng) -> Option<(NodeId, OperationIds)> {
+        let mut guard = BATCH_SEND_QUEUE.lock().unwrap();
+        guard.shuffle(rng);
+        guard.pop()
+    }
+
+    fn pop_pull() -> Option<NodeId> {
+        DIGEST_ASK_QUEUE.lock().unwrap().pop_front()
+    }
+
+    fn on_node_connected(&mut self, node_id: NodeId) {
+        on_node_connected(node_id, self);
+    }
+
+    fn on_node_disconnected(&mut self, node_id: NodeId) {
+        on_node_disconnected(node_id, self);
+    }
+
+    fn on_fees_received(&mut self, fees: OperationFees) {
+        on_fees_received(fees, self);
+    }
+
+    fn on_batch_received(&mut self, node_id: NodeId, op_ids: OperationIds) -> OperationIds {
+        on_batch_received(op_ids, node_id, self)
+    }
+
+    fn on_operations(&mut self, node_id: NodeId, answer: Answer) {
+        let operations = answer
+            .into_iter()
+            .filter_map(|(id, op)| op.map(|op| (id, op)))
+            .collect();
+        on_operation_received(node_id, operations, self);
+    }
+
+    /// The traffic of the node, reconciled or not. The node reconciles
+    /// with us the ids it got in that round, the batches are only sent when
+    /// the reconciliation falls back to the full list.
+    fn on_round(&mut self, batches: Vec<OperationIds>, link: &mut Link) {
+        let node_id = link.node_id();
+        for batch in batches.iter() {
+            self.recorder
+                .record(|| Event::Batch(node_id, batch.clone()));
+        }
+        let recon_set: OperationIds = batches.iter().flatten().cloned().collect();
+        // the node answers the asks of what it reconciles
+        link.share(&recon_set);
+        let full_list = match on_recon_started(node_id, recon_set.len(), self) {
+            ReconReply::Sketch(cells) => {
+                let sketch = Iblt::from_ids(recon_set, cells);
+                link.send(sketch.size());
+                !on_sketch_received(node_id, sketch, self)
+            }
+            ReconReply::FullList => true,
+            ReconReply::Refused => false,
+        };
+        if full_list {
+            for batch in batches {
+                let batch = link.announce(batch);
+                on_batch_received(batch, node_id, self);
+            }
+        }
+    }
+
+    fn on_ask_received(&mut self, node_id: NodeId, op_ids: OperationIds) {
+        on_ask_received(node_id, op_ids, self);
+    }
+
+    fn on_digest_received(&mut self, node_id: NodeId, digest: OperationIds) {
+        on_digest_received(node_id, digest, self);
+    }
+
+    fn on_send_tick(&mut self) {
+        on_send_loop(self);
+    }
+
+    fn on_asking_tick(&mut self) {
+        on_asking_loop(self);
+    }
+}
+
+pub fn process(scenario: &Scenario) -> Report {
+    harness::process::<FakeProtocol>(scenario)
+}
+
+/// Feed the inputs of `trace` to the hooks of a new measured node, see
+/// [harness::replay]
+pub fn replay(trace: &Trace) -> ReplayReport {
+    harness::replay::<FakeProtocol>(trace)
+}
+
+/// Run `scenario` on a single thread and a virtual clock, see
+/// [harness::simulate]
+pub fn simulate(scenario: &Scenario) -> ReplayReport {
+    harness::simulate::<FakeProtocol>(scenario)
+}
@@ -0,0 +1,186 @@
+use crate::clock::Clock;
+use crate::memory::{map_memory, operations_memory, set_memory, MemoryUsage};
+use crate::rng::{HashMap, HashSet, SimRng, Stream};
+use crate::scenario::DEFAULT_SEED;
+use crate::trace::Recorder;
+use std::time::Duration;
+
+pub use crate::batch::OversizedPolicy;
+pub use crate::iblt::Iblt;
+pub use crate::known_ops::{KnownOps, KnownOpsRepr, KnownOpsStats};
+pub use crate::operation::{Fee, Operation, OperationFees};
+pub use crate::peers::{PeerInfo, Peers};
+pub use crate::pull::{PullConfig, PullStats, Puller};
+pub use crate::rate_limit::{AskQuota, PeerQuota, ThrottlePolicy, ThrottleStats};
+pub use crate::score::{PeerScore, ScoreConfig, ScoreWeights};
+pub use crate::validation::OpValidation;
+
+pub type OperationId = u64;
+pub type NodeId = u64;
+pub type OperationMap = HashMap<OperationId, Operation>;
+pub type OperationIds = HashSet<OperationId>;
+
+/// Default [FakeProtocol::max_recon_size], in batches: a node reconciles
+/// with us all the batches it got since the previous reconciliation
+pub const RECON_BATCHES: usize = 4;
+
+/* ****  Following Difer from the other algos **** */
+/// What we answer to a node that wants to reconcile its set with ours
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconReply {
+    /// Send a table of that many cells
+    Sketch(usize),
+    /// The lists are smaller than the table, send the full list. Ours has
+    /// been sent.
+    FullList,
+    /// We don't want to reconcile with that node
+    Refused,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReconStats {
+    /// Tables received
+    pub sketches: u64,
+    /// Tables that we failed to decode, we fell back to the full lists
+    pub failed: u64,
+    /// Reconciliations where we directly exchanged the full lists
+    pub full_lists: u64,
+}
+
+pub use crate::wishlist::InFlightOperations;
+
+#[derive(Default)]
+pub struct NodeInfo {
+    /// Operations we won't announce to the node
+    pub known_op: KnownOps,
+    /// What the node did for us
+    pub score: PeerScore,
+    /// What the node consumed of its [AskQuota]
+    pub quota: PeerQuota,
+    /// Operations received since the last reconciliation with the node,
+    /// that the node doesn't know from us
+    pub recon_set: OperationIds,
+    /// Estimation of [FakeProtocol::recon_q] for that node, learned from
+    /// the previous reconciliations
+    pub recon_q: Option<f64>,
+}
+
+impl PeerInfo for NodeInfo {
+    fn known_op(&self) -> &KnownOps {
+        &self.known_op
+    }
+
+    fn known_op_mut(&mut self) -> &mut KnownOps {
+        &mut self.known_op
+    }
+
+    fn score(&self) -> &PeerScore {
+        &self.score
+    }
+
+    fn score_mut(&mut self) -> &mut PeerScore {
+        &mut self.score
+    }
+
+    fn quota_mut(&mut self) -> &mut PeerQuota {
+        &mut self.quota
+    }
+
+    fn fields_memory(&self) -> usize {
+        set_memory(&self.quota.deferred) + set_memory(&self.recon_set)
+    }
+}
+
+pub struct FakeProtocol {
+    /// Remember that nodes know and have
+    pub node_infos: Peers<NodeInfo>,
+    /// Operations asked right away when first announced
+    pub in_flight: InFlightOperations,
+    /// Operations announced while already in flight, asked again to another
+    /// node by the asking loop if they don't come in time
+    pub wishlist: OperationIds,
+    /// Map<OperationId, Operation> received!
+    pub received: OperationMap,
+    /// Fees announced by the other nodes, used to ask the best operations first
+    pub op_fees: OperationFees,
+
+    /// config maximum size of a batch (number of operations)
+    pub max_batch_size: usize,
+    /// config after that an operation of the wishlist still in flight is
+    /// asked to another node
+    pub retry_period: Duration,
+    /// config biggest set that a node can reconcile with us, the bigger
+    /// ones are oversized
+    pub max_recon_size: usize,
+
+    /// config first estimation of the part of the smaller set that is in the
+    /// difference of the sets, adjusted for each node after each
+    /// reconciliation
+    pub recon_q: f64,
+    /// What happened to the reconciliations
+    pub recon_stats: ReconStats,
+    /// config what we do with the received batches bigger than `max_batch_size`
+    pub oversized_policy: OversizedPolicy,
+    /// config verification done on each received operation
+    pub op_validation: OpValidation,
+    /// config limits of the asks of each node, unlimited if None
+    pub ask_quota: Option<AskQuota>,
+    /// Number of asks throttled by the [FakeProtocol::ask_quota]
+    pub throttle_stats: ThrottleStats,
+    /// config scoring of the nodes, no timeouts and no ban if None
+    pub scoring: Option<ScoreConfig>,
+    /// Nodes disconnected because of their score, with their last score
+    pub banned: HashMap<NodeId, PeerScore>,
+    /// config anti-entropy pull and what it brought us
+    pub pull: Puller,
+
+    /// Draws the random decisions of the node, seeded by the scenario
+    pub rng: SimRng,
+    /// Records the events of the node, see [crate::trace]
+    pub recorder: Recorder,
+    /// Time read by the hooks, see [crate::clock]
+    pub clock: Clock,
+
+    /// used for measurement, if true, it's the one we measure (default = true)
+    pub is_measured: bool,
+}
+
+impl FakeProtocol {
+    pub fn new(nodes_number: usize, max_batch_size: usize, retry_period: Duration) -> Self {
+        // the batches are split by `max_batch_size`
+        assert!(max_batch_size > 0, "max_batch_size must be > 0");
+        Self {
+            node_infos: Peers::new(nodes_number),
+            in_flight: InFlightOperations::default(),
+            wishlist: OperationIds::default(),
+            received: OperationMap::default(),
+            op_fees: OperationFees::default(),
+            max_batch_size,
+            retry_period,
+            max_recon_size: RECON_BATCHES * max_batch_size,
+            recon_q: 1.,
+            recon_stats: ReconStats::default(),
+            oversized_policy: OversizedPolicy::default(),
+            op_validation: OpValidation::default(),
+            ask_quota: None,
+            throttle_stats: ThrottleStats::default(),
+            scoring: None,
+            banned: HashMap::default(),
+            pull: Puller::default(),
+            rng: Stream::Protocol.rng(DEFAULT_SEED),
+            recorder: Recorder::default(),
+            clock: Clock::default(),
+            is_measured: true,
+        }
+    }
+
+    /// Approximate heap size of the structures, see [crate::memory]
+    pub fn memory(&self) -> MemoryUsage {
+        MemoryUsage::default()
+            .with("received", operations_memory(&self.received))
+            .with("node_infos", self.node_infos.memory())
+            .with("in_flight", map_memory(&self.in_flight))
+            .with("wishlist", set_memory(&self.wishlist))
+            .with("op_fees", map_memory(&self.op_fees))
+    }
+}
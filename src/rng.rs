@@ -0,0 +1,52 @@
+//! Seeded random generators. Every random decision of a run (fees, orders of
+//! the announcements, losses, sleeps, shuffles of the queues, peers to pull,
+//! salts of the short ids) is drawn from the seed of the scenario, each
+//! thread or component from its own stream.
+//!
+//! The maps of the crate hash with [FixedState], they iterate in the same
+//! order in every process. So a single-threaded run on a virtual clock
+//! ([crate::harness::simulate]) sends the same messages for the same seed.
+//! The threaded run of [crate::harness::process] only draws the same inputs
+//! (the fees, the orders of the announcements and their losses): how many
+//! numbers the other streams draw, and for what, depends on the timings of
+//! the threads on the wall clock.
+
+use rand::{rngs::StdRng, SeedableRng};
+use std::{collections::hash_map::DefaultHasher, hash::BuildHasherDefault};
+
+pub type SimRng = StdRng;
+
+/// Hasher of the maps, with fixed keys unlike `RandomState`
+pub type FixedState = BuildHasherDefault<DefaultHasher>;
+pub type HashMap<K, V> = std::collections::HashMap<K, V, FixedState>;
+pub type HashSet<T> = std::collections::HashSet<T, FixedState>;
+
+/// Streams of a run, one by thread or by component drawing numbers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Fees,
+    /// Orders of the announcements and their losses
+    BatchSender,
+    /// Sleeps of the remote nodes answering our asks
+    AskedReceiver,
+    /// Sleeps of the remote nodes asking our announcements and shuffles of
+    /// the announcements
+    OperationAsker,
+    /// Sleeps of the loops of the measured node
+    SendLoop,
+    AskingLoop,
+    /// Decisions of the measured node, see [crate::pull::pick_peer]
+    Protocol,
+    /// Salts of the connections, see [crate::short_id::ShortIdLinks]
+    ShortIds,
+    /// Deadlines and nodes of the future sets of the deferred asks bench
+    DeferredAsks,
+}
+
+impl Stream {
+    /// Generator of the stream for `seed`
+    pub fn rng(self, seed: u64) -> SimRng {
+        const GOLDEN: u64 = 0x9e37_79b9_7f4a_7c15;
+        SimRng::seed_from_u64(seed ^ (self as u64 + 1).wrapping_mul(GOLDEN))
+    }
+}
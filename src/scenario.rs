@@ -0,0 +1,183 @@
+//! Scenarios run by the measurements of every algorithm
+
+use crate::{
+    adversary::{Adversaries, Behavior},
+    known_ops::KnownOpsRepr,
+    pull::PullConfig,
+    rng::{SimRng, Stream},
+};
+use std::{path::PathBuf, time::Duration};
+
+/// Seed of the scenarios, unless another one is given
+pub const DEFAULT_SEED: u64 = 0;
+
+/// Connection or disconnection of a remote node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Churn {
+    Join(u64),
+    Leave(u64),
+}
+
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub name: &'static str,
+    /// Behaviors of the remote nodes
+    pub adversaries: Adversaries,
+    /// Nodes joining or leaving, applied before the announcement round of the
+    /// given index
+    pub churn: Vec<(usize, Churn)>,
+    /// After that we stop waiting the missing operations
+    pub deadline: Duration,
+    /// Bytes of the short ids sent in the announcements and the asks instead
+    /// of the full ids, None to send the full ids, see [crate::short_id]
+    pub short_ids: Option<usize>,
+    /// Probability that an announcement of a remote node is lost
+    pub announce_loss: f64,
+    /// Anti-entropy pull of the measured node, no pull if None
+    pub pull: Option<PullConfig>,
+    /// Representation of the ids known by each remote node
+    pub known_ops: KnownOpsRepr,
+    /// Every random decision of the run comes from that seed, see [crate::rng]
+    pub seed: u64,
+    /// Directory where the trace of the run is written, not recorded if
+    /// None, see [crate::trace]
+    pub record: Option<PathBuf>,
+}
+
+impl Scenario {
+    /// Every node is honest
+    pub fn honest() -> Self {
+        Self {
+            name: "honest",
+            adversaries: Adversaries::default(),
+            churn: vec![],
+            deadline: Duration::from_secs(10),
+            short_ids: None,
+            announce_loss: 0.,
+            pull: None,
+            known_ops: KnownOpsRepr::Exact,
+            seed: DEFAULT_SEED,
+            record: None,
+        }
+    }
+
+    /// One node for each misbehavior, the others are honest
+    pub fn adversarial() -> Self {
+        Self {
+            name: "adversarial",
+            adversaries: Adversaries::default()
+                .with(1, Behavior::Phantom)
+                .with(2, Behavior::Mute)
+                .with(3, Behavior::Corrupt)
+                .with(4, Behavior::Flood)
+                .with(5, Behavior::Replay),
+            churn: vec![],
+            deadline: Duration::from_secs(5),
+            short_ids: None,
+            announce_loss: 0.,
+            pull: None,
+            known_ops: KnownOpsRepr::Exact,
+            seed: DEFAULT_SEED,
+            record: None,
+        }
+    }
+
+    /// Honest nodes leaving during the run, some of them come back later
+    pub fn churn() -> Self {
+        Self {
+            name: "churn",
+            adversaries: Adversaries::default(),
+            churn: vec![
+                (20, Churn::Leave(6)),
+                (30, Churn::Leave(7)),
+                (50, Churn::Join(6)),
+                (60, Churn::Leave(8)),
+                (70, Churn::Join(7)),
+            ],
+            deadline: Duration::from_secs(5),
+            short_ids: None,
+            announce_loss: 0.,
+            pull: None,
+            known_ops: KnownOpsRepr::Exact,
+            seed: DEFAULT_SEED,
+            record: None,
+        }
+    }
+
+    /// Every node is honest, the bandwidth of the ids announced and asked is
+    /// counted with 4 bytes short ids
+    pub fn short_ids() -> Self {
+        Self {
+            name: "short_ids",
+            short_ids: Some(4),
+            ..Self::honest()
+        }
+    }
+
+    /// Most announcements are lost, the operations announced by none of the
+    /// nodes are never received
+    pub fn lossy() -> Self {
+        Self {
+            name: "lossy",
+            announce_loss: 0.85,
+            deadline: Duration::from_secs(5),
+            ..Self::honest()
+        }
+    }
+
+    /// Same losses, the measured node pulls the missed operations
+    pub fn lossy_pull() -> Self {
+        Self {
+            name: "lossy_pull",
+            pull: Some(PullConfig {
+                period: Duration::from_millis(50),
+                window: Duration::from_secs(2),
+                max_ids: 10_000,
+            }),
+            ..Self::lossy()
+        }
+    }
+
+    /// Every node is honest, the ids known by each node are kept in Bloom
+    /// filters of 8192 ids (about 1% of false positives) instead of sets.
+    /// The sets are still kept to count the errors of the filters.
+    pub fn bloom() -> Self {
+        Self {
+            name: "bloom",
+            known_ops: KnownOpsRepr::AgingBloom {
+                capacity: 8_192,
+                bits: 81_920,
+                hashes: 7,
+                accuracy_stats: true,
+            },
+            ..Self::honest()
+        }
+    }
+
+    /// Every operation reaches the measured node: no misbehaving node, no
+    /// churn and no lost announcement
+    pub fn loses_nothing(&self) -> bool {
+        self.adversaries.is_empty() && self.churn.is_empty() && self.announce_loss == 0.
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+
+    pub fn with_record(self, record: Option<PathBuf>) -> Self {
+        Self { record, ..self }
+    }
+
+    /// Generator of `stream` for this run
+    pub fn rng(&self, stream: Stream) -> SimRng {
+        stream.rng(self.seed)
+    }
+
+    /// Churn events to apply before the announcement `round`
+    pub fn churn_at(&self, round: usize) -> impl Iterator<Item = Churn> + '_ {
+        self.churn
+            .iter()
+            .filter(move |(at, _)| *at == round)
+            .map(|(_, churn)| *churn)
+    }
+}
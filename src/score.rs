@@ -0,0 +1,64 @@
+//! Score of the peers, computed from what they did for us. A peer with a bad
+//! score is asked last and disconnected under a threshold.
+
+use std::time::Duration;
+
+/// What a peer did for us since the connection
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerScore {
+    /// Operations correctly delivered
+    pub delivered: u64,
+    /// Asked operations not delivered, or not in time
+    pub timeouts: u64,
+    /// Invalid operations received
+    pub invalid: u64,
+    /// Batches bigger than `max_batch_size`
+    pub oversized: u64,
+}
+
+impl PeerScore {
+    pub fn value(&self, weights: &ScoreWeights) -> i64 {
+        self.delivered as i64
+            - weights.timeout * self.timeouts as i64
+            - weights.invalid * self.invalid as i64
+            - weights.oversized * self.oversized as i64
+    }
+}
+
+/// config penalty of each misbehavior, a delivered operation is worth 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScoreWeights {
+    pub timeout: i64,
+    pub invalid: i64,
+    pub oversized: i64,
+}
+
+impl ScoreWeights {
+    pub const DEFAULT: ScoreWeights = ScoreWeights {
+        timeout: 10,
+        invalid: 50,
+        oversized: 50,
+    };
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// config of the scoring
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreConfig {
+    /// An asked operation not received after that is a timeout for the
+    /// asked peer
+    pub ask_timeout: Duration,
+    /// Peers with a score under the threshold are disconnected
+    pub ban_threshold: i64,
+    pub weights: ScoreWeights,
+}
+
+/// Weights of the scores, the default ones without scoring
+pub fn weights(scoring: Option<ScoreConfig>) -> ScoreWeights {
+    scoring.map(|scoring| scoring.weights).unwrap_or_default()
+}
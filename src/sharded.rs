@@ -0,0 +1,197 @@
+//! Protocol state split in shards by operation id, each behind its own lock.
+//! A handler only locks the shards of the ids it gets, the maps by operation
+//! of each shard are smaller and the loops run on one shard at a time.
+//!
+//! Each shard is a whole `FakeProtocol` that only sees its ids, but what is
+//! kept by node (node infos, scores, quotas, bans) is not split: it's taken
+//! out of the shards in one shared [Shard::Peers], lent to a shard while it's
+//! locked. The lock of the peers is taken after the one of the shard, so the
+//! handlers on different shards still wait for each other on it.
+
+use crate::rng::{HashMap, HashSet};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard},
+};
+
+/// A protocol whose state by node can be shared by its shards
+pub trait Shard {
+    /// What the protocol keeps by node
+    type Peers: Default;
+
+    /// Exchange the state by node of the protocol with `peers`
+    fn swap_peers(&mut self, peers: &mut Self::Peers);
+}
+
+pub struct Sharded<P: Shard> {
+    shards: Vec<Mutex<P>>,
+    peers: Mutex<P::Peers>,
+}
+
+/// A locked shard, with the shared peers in it until it's dropped
+pub struct ShardGuard<'a, P: Shard> {
+    shard: MutexGuard<'a, P>,
+    peers: MutexGuard<'a, P::Peers>,
+}
+
+impl<P: Shard> Deref for ShardGuard<'_, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.shard
+    }
+}
+
+impl<P: Shard> DerefMut for ShardGuard<'_, P> {
+    fn deref_mut(&mut self) -> &mut P {
+        &mut self.shard
+    }
+}
+
+impl<P: Shard> Drop for ShardGuard<'_, P> {
+    fn drop(&mut self) {
+        self.shard.swap_peers(&mut self.peers);
+    }
+}
+
+impl<P: Shard> Sharded<P> {
+    /// `shards` protocols built with `new_shard`, the peers of the first one
+    /// are shared by all of them
+    pub fn new(shards: usize, mut new_shard: impl FnMut() -> P) -> Self {
+        assert!(shards > 0, "at least one shard");
+        let mut peers = P::Peers::default();
+        let shards: Vec<Mutex<P>> = (0..shards)
+            .map(|shard| {
+                let mut protocol = new_shard();
+                if shard == 0 {
+                    protocol.swap_peers(&mut peers);
+                } else {
+                    protocol.swap_peers(&mut P::Peers::default());
+                }
+                Mutex::new(protocol)
+            })
+            .collect();
+        Self {
+            shards,
+            peers: Mutex::new(peers),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// The ids of the tests are often consecutive, mix them before the modulo
+    pub fn shard_of(&self, op_id: u64) -> usize {
+        ((op_id.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % self.shards.len() as u64) as usize
+    }
+
+    pub fn lock(&self, shard: usize) -> ShardGuard<'_, P> {
+        let mut shard = self.shards[shard].lock().unwrap();
+        let mut peers = self.peers.lock().unwrap();
+        shard.swap_peers(&mut peers);
+        ShardGuard { shard, peers }
+    }
+
+    /// The ids of each shard, without the shards that get none
+    pub fn split_ids(&self, op_ids: HashSet<u64>) -> Vec<(usize, HashSet<u64>)> {
+        let mut split = vec![HashSet::default(); self.shards.len()];
+        for op_id in op_ids {
+            split[self.shard_of(op_id)].insert(op_id);
+        }
+        split
+            .into_iter()
+            .enumerate()
+            .filter(|(_, op_ids)| !op_ids.is_empty())
+            .collect()
+    }
+
+    /// Same as [Sharded::split_ids] with the values of the ids
+    pub fn split_map<V>(&self, map: HashMap<u64, V>) -> Vec<(usize, HashMap<u64, V>)> {
+        let mut split: Vec<HashMap<u64, V>> =
+            (0..self.shards.len()).map(|_| HashMap::default()).collect();
+        for (op_id, value) in map {
+            split[self.shard_of(op_id)].insert(op_id, value);
+        }
+        split
+            .into_iter()
+            .enumerate()
+            .filter(|(_, map)| !map.is_empty())
+            .collect()
+    }
+
+    /// Run the handler `f` on the shard of each part of `op_ids`, one lock
+    /// at a time, and return what it returned for each shard
+    pub fn on_ids<R>(
+        &self,
+        op_ids: HashSet<u64>,
+        mut f: impl FnMut(HashSet<u64>, &mut P) -> R,
+    ) -> Vec<R> {
+        self.split_ids(op_ids)
+            .into_iter()
+            .map(|(shard, op_ids)| f(op_ids, &mut self.lock(shard)))
+            .collect()
+    }
+
+    /// Same as [Sharded::on_ids] with the values of the ids
+    pub fn on_map<V, R>(
+        &self,
+        map: HashMap<u64, V>,
+        mut f: impl FnMut(HashMap<u64, V>, &mut P) -> R,
+    ) -> Vec<R> {
+        self.split_map(map)
+            .into_iter()
+            .map(|(shard, map)| f(map, &mut self.lock(shard)))
+            .collect()
+    }
+
+    /// Run `f` on every shard, one lock at a time. For what isn't about some
+    /// ids: connections, loops.
+    pub fn for_each(&self, mut f: impl FnMut(&mut P)) {
+        for shard in 0..self.shards.len() {
+            f(&mut self.lock(shard));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts the ids it handled, by shard and by node
+    #[derive(Default)]
+    struct Counter {
+        ids: usize,
+        by_node: HashMap<u64, usize>,
+    }
+
+    impl Shard for Counter {
+        type Peers = HashMap<u64, usize>;
+
+        fn swap_peers(&mut self, peers: &mut Self::Peers) {
+            std::mem::swap(&mut self.by_node, peers);
+        }
+    }
+
+    #[test]
+    fn peers_are_shared_by_the_shards() {
+        let sharded = Sharded::new(4, Counter::default);
+        let counts = sharded.on_ids((0..100).collect(), |op_ids, counter| {
+            counter.ids += op_ids.len();
+            *counter.by_node.entry(7).or_default() += op_ids.len();
+            op_ids.len()
+        });
+        assert!(counts.len() > 1);
+        assert_eq!(counts.iter().sum::<usize>(), 100);
+        let mut ids = 0;
+        sharded.for_each(|counter| {
+            ids += counter.ids;
+            assert_eq!(counter.by_node[&7], 100);
+        });
+        assert_eq!(ids, 100);
+    }
+}
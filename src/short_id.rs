@@ -0,0 +1,356 @@
+//! Short ids of the operations, announced and asked instead of the 32 bytes
+//! ids. Like in the compact blocks, a short id is a hash of the id salted for
+//! each connection and truncated, so a collision on a connection isn't one on
+//! the others.
+//!
+//! Each side of a connection resolves the short ids it receives with the ids
+//! it knows: the measured node the batches with the operations it received,
+//! the remote node the asks with the ids it announced. The ids colliding in
+//! a batch or an ask are sent in full. A short id matching several known ids
+//! falls back to the full id, at the cost of a round trip. A short id
+//! matching a single wrong id is mistaken without anyone knowing it: the
+//! measured node ignores an announcement, or is answered another operation.
+//!
+//! The ids are hashed from their 32 bytes [crate::operation::OperationHash],
+//! the algorithms still handle the `u64` index of the operations.
+
+use crate::operation::{operation_hash, OPERATION_ID_SIZE};
+use crate::rng::{HashMap, HashSet, SimRng};
+use rand::Rng;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+pub type ShortId = u64;
+
+/// What happened to the short ids of a run
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShortIdStats {
+    /// Short ids resolved with the ids known by the receiver
+    pub resolved: u64,
+    /// Short ids of operations unknown by the receiver, asked by short id
+    pub unknown: u64,
+    /// Ids sent in full because their short id collided in the batch
+    pub collisions: u64,
+    /// Short ids matching several known ids, the receiver asked the full ids
+    pub ambiguous: u64,
+    /// Short ids resolved to the wrong operation, the receiver ignores that
+    /// announcement without knowing it
+    pub mistaken: u64,
+    /// Short ids of the asks that the node asked didn't resolve to one id,
+    /// asked again in full
+    pub asked_in_full: u64,
+}
+
+/// Ids of a batch or of an ask as they are sent
+#[derive(Clone, Debug, Default)]
+pub struct CompactBatch {
+    /// The ids are not sent, the sender keeps them to answer the asks by
+    /// short id
+    pub short_ids: Vec<(ShortId, u64)>,
+    /// Ids that collided in the batch
+    pub full_ids: HashSet<u64>,
+}
+
+/// Short ids of a connection
+pub struct ShortIds {
+    salt: u64,
+    /// Bytes of a short id on the wire
+    bytes: usize,
+    /// Ids known by the receiver, by short id
+    known: HashMap<ShortId, Vec<u64>>,
+}
+
+impl ShortIds {
+    pub fn new(salt: u64, bytes: usize) -> Self {
+        Self {
+            salt,
+            bytes,
+            known: HashMap::default(),
+        }
+    }
+
+    pub fn short_id(&self, op_id: u64) -> ShortId {
+        let mut hasher = DefaultHasher::new();
+        (self.salt, operation_hash(op_id)).hash(&mut hasher);
+        hasher.finish() & (u64::MAX >> (64 - 8 * self.bytes.min(8)))
+    }
+
+    /// The receiver knows the full id of `op_id`
+    pub fn learn(&mut self, op_id: u64) {
+        let known = self.known.entry(self.short_id(op_id)).or_default();
+        if !known.contains(&op_id) {
+            known.push(op_id);
+        }
+    }
+
+    /// Short ids of the batch, the ids with the same short id are sent in full
+    pub fn encode(&self, op_ids: &HashSet<u64>, stats: &mut ShortIdStats) -> CompactBatch {
+        let mut by_short: HashMap<ShortId, Vec<u64>> = HashMap::default();
+        for op_id in op_ids {
+            by_short
+                .entry(self.short_id(*op_id))
+                .or_default()
+                .push(*op_id);
+        }
+        let mut batch = CompactBatch::default();
+        for (short_id, op_ids) in by_short {
+            if op_ids.len() == 1 {
+                batch.short_ids.push((short_id, op_ids[0]));
+            } else {
+                stats.collisions += op_ids.len() as u64;
+                batch.full_ids.extend(op_ids);
+            }
+        }
+        batch
+    }
+
+    /// Ids understood by the receiver and the bytes exchanged for the batch.
+    /// The unknown short ids are asked by short id, the answer brings the
+    /// full id with the operation.
+    pub fn decode(&self, batch: CompactBatch, stats: &mut ShortIdStats) -> (HashSet<u64>, usize) {
+        let mut bytes =
+            batch.short_ids.len() * self.bytes + batch.full_ids.len() * OPERATION_ID_SIZE;
+        let mut op_ids = batch.full_ids;
+        for (short_id, op_id) in batch.short_ids {
+            match self.known.get(&short_id).map(|known| known.as_slice()) {
+                Some([known]) => {
+                    stats.resolved += 1;
+                    if *known != op_id {
+                        stats.mistaken += 1;
+                    }
+                    op_ids.insert(*known);
+                }
+                Some(_) => {
+                    // fallback to the full id
+                    stats.ambiguous += 1;
+                    bytes += self.bytes + OPERATION_ID_SIZE;
+                    op_ids.insert(op_id);
+                }
+                None => {
+                    stats.unknown += 1;
+                    op_ids.insert(op_id);
+                }
+            }
+        }
+        (op_ids, bytes)
+    }
+
+    /// Ids understood by the node asked and the bytes exchanged for the ask.
+    /// The short ids it doesn't know, or that match several ids it knows,
+    /// are asked again in full.
+    pub fn decode_ask(&self, ask: CompactBatch, stats: &mut ShortIdStats) -> (HashSet<u64>, usize) {
+        let mut bytes = ask.short_ids.len() * self.bytes + ask.full_ids.len() * OPERATION_ID_SIZE;
+        let mut op_ids = ask.full_ids;
+        for (short_id, op_id) in ask.short_ids {
+            match self.known.get(&short_id).map(|known| known.as_slice()) {
+                Some([known]) => {
+                    stats.resolved += 1;
+                    if *known != op_id {
+                        stats.mistaken += 1;
+                    }
+                    op_ids.insert(*known);
+                }
+                _ => {
+                    // the node asked sends back the short id, we ask the full id
+                    stats.asked_in_full += 1;
+                    bytes += self.bytes + OPERATION_ID_SIZE;
+                    op_ids.insert(op_id);
+                }
+            }
+        }
+        (op_ids, bytes)
+    }
+}
+
+/// Connection of the measured node with a remote node, with the same salt
+/// both ways
+struct Link {
+    /// Ids known by the measured node, resolve the batches
+    local: ShortIds,
+    /// Ids announced by the remote node, resolve the asks
+    remote: ShortIds,
+}
+
+/// Connections of the measured node with the remote nodes. Without short
+/// ids, the batches and the asks are sent with the full ids.
+pub struct ShortIdLinks {
+    /// Bytes of a short id, None to send the full ids
+    bytes: Option<usize>,
+    links: HashMap<u64, Link>,
+    /// Ids known by the measured node
+    learned: HashSet<u64>,
+    /// Draws the salts of the connections
+    rng: SimRng,
+    pub stats: ShortIdStats,
+}
+
+impl ShortIdLinks {
+    pub fn new(bytes: Option<usize>, rng: SimRng) -> Self {
+        Self {
+            bytes,
+            links: HashMap::default(),
+            learned: HashSet::default(),
+            rng,
+            stats: ShortIdStats::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.bytes.is_some()
+    }
+
+    /// `node_id` announces `op_ids` to the measured node. Return the ids the
+    /// measured node understood and the bytes exchanged.
+    pub fn announce(&mut self, node_id: u64, op_ids: HashSet<u64>) -> (HashSet<u64>, usize) {
+        if self.bytes.is_none() {
+            let size = op_ids.len() * OPERATION_ID_SIZE;
+            return (op_ids, size);
+        }
+        self.share(node_id, &op_ids);
+        let link = &self.links[&node_id];
+        let batch = link.local.encode(&op_ids, &mut self.stats);
+        link.local.decode(batch, &mut self.stats)
+    }
+
+    /// `node_id` knows `op_ids`, it resolves the asks of them. That's
+    /// the case of the ids it announces or reconciles with the measured node.
+    pub fn share(&mut self, node_id: u64, op_ids: &HashSet<u64>) {
+        let bytes = match self.bytes {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        let (learned, rng) = (&self.learned, &mut self.rng);
+        let link = self.links.entry(node_id).or_insert_with(|| {
+            // a new salt for each connection
+            let salt = rng.gen();
+            let mut local = ShortIds::new(salt, bytes);
+            for op_id in learned.iter() {
+                local.learn(*op_id);
+            }
+            let remote = ShortIds::new(salt, bytes);
+            Link { local, remote }
+        });
+        for op_id in op_ids.iter() {
+            link.remote.learn(*op_id);
+        }
+    }
+
+    /// The measured node asks `op_ids` to `node_id`. Return the ids the node
+    /// understood and the bytes exchanged. A node that never announced
+    /// anything is asked the full ids.
+    pub fn ask(&mut self, node_id: u64, op_ids: &HashSet<u64>) -> (HashSet<u64>, usize) {
+        match self.links.get(&node_id).filter(|_| self.bytes.is_some()) {
+            Some(link) => {
+                let ask = link.remote.encode(op_ids, &mut self.stats);
+                link.remote.decode_ask(ask, &mut self.stats)
+            }
+            None => (op_ids.clone(), op_ids.len() * OPERATION_ID_SIZE),
+        }
+    }
+
+    /// The measured node received the operations, it knows their full ids
+    pub fn learn<I: IntoIterator<Item = u64>>(&mut self, op_ids: I) {
+        if self.bytes.is_none() {
+            return;
+        }
+        for op_id in op_ids {
+            if self.learned.insert(op_id) {
+                for link in self.links.values_mut() {
+                    link.local.learn(op_id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Stream;
+
+    /// Two ids of `0..1000` with the same short id in `ids`
+    fn colliding(ids: &ShortIds) -> (u64, u64) {
+        let mut seen: HashMap<ShortId, u64> = HashMap::default();
+        for op_id in 0..1000 {
+            if let Some(other) = seen.insert(ids.short_id(op_id), op_id) {
+                return (other, op_id);
+            }
+        }
+        panic!("no collision on 1 byte");
+    }
+
+    /// An id of `0..1000` whose short id is none of those of `op_ids`
+    fn distinct(ids: &ShortIds, op_ids: &[u64]) -> u64 {
+        (0..1000)
+            .find(|op_id| {
+                op_ids
+                    .iter()
+                    .all(|id| ids.short_id(*id) != ids.short_id(*op_id))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn colliding_ids_are_sent_in_full() {
+        let mut ids = ShortIds::new(7, 1);
+        let (a, b) = colliding(&ids);
+        let c = distinct(&ids, &[a, b]);
+        ids.learn(c);
+        let mut stats = ShortIdStats::default();
+        let batch = ids.encode(&[a, b, c].into_iter().collect(), &mut stats);
+        assert_eq!(batch.full_ids, [a, b].into_iter().collect());
+        assert_eq!(batch.short_ids, vec![(ids.short_id(c), c)]);
+        assert_eq!(stats.collisions, 2);
+        let (op_ids, bytes) = ids.decode(batch, &mut stats);
+        assert_eq!(op_ids, [a, b, c].into_iter().collect());
+        assert_eq!(bytes, 1 + 2 * OPERATION_ID_SIZE);
+        assert_eq!(stats.resolved, 1);
+    }
+
+    #[test]
+    fn ambiguous_short_id_falls_back_to_the_full_id() {
+        let mut ids = ShortIds::new(7, 1);
+        let (a, b) = colliding(&ids);
+        ids.learn(a);
+        let mut stats = ShortIdStats::default();
+        // known alone, `b` is mistaken for `a`
+        let batch = ids.encode(&[b].into_iter().collect(), &mut stats);
+        let (op_ids, _) = ids.decode(batch, &mut stats);
+        assert_eq!(op_ids, [a].into_iter().collect());
+        assert_eq!(stats.mistaken, 1);
+        // known with `a`, the short id is ambiguous
+        ids.learn(b);
+        let batch = ids.encode(&[b].into_iter().collect(), &mut stats);
+        let (op_ids, bytes) = ids.decode(batch, &mut stats);
+        assert_eq!(op_ids, [b].into_iter().collect());
+        assert_eq!(bytes, 1 + 1 + OPERATION_ID_SIZE);
+        assert_eq!(stats.ambiguous, 1);
+        assert_eq!(stats.mistaken, 1);
+    }
+
+    #[test]
+    fn asks_are_resolved_by_the_node_asked() {
+        let mut links = ShortIdLinks::new(Some(1), Stream::ShortIds.rng(0));
+        links.announce(1, HashSet::default());
+        let remote = &links.links[&1].remote;
+        let (a, b) = colliding(remote);
+        let c = distinct(remote, &[a, b]);
+        // announced in two batches, `a` and `b` don't collide in a batch
+        links.announce(1, [a, c].into_iter().collect());
+        links.announce(1, [b].into_iter().collect());
+        let (asked, bytes) = links.ask(1, &[c].into_iter().collect());
+        assert_eq!(asked, [c].into_iter().collect());
+        assert_eq!(bytes, 1);
+        // the node knows both ids of the short id, we ask it the full id
+        let (asked, bytes) = links.ask(1, &[a].into_iter().collect());
+        assert_eq!(asked, [a].into_iter().collect());
+        assert_eq!(bytes, 1 + 1 + OPERATION_ID_SIZE);
+        assert_eq!(links.stats.asked_in_full, 1);
+        // a node that never announced anything is asked the full ids
+        let (asked, bytes) = links.ask(2, &[a].into_iter().collect());
+        assert_eq!(asked, [a].into_iter().collect());
+        assert_eq!(bytes, OPERATION_ID_SIZE);
+    }
+}
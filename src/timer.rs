@@ -0,0 +1,191 @@
+//! Deadline scheduler of the deferred re-asks. The entries of a `VecDeque`
+//! have to be pushed in deadline order to be popped in time, a heap accepts
+//! any deadline and always pops the earliest one.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    mem::size_of,
+    time::Instant,
+};
+
+pub struct TimerHeap<T> {
+    /// Deadline and key of each entry, the earliest first
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// Entries in insertion order, the key of `entries[i]` is
+    /// `first_key + i`. A popped entry leaves a hole until the ones before it
+    /// are popped.
+    entries: VecDeque<Option<(Instant, T)>>,
+    first_key: u64,
+    len: usize,
+}
+
+impl<T> Default for TimerHeap<T> {
+    fn default() -> Self {
+        Self {
+            heap: BinaryHeap::default(),
+            entries: VecDeque::default(),
+            first_key: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T> TimerHeap<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Schedule `value` at `deadline`, return its key
+    pub fn push(&mut self, deadline: Instant, value: T) -> u64 {
+        let key = self.first_key + self.entries.len() as u64;
+        self.heap.push(Reverse((deadline, key)));
+        self.entries.push_back(Some((deadline, value)));
+        self.len += 1;
+        key
+    }
+
+    fn slot(&mut self, key: u64) -> Option<&mut Option<(Instant, T)>> {
+        let index = key.checked_sub(self.first_key)?;
+        self.entries.get_mut(index as usize)
+    }
+
+    /// The entry with the earliest deadline, even if it isn't due. The keys
+    /// of the entries already removed are skipped.
+    pub fn pop_first(&mut self) -> Option<(Instant, T)> {
+        while let Some(Reverse((_, key))) = self.heap.pop() {
+            if let Some(entry) = self.slot(key).and_then(Option::take) {
+                self.len -= 1;
+                while let Some(None) = self.entries.front() {
+                    self.entries.pop_front();
+                    self.first_key += 1;
+                }
+                return Some(entry);
+            }
+        }
+        None
+    }
+
+    /// The entry with the earliest deadline, if that deadline passed
+    pub fn pop_due(&mut self, now: Instant) -> Option<(Instant, T)> {
+        match self.heap.peek() {
+            Some(Reverse((deadline, _))) if *deadline <= now => self.pop_first(),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: u64) -> Option<&mut T> {
+        self.slot(key)?.as_mut().map(|(_, value)| value)
+    }
+
+    /// Entries with their key, in insertion order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (u64, &T)> {
+        let first_key = self.first_key;
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, entry)| {
+                entry
+                    .as_ref()
+                    .map(|(_, value)| (first_key + index as u64, value))
+            })
+    }
+
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        for entry in self.entries.iter_mut() {
+            if entry.as_ref().is_some_and(|(_, value)| !keep(value)) {
+                *entry = None;
+                self.len -= 1;
+            }
+        }
+        let (entries, first_key) = (&self.entries, self.first_key);
+        self.heap.retain(|Reverse((_, key))| {
+            entries
+                .get((key - first_key) as usize)
+                .is_some_and(Option::is_some)
+        });
+        while let Some(None) = self.entries.front() {
+            self.entries.pop_front();
+            self.first_key += 1;
+        }
+    }
+
+    /// Approximate bytes on the heap, without what the entries own
+    pub fn memory(&self) -> usize {
+        self.heap.capacity() * size_of::<(Instant, u64)>()
+            + self.entries.capacity() * size_of::<Option<(Instant, T)>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn push_pops_the_earliest_deadline_first() {
+        let mut timers = TimerHeap::default();
+        let now = Instant::now();
+        let late = timers.push(now + Duration::from_secs(2), "late");
+        let early = timers.push(now + Duration::from_secs(1), "early");
+        assert_ne!(late, early);
+        assert_eq!(timers.len(), 2);
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some("early"));
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some("late"));
+        assert!(timers.is_empty());
+        assert!(timers.pop_first().is_none());
+    }
+
+    #[test]
+    fn pop_due_waits_for_the_deadline() {
+        let mut timers = TimerHeap::default();
+        let now = Instant::now();
+        let deadline = now + Duration::from_millis(5);
+        timers.push(deadline, 0);
+        assert!(timers.pop_due(now).is_none());
+        assert_eq!(timers.pop_due(deadline), Some((deadline, 0)));
+        assert!(timers.pop_due(deadline).is_none());
+    }
+
+    #[test]
+    fn removed_entries_are_not_popped() {
+        let mut timers = TimerHeap::default();
+        let now = Instant::now();
+        for value in 0..5 {
+            timers.push(now + Duration::from_millis(value), value);
+        }
+        timers.retain(|value| value % 2 == 1);
+        assert_eq!(timers.len(), 2);
+        let values: Vec<u64> = timers.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, [1, 3]);
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some(1));
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some(3));
+        assert_eq!(timers.len(), 0);
+        assert!(timers.pop_first().is_none());
+    }
+
+    #[test]
+    fn a_stale_key_is_skipped() {
+        let mut timers = TimerHeap::default();
+        let now = Instant::now();
+        let first = timers.push(now, 0);
+        timers.push(now + Duration::from_millis(1), 1);
+        // removed behind the back of the heap
+        *timers.slot(first).unwrap() = None;
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some(1));
+        assert_eq!(timers.len(), 1);
+    }
+
+    #[test]
+    fn get_mut_changes_a_scheduled_entry() {
+        let mut timers = TimerHeap::default();
+        let key = timers.push(Instant::now(), vec![1]);
+        timers.get_mut(key).unwrap().push(2);
+        assert_eq!(timers.pop_first().map(|(_, value)| value), Some(vec![1, 2]));
+        assert!(timers.get_mut(key).is_none());
+    }
+}
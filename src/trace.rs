@@ -0,0 +1,851 @@
+//! Traces of the runs: every event of the measured node, with the time it
+//! happened since the start of the run. A trace is written in a file, one
+//! event by line, and can be replayed into the hooks of any algorithm (see
+//! [crate::harness::replay]), to feed the traffic of a run again or to
+//! compare the algorithms on the same traffic.
+
+use crate::{
+    clock::Clock,
+    operation::{Fee, Operation, OperationFees, OPERATION_ID_SIZE},
+    rng::{HashMap, HashSet},
+    scenario::Scenario,
+    validation::sign,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+type NodeId = u64;
+type OperationId = u64;
+
+/// Timers of the measured node
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Timer {
+    /// Loop sending the announcements and the deferred asks, or the
+    /// operations in `more_cpu`
+    Send,
+    /// Loop asking the wanted operations
+    Asking,
+}
+
+impl Timer {
+    fn name(&self) -> &'static str {
+        match self {
+            Timer::Send => "send",
+            Timer::Asking => "asking",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Timer::Send, Timer::Asking]
+            .into_iter()
+            .find(|timer| timer.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Connected(NodeId),
+    Disconnected(NodeId),
+    /// Fees announced with the batches of a node
+    Fees(OperationFees),
+    /// Batch of ids announced by a node
+    Batch(NodeId, HashSet<OperationId>),
+    /// Ids asked by the measured node, that's what the algorithm decided
+    AskSent(NodeId, HashSet<OperationId>),
+    /// Operations answered by a node
+    Operations(NodeId, HashMap<OperationId, Operation>),
+    /// Ids a node asks to the measured node
+    AskReceived(NodeId, HashSet<OperationId>),
+    /// Answer of a node to a pull
+    Digest(NodeId, HashSet<OperationId>),
+    Tick(Timer),
+}
+
+impl Event {
+    /// Inputs are fed to the hooks on replay. The asks sent are what the
+    /// replayed algorithm decides again, the answers follow its asks (see
+    /// [Responses])
+    pub fn is_input(&self) -> bool {
+        !matches!(self, Event::AskSent(..) | Event::Operations(..))
+    }
+
+    /// Bytes of the event on the wire, with the full ids
+    fn bytes(&self) -> usize {
+        match self {
+            Event::Fees(fees) => fees.len() * (OPERATION_ID_SIZE + std::mem::size_of::<Fee>()),
+            Event::Batch(_, op_ids)
+            | Event::AskSent(_, op_ids)
+            | Event::AskReceived(_, op_ids)
+            | Event::Digest(_, op_ids) => op_ids.len() * OPERATION_ID_SIZE,
+            Event::Operations(_, operations) => operations
+                .values()
+                .map(|op| OPERATION_ID_SIZE + op.size())
+                .sum(),
+            Event::Connected(_) | Event::Disconnected(_) | Event::Tick(_) => 0,
+        }
+    }
+}
+
+fn join_ids<'a>(op_ids: impl IntoIterator<Item = &'a OperationId>) -> String {
+    let mut op_ids: Vec<&OperationId> = op_ids.into_iter().collect();
+    op_ids.sort_unstable();
+    let op_ids: Vec<String> = op_ids.iter().map(|id| id.to_string()).collect();
+    op_ids.join(",")
+}
+
+/// The content of an operation without the separators of the trace:
+/// `\\` for `\`, `\c` for `,`, `\t`, `\n` and `\r` for the tab and the line ends
+fn escape(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    for c in content.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\c"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Opposite of [escape], None on an unknown escape
+fn unescape(escaped: &str) -> Option<String> {
+    let mut content = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            content.push(c);
+            continue;
+        }
+        content.push(match chars.next()? {
+            '\\' => '\\',
+            'c' => ',',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(content)
+}
+
+fn split_ids(payload: &str) -> Option<HashSet<OperationId>> {
+    payload
+        .split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.parse().ok())
+        .collect()
+}
+
+/// Events of a run with their time since its start, in the order they
+/// reached the measured node
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    pub events: Vec<(Duration, Event)>,
+    /// Period of the ticks of both loops made up on replay, for a trace
+    /// without recorded ticks
+    pub tick: Option<Duration>,
+    /// Answers made up on replay for the operations that no node sent, a
+    /// capture has no answers
+    pub answers: Option<Answers>,
+}
+
+/// How the remote nodes answer the asks of the replayed node. A node answers
+/// the ids it announced before: with the operation it sent in the recorded
+/// run, or else with the copy another node sent. A node asked in the
+/// recorded run that never answered stays silent. The operations nobody sent
+/// are made up by the [Answers] of the trace, or not answered.
+struct Responses<'a> {
+    delay: Duration,
+    /// Operations each node sent in the recorded run
+    sent: HashMap<NodeId, HashMap<OperationId, &'a Operation>>,
+    /// First copy of each operation sent in the recorded run
+    copies: HashMap<OperationId, &'a Operation>,
+    silent: HashSet<NodeId>,
+    made_up: Option<&'a Answers>,
+}
+
+impl<'a> Responses<'a> {
+    /// The answers of the recorded run, `delay` after the ask on average
+    fn new(trace: &'a Trace) -> Self {
+        let mut asked_at: HashMap<(NodeId, OperationId), Duration> = HashMap::default();
+        let mut delays = vec![];
+        let mut sent: HashMap<NodeId, HashMap<OperationId, &Operation>> = HashMap::default();
+        let mut copies = HashMap::default();
+        let mut asked = HashSet::default();
+        for (at, event) in trace.events.iter() {
+            match event {
+                Event::AskSent(node_id, op_ids) => {
+                    asked.insert(*node_id);
+                    for op_id in op_ids {
+                        asked_at.insert((*node_id, *op_id), *at);
+                    }
+                }
+                Event::Operations(node_id, operations) => {
+                    let ask = operations
+                        .keys()
+                        .find_map(|op_id| asked_at.get(&(*node_id, *op_id)));
+                    if let Some(ask) = ask {
+                        delays.push(at.saturating_sub(*ask));
+                    }
+                    for (op_id, operation) in operations {
+                        sent.entry(*node_id).or_default().insert(*op_id, operation);
+                        copies.entry(*op_id).or_insert(operation);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let delay = match &trace.answers {
+            Some(answers) => answers.delay,
+            None if delays.is_empty() => Duration::ZERO,
+            None => delays.iter().sum::<Duration>() / delays.len() as u32,
+        };
+        let silent = asked
+            .into_iter()
+            .filter(|node_id| !sent.contains_key(node_id))
+            .collect();
+        Self {
+            delay,
+            sent,
+            copies,
+            silent,
+            made_up: trace.answers.as_ref(),
+        }
+    }
+
+    /// What `node_id` answers to an ask of `op_id`
+    fn answer(&self, node_id: NodeId, op_id: OperationId) -> Option<Operation> {
+        if self.silent.contains(&node_id) {
+            return None;
+        }
+        let recorded = self
+            .sent
+            .get(&node_id)
+            .and_then(|sent| sent.get(&op_id))
+            .or_else(|| self.copies.get(&op_id));
+        match (recorded, self.made_up) {
+            (Some(operation), _) => Some((*operation).clone()),
+            (None, Some(made_up)) => Some(made_up.answer(op_id)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// How the asks of the replayed node are answered when the trace has no
+/// answers: the node asked gives, `delay` later, the asked operations it
+/// announced before, with their size in `sizes`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Answers {
+    pub delay: Duration,
+    pub sizes: HashMap<OperationId, usize>,
+}
+
+impl Answers {
+    fn answer(&self, op_id: OperationId) -> Operation {
+        Operation {
+            fee: 0,
+            content: sign(op_id),
+            wire_size: self.sizes.get(&op_id).cloned(),
+        }
+    }
+}
+
+/// What [Trace::replay] feeds next
+enum Input {
+    Event(Event),
+    Answer(NodeId, HashMap<OperationId, Operation>),
+    Tick,
+}
+
+impl Trace {
+    /// Write the trace, one event by line:
+    /// `micros <tab> kind <tab> node <tab> payload`. The content of the
+    /// operations is escaped, it can't hold a separator. The ticks and the
+    /// answers made up on replay are written first, on lines
+    /// `# <tab> setting <tab> value`.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+        if let Some(tick) = self.tick {
+            writeln!(file, "#\ttick\t{}", tick.as_micros())?;
+        }
+        if let Some(answers) = &self.answers {
+            let mut sizes: Vec<(&OperationId, &usize)> = answers.sizes.iter().collect();
+            sizes.sort_unstable();
+            let sizes: Vec<String> = sizes
+                .iter()
+                .map(|(op_id, size)| format!("{}:{}", op_id, size))
+                .collect();
+            writeln!(file, "#\tanswer_delay\t{}", answers.delay.as_micros())?;
+            writeln!(file, "#\tsizes\t{}", sizes.join(","))?;
+        }
+        for (at, event) in self.events.iter() {
+            let (kind, node, payload) = match event {
+                Event::Connected(node_id) => ("connected", Some(node_id), String::new()),
+                Event::Disconnected(node_id) => ("disconnected", Some(node_id), String::new()),
+                Event::Fees(fees) => {
+                    let mut fees: Vec<(&OperationId, &Fee)> = fees.iter().collect();
+                    fees.sort_unstable();
+                    let fees: Vec<String> = fees
+                        .iter()
+                        .map(|(op_id, fee)| format!("{}:{}", op_id, fee))
+                        .collect();
+                    ("fees", None, fees.join(","))
+                }
+                Event::Batch(node_id, op_ids) => ("batch", Some(node_id), join_ids(op_ids)),
+                Event::AskSent(node_id, op_ids) => ("ask_sent", Some(node_id), join_ids(op_ids)),
+                Event::Operations(node_id, operations) => {
+                    let mut operations: Vec<_> = operations.iter().collect();
+                    operations.sort_unstable_by_key(|(op_id, _)| **op_id);
+                    let operations: Vec<String> = operations
+                        .iter()
+                        .map(|(op_id, op)| {
+                            let wire_size = op.wire_size.map(|size| size.to_string());
+                            format!(
+                                "{}:{}:{}:{}",
+                                op_id,
+                                op.fee,
+                                wire_size.unwrap_or_default(),
+                                escape(&op.content)
+                            )
+                        })
+                        .collect();
+                    ("operations", Some(node_id), operations.join(","))
+                }
+                Event::AskReceived(node_id, op_ids) => {
+                    ("ask_received", Some(node_id), join_ids(op_ids))
+                }
+                Event::Digest(node_id, op_ids) => ("digest", Some(node_id), join_ids(op_ids)),
+                Event::Tick(timer) => ("tick", None, timer.name().to_string()),
+            };
+            let node = node.map_or(String::from("-"), |node_id| node_id.to_string());
+            writeln!(file, "{}\t{}\t{}\t{}", at.as_micros(), kind, node, payload)?;
+        }
+        file.flush()
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut trace = Trace::default();
+        let file = io::BufReader::new(fs::File::open(path)?);
+        for (index, line) in file.lines().enumerate() {
+            let line = line?;
+            let invalid = |what: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: invalid {}", path.display(), index + 1, what),
+                )
+            };
+            if line.starts_with('#') {
+                trace
+                    .parse_setting(&line)
+                    .ok_or_else(|| invalid("setting"))?;
+                continue;
+            }
+            let event = Self::parse_line(&line).ok_or_else(|| invalid("event"))?;
+            trace.events.push(event);
+        }
+        Ok(trace)
+    }
+
+    fn parse_setting(&mut self, line: &str) -> Option<()> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 || fields[0] != "#" {
+            return None;
+        }
+        let micros = || fields[2].parse().ok().map(Duration::from_micros);
+        match fields[1] {
+            "tick" => self.tick = Some(micros()?),
+            "answer_delay" => self.answers.get_or_insert_with(Answers::default).delay = micros()?,
+            "sizes" => {
+                self.answers.get_or_insert_with(Answers::default).sizes = fields[2]
+                    .split(',')
+                    .filter(|size| !size.is_empty())
+                    .map(|size| {
+                        let (op_id, size) = size.split_once(':')?;
+                        Some((op_id.parse().ok()?, size.parse().ok()?))
+                    })
+                    .collect::<Option<_>>()?
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn parse_line(line: &str) -> Option<(Duration, Event)> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        let at = Duration::from_micros(fields[0].parse().ok()?);
+        let node = fields[2].parse::<NodeId>().ok();
+        let payload = fields[3];
+        let event = match fields[1] {
+            "connected" => Event::Connected(node?),
+            "disconnected" => Event::Disconnected(node?),
+            "fees" => Event::Fees(
+                payload
+                    .split(',')
+                    .filter(|fee| !fee.is_empty())
+                    .map(|fee| {
+                        let (op_id, fee) = fee.split_once(':')?;
+                        Some((op_id.parse().ok()?, fee.parse().ok()?))
+                    })
+                    .collect::<Option<_>>()?,
+            ),
+            "batch" => Event::Batch(node?, split_ids(payload)?),
+            "ask_sent" => Event::AskSent(node?, split_ids(payload)?),
+            "operations" => Event::Operations(
+                node?,
+                payload
+                    .split(',')
+                    .filter(|op| !op.is_empty())
+                    .map(|op| {
+                        let mut parts = op.splitn(4, ':');
+                        let op_id = parts.next()?.parse().ok()?;
+                        let fee = parts.next()?.parse().ok()?;
+                        let wire_size = match parts.next()? {
+                            "" => None,
+                            size => Some(size.parse().ok()?),
+                        };
+                        let content = unescape(parts.next()?)?;
+                        let operation = Operation {
+                            fee,
+                            content,
+                            wire_size,
+                        };
+                        Some((op_id, operation))
+                    })
+                    .collect::<Option<_>>()?,
+            ),
+            "ask_received" => Event::AskReceived(node?, split_ids(payload)?),
+            "digest" => Event::Digest(node?, split_ids(payload)?),
+            "tick" => Event::Tick(Timer::parse(payload)?),
+            _ => return None,
+        };
+        Some((at, event))
+    }
+
+    /// True if the trace has ticks of `timer`, recorded or made up
+    pub fn has_ticks(&self, timer: Timer) -> bool {
+        self.tick.is_some()
+            || self
+                .events
+                .iter()
+                .any(|(_, event)| *event == Event::Tick(timer))
+    }
+
+    /// Asks sent in the recorded run, and the ids they asked
+    pub fn asks_sent(&self) -> (usize, usize) {
+        self.events
+            .iter()
+            .filter_map(|(_, event)| match event {
+                Event::AskSent(_, op_ids) => Some(op_ids.len()),
+                _ => None,
+            })
+            .fold((0, 0), |(asks, ids), len| (asks + 1, ids + len))
+    }
+
+    /// Feed the inputs to the `hooks` of `protocol`, each one at its time
+    /// since the start of the replay, with the ticks made up by
+    /// [Trace::tick]. The asks of `protocol` are answered by the node asked,
+    /// see [Responses]. The hooks read `clock`, they
+    /// see the same delays between the events as in the recorded run: a
+    /// virtual clock is moved to the time of the event, the wall clock
+    /// sleeps.
+    pub fn replay<P>(&self, protocol: &mut P, clock: &Clock, hooks: &Hooks<P>) -> ReplayReport {
+        let start = Instant::now();
+        let replay_start = clock.now();
+        // a trace of less_cpu has no asking loop, its send loop drives both
+        let asking_ticks = self.has_ticks(Timer::Asking);
+        let tick = self.tick.filter(|tick| !tick.is_zero());
+        let mut report = ReplayReport::default();
+        let mut events = self.events.iter().filter(|(_, event)| event.is_input());
+        let mut next_event = events.next();
+        let responses = Responses::new(self);
+        // answers by time, then by ask
+        let mut answers: BTreeMap<(Duration, usize), (NodeId, HashMap<OperationId, Operation>)> =
+            BTreeMap::new();
+        let mut announced: HashMap<NodeId, HashSet<OperationId>> = HashMap::default();
+        let mut next_tick = Duration::ZERO;
+        // the ticks go on until the last event or answer
+        let mut last = Duration::ZERO;
+        loop {
+            let event_at = next_event.map(|(at, _)| *at);
+            let answer_at = answers.keys().next().map(|(at, _)| *at);
+            let pending = event_at.is_some() || answer_at.is_some();
+            let tick_at = tick
+                .filter(|_| pending || next_tick <= last)
+                .map(|_| next_tick);
+            // at the same time, the events come before the answers and the ticks
+            let (at, input) = match (event_at, answer_at, tick_at) {
+                (Some(at), _, _)
+                    if answer_at.is_none_or(|answer_at| at <= answer_at)
+                        && tick_at.is_none_or(|tick_at| at <= tick_at) =>
+                {
+                    let (_, event) = next_event.unwrap();
+                    next_event = events.next();
+                    last = at;
+                    (at, Input::Event(event.clone()))
+                }
+                (_, Some(at), _) if tick_at.is_none_or(|tick_at| at <= tick_at) => {
+                    let (_, (node_id, operations)) = answers.pop_first().unwrap();
+                    last = at;
+                    (at, Input::Answer(node_id, operations))
+                }
+                (_, _, Some(at)) => {
+                    next_tick += tick.unwrap();
+                    (at, Input::Tick)
+                }
+                _ => break,
+            };
+            if let Some(wait) = (replay_start + at).checked_duration_since(clock.now()) {
+                clock.advance(wait);
+            }
+            match input {
+                Input::Event(event) => {
+                    if let Event::Batch(node_id, op_ids) = &event {
+                        announced.entry(*node_id).or_default().extend(op_ids);
+                    }
+                    report.bandwidth += feed(protocol, hooks, event, asking_ticks);
+                }
+                Input::Answer(node_id, operations) => {
+                    let event = Event::Operations(node_id, operations);
+                    report.bandwidth += feed(protocol, hooks, event, asking_ticks);
+                }
+                Input::Tick => {
+                    feed(protocol, hooks, Event::Tick(Timer::Send), asking_ticks);
+                    feed(protocol, hooks, Event::Tick(Timer::Asking), asking_ticks);
+                }
+            }
+            for (node_id, op_ids) in (hooks.asks)() {
+                report.asks += 1;
+                report.asked_ids += op_ids.len();
+                report.bandwidth += op_ids.len() * OPERATION_ID_SIZE;
+                let operations: HashMap<OperationId, Operation> = op_ids
+                    .into_iter()
+                    .filter(|op_id| {
+                        announced
+                            .get(&node_id)
+                            .is_some_and(|announced| announced.contains(op_id))
+                    })
+                    .filter_map(|op_id| Some((op_id, responses.answer(node_id, op_id)?)))
+                    .collect();
+                if !operations.is_empty() {
+                    let key = (at + responses.delay, report.asks);
+                    answers.insert(key, (node_id, operations));
+                }
+            }
+        }
+        report.received = (hooks.received)(protocol);
+        report.elapsed = start.elapsed();
+        report
+    }
+}
+
+/// Give `event` to its hook, return the bytes it took on the wire
+fn feed<P>(protocol: &mut P, hooks: &Hooks<P>, event: Event, asking_ticks: bool) -> usize {
+    let bytes = event.bytes();
+    match event {
+        Event::Connected(node_id) => (hooks.connected)(protocol, node_id),
+        Event::Disconnected(node_id) => (hooks.disconnected)(protocol, node_id),
+        Event::Fees(fees) => (hooks.fees)(protocol, fees),
+        Event::Batch(node_id, op_ids) => return (hooks.batch)(protocol, node_id, op_ids),
+        Event::Operations(node_id, operations) => (hooks.operations)(protocol, node_id, operations),
+        Event::AskReceived(node_id, op_ids) => (hooks.ask_received)(protocol, node_id, op_ids),
+        Event::Digest(node_id, op_ids) => (hooks.digest)(protocol, node_id, op_ids),
+        Event::Tick(Timer::Send) => {
+            (hooks.send_tick)(protocol);
+            if let Some(asking_tick) = hooks.asking_tick.filter(|_| !asking_ticks) {
+                asking_tick(protocol);
+            }
+        }
+        Event::Tick(Timer::Asking) => {
+            if let Some(asking_tick) = hooks.asking_tick {
+                asking_tick(protocol);
+            }
+        }
+        Event::AskSent(..) => {}
+    }
+    bytes
+}
+
+/// The hooks of a node `P` fed by [Trace::replay], each one a closure
+/// taking the node
+pub struct Hooks<P> {
+    pub connected: fn(&mut P, NodeId),
+    pub disconnected: fn(&mut P, NodeId),
+    pub fees: fn(&mut P, OperationFees),
+    /// The batch of a node, return the bytes exchanged for it: the node
+    /// may reconcile instead of sending it
+    pub batch: fn(&mut P, NodeId, HashSet<OperationId>) -> usize,
+    pub operations: fn(&mut P, NodeId, HashMap<OperationId, Operation>),
+    pub ask_received: fn(&mut P, NodeId, HashSet<OperationId>),
+    pub digest: fn(&mut P, NodeId, HashSet<OperationId>),
+    pub send_tick: fn(&mut P),
+    /// None without asking loop, the asks are sent with the batches
+    pub asking_tick: Option<fn(&mut P)>,
+    /// Take the asks sent by the node
+    pub asks: fn() -> Vec<(NodeId, HashSet<OperationId>)>,
+    /// Number of operations received by the node
+    pub received: fn(&P) -> usize,
+}
+
+/// Records the events of a run, shared by its threads, at their time on the
+/// clock of the run. Does nothing when disabled.
+#[derive(Clone, Default)]
+pub struct Recorder(Option<Arc<Mutex<(Clock, Instant, Trace)>>>);
+
+impl Recorder {
+    /// Recorder of the run of `scenario` on the wall clock, enabled if the
+    /// scenario has a [Scenario::record] directory
+    pub fn new(scenario: &Scenario) -> Self {
+        Self::on_clock(scenario, Clock::default())
+    }
+
+    /// Same as [Recorder::new], the times are read on `clock`
+    pub fn on_clock(scenario: &Scenario, clock: Clock) -> Self {
+        match scenario.record {
+            Some(_) => {
+                let start = clock.now();
+                Self(Some(Arc::new(Mutex::new((clock, start, Trace::default())))))
+            }
+            None => Self(None),
+        }
+    }
+
+    /// Keep the event built by `event`, called with the lock of the measured
+    /// node held so the events are in the order of the hooks
+    pub fn record(&self, event: impl FnOnce() -> Event) {
+        if let Some(recorder) = &self.0 {
+            let mut guard = recorder.lock().unwrap();
+            let at = guard.0.now() - guard.1;
+            guard.2.events.push((at, event()));
+        }
+    }
+
+    /// Write the trace of the run of `algorithm` in the [Scenario::record]
+    /// directory
+    pub fn save(&self, algorithm: &str, scenario: &Scenario) -> io::Result<Option<PathBuf>> {
+        let (recorder, dir) = match (&self.0, &scenario.record) {
+            (Some(recorder), Some(dir)) => (recorder, dir),
+            _ => return Ok(None),
+        };
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}-{}-{}.trace",
+            algorithm, scenario.name, scenario.seed
+        ));
+        recorder.lock().unwrap().2.write(&path)?;
+        println!("Trace written to {}", path.display());
+        Ok(Some(path))
+    }
+}
+
+/// What an algorithm did with a replayed trace
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayReport {
+    pub asks: usize,
+    pub asked_ids: usize,
+    /// Operations the measured node received
+    pub received: usize,
+    /// Bytes of the events received by the measured node and of its asks
+    pub bandwidth: usize,
+    pub elapsed: Duration,
+}
+
+impl ReplayReport {
+    pub fn print(&self, algorithm: &str, trace: &Trace) {
+        // an imported capture has no asks, nothing to compare with
+        let recorded = match trace.asks_sent() {
+            (0, _) => String::new(),
+            (asks, asked_ids) => format!(" (recorded: {} asks of {} ids)", asks, asked_ids),
+        };
+        println!(
+            "{}: {} asks of {} ids{}, {} operations received, {} bytes, replayed in {:?}",
+            algorithm,
+            self.asks,
+            self.asked_ids,
+            recorded,
+            self.received,
+            self.bandwidth,
+            self.elapsed
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    thread_local! {
+        /// Asks sent by the [Node]
+        static ASKS: std::cell::RefCell<Vec<(NodeId, HashSet<OperationId>)>> = Default::default();
+    }
+
+    /// Keeps the time of its ticks and of the operations it received, asks
+    /// everything to the node 1 on its first tick
+    struct Node {
+        clock: Clock,
+        ticks: Vec<Instant>,
+        received: Vec<(Instant, NodeId, HashMap<OperationId, Operation>)>,
+    }
+
+    fn hooks() -> Hooks<Node> {
+        Hooks {
+            connected: |_, _| {},
+            disconnected: |_, _| {},
+            fees: |_, _| {},
+            batch: |_, _, op_ids| op_ids.len() * OPERATION_ID_SIZE,
+            operations: |node, node_id, operations| {
+                node.received.push((node.clock.now(), node_id, operations))
+            },
+            ask_received: |_, _, _| {},
+            digest: |_, _, _| {},
+            send_tick: |node| {
+                if node.ticks.is_empty() {
+                    ASKS.with(|asks| asks.borrow_mut().push((1, (1..=3).collect())));
+                }
+                node.ticks.push(node.clock.now())
+            },
+            asking_tick: None,
+            asks: || ASKS.with(|asks| asks.take()),
+            received: |node| node.received.len(),
+        }
+    }
+
+    #[test]
+    fn content_with_separators_is_written_and_read_back() {
+        let content = "a,b\tc\nd\\c:e\r".to_string();
+        let operation = Operation {
+            fee: 2,
+            content,
+            wire_size: Some(300),
+        };
+        let operations = [(1, operation)].into_iter().collect();
+        let trace = Trace {
+            events: vec![(Duration::from_micros(5), Event::Operations(7, operations))],
+            ..Trace::default()
+        };
+        let path = std::env::temp_dir().join(format!("trace-{}.trace", std::process::id()));
+        trace.write(&path).unwrap();
+        let read = Trace::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.events, trace.events);
+        assert_eq!(unescape("\\x"), None);
+    }
+
+    #[test]
+    fn replay_moves_the_virtual_clock() {
+        let hour = Duration::from_secs(3600);
+        let trace = Trace {
+            events: vec![
+                (Duration::ZERO, Event::Tick(Timer::Send)),
+                (hour, Event::Tick(Timer::Send)),
+            ],
+            ..Trace::default()
+        };
+        let clock = Clock::stopped();
+        let mut node = Node {
+            clock: clock.clone(),
+            ticks: vec![],
+            received: vec![],
+        };
+        let report = trace.replay(&mut node, &clock, &hooks());
+        assert_eq!(node.ticks[1] - node.ticks[0], hour);
+        assert!(report.elapsed < hour);
+    }
+
+    #[test]
+    fn made_up_answers_give_what_was_asked_and_announced() {
+        let delay = Duration::from_millis(10);
+        let trace = Trace {
+            events: vec![
+                (
+                    Duration::ZERO,
+                    Event::Batch(1, [1, 2].into_iter().collect()),
+                ),
+                (Duration::ZERO, Event::Batch(2, [3].into_iter().collect())),
+            ],
+            tick: Some(Duration::from_millis(1)),
+            answers: Some(Answers {
+                delay,
+                sizes: [(1, 500)].into_iter().collect(),
+            }),
+        };
+        let clock = Clock::stopped();
+        let mut node = Node {
+            clock: clock.clone(),
+            ticks: vec![],
+            received: vec![],
+        };
+        let report = trace.replay(&mut node, &clock, &hooks());
+        assert_eq!(report.asks, 1);
+        assert_eq!(report.received, 1);
+        let (at, node_id, operations) = &node.received[0];
+        assert_eq!(*at - node.ticks[0], delay);
+        assert_eq!(*node_id, 1);
+        assert_eq!(operations[&1].wire_size, Some(500));
+        assert_eq!(operations[&2].wire_size, None);
+        assert!(!operations.contains_key(&3));
+        // the ticks stop with the last answer
+        assert_eq!(node.ticks.len(), 11);
+    }
+
+    #[test]
+    fn answers_follow_the_asks_of_the_replayed_node() {
+        let recorded = Operation {
+            fee: 1,
+            content: "recorded".to_string(),
+            wire_size: None,
+        };
+        let mut trace = Trace {
+            events: vec![
+                (
+                    Duration::ZERO,
+                    Event::Batch(1, [1, 2, 3].into_iter().collect()),
+                ),
+                (Duration::ZERO, Event::Batch(2, [1].into_iter().collect())),
+                // in the recorded run the node 2 was asked, 5ms to answer
+                (Duration::ZERO, Event::AskSent(2, [1].into_iter().collect())),
+                (
+                    Duration::from_millis(5),
+                    Event::Operations(2, [(1, recorded.clone())].into_iter().collect()),
+                ),
+            ],
+            tick: Some(Duration::from_millis(1)),
+            answers: None,
+        };
+        let replay = |trace: &Trace| {
+            let clock = Clock::stopped();
+            let mut node = Node {
+                clock: clock.clone(),
+                ticks: vec![],
+                received: vec![],
+            };
+            trace.replay(&mut node, &clock, &hooks());
+            node
+        };
+        // the node asks 1 to 3 to the node 1, that answers the copy of the
+        // node 2, nobody sent 2 and 3
+        let node = replay(&trace);
+        assert_eq!(node.received.len(), 1);
+        let (at, node_id, operations) = &node.received[0];
+        assert_eq!(*node_id, 1);
+        assert_eq!(*at - node.ticks[0], Duration::from_millis(5));
+        assert_eq!(*operations, [(1, recorded)].into_iter().collect());
+        // the node 1 was asked in the recorded run and never answered
+        trace.events.push((
+            Duration::from_millis(6),
+            Event::AskSent(1, [2].into_iter().collect()),
+        ));
+        assert!(replay(&trace).received.is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+//! Cost model of the checks a real node does on every operation it receives
+//! (deserialization and signature verification) before accepting it in its
+//! `received` map. Both algorithms use the same model so the comparison
+//! between them includes that cost.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+/// How the reception of an operation is verified
+#[derive(Clone, Copy, Debug, Default)]
+pub enum OpValidation {
+    /// Accept everything without checking (previous behavior)
+    #[default]
+    Disabled,
+    /// Check the signature, then hash it `rounds` more times to simulate the
+    /// cost of the deserialization and of an ed25519 verification
+    Hash { rounds: u32 },
+    /// Check the signature, then burn the cpu for a calibrated duration
+    CpuBurn(Duration),
+}
+
+fn digest(op_id: u64, round: u32) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    op_id.hash(&mut hasher);
+    round.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fake signature of an operation, that's what an honest node send us as the
+/// content of the operation `op_id`
+pub fn sign(op_id: u64) -> String {
+    format!("{:016x}", digest(op_id, 0))
+}
+
+/// Return true if the `operation` is accepted for the `op_id` with the given
+/// validation mode. The work done here is the one we want to measure.
+pub fn validate(op_id: u64, operation: &str, validation: OpValidation) -> bool {
+    match validation {
+        OpValidation::Disabled => true,
+        OpValidation::Hash { rounds } => {
+            let mut acc = 0u64;
+            for round in 1..=rounds {
+                acc ^= digest(op_id, round);
+            }
+            std::hint::black_box(acc);
+            operation == sign(op_id)
+        }
+        OpValidation::CpuBurn(duration) => {
+            let start = Instant::now();
+            while start.elapsed() < duration {
+                std::hint::spin_loop();
+            }
+            operation == sign(op_id)
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! The hooks of `hybrid` and `reconcile`: the fresh ids are asked right away
+//! to the node that knows them, the ones already in flight go to a wishlist
+//! and are asked again to another node by the asking loop if they don't
+//! come in time. The two algorithms only differ in how they learn the ids
+//! (batches or reconciliations) and tell them to the other nodes.
+
+use crate::hooks::{self, ban_if_needed, Protocol, Shared};
+use crate::operation::{by_priority, Operation};
+use crate::peers::PeerInfo;
+use crate::rng::{HashMap, HashSet};
+use crate::score::ScoreConfig;
+use crate::trace::Recorder;
+use std::{
+    cmp::Reverse,
+    time::{Duration, Instant},
+};
+
+type NodeId = u64;
+type OperationId = u64;
+type OperationIds = HashSet<OperationId>;
+type OperationMap = HashMap<OperationId, Operation>;
+
+/// Operations asked and not received yet, with when and to which `NodeId`
+/// we asked them.
+pub type InFlightOperations = HashMap<OperationId, (Instant, NodeId)>;
+
+/// The fields of a [WishlistProtocol] beside the [Shared] ones
+pub struct Wishlist<'a> {
+    pub in_flight: &'a mut InFlightOperations,
+    pub wishlist: &'a mut OperationIds,
+    pub retry_period: Duration,
+}
+
+/// The `FakeProtocol` of an algorithm with a wishlist
+pub trait WishlistProtocol: Protocol {
+    fn split(&mut self) -> (Shared<'_, Self::Info>, Wishlist<'_>);
+
+    /// Push the ids asked to `node_id` in the queue of the algorithm
+    fn ask_operations(node_id: NodeId, op_ids: OperationIds, recorder: &Recorder);
+}
+
+/// The fresh operations are asked right away to `node_id`, like in
+/// `less_cpu`. The ones already in flight go to the wishlist, like in
+/// `more_cpu`, and are asked again by [on_asking_loop] if they don't come in
+/// time.
+///
+/// # Return
+///
+/// Operation asked in that call (usefull for measurement but not needed in
+/// the final implementation)
+pub fn on_batch_received<P: WishlistProtocol>(
+    op_batch: OperationIds,
+    node_id: NodeId,
+    protocol: &mut P, /* self simulation */
+) -> OperationIds {
+    let op_batch = match hooks::admit_batch(op_batch, node_id, protocol) {
+        Some(op_batch) => op_batch,
+        None => return OperationIds::default(),
+    };
+    // a node with a bad score is asked by the asking loop, only if nobody
+    // better knows the operation
+    let trusted = hooks::is_trusted(node_id, protocol);
+    let (shared, wish) = protocol.split();
+    let mut ask_set = OperationIds::with_capacity_and_hasher(op_batch.len(), Default::default());
+    let now = shared.clock.now();
+    for op_id in op_batch {
+        if shared.received.contains_key(&op_id) {
+            continue;
+        }
+        if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+            node_info.known_op_mut().insert(op_id);
+        }
+        match wish.in_flight.get(&op_id) {
+            Some((_, asked)) if *asked == node_id => continue, // already asked to the `node_id`
+            Some(_) => {
+                wish.wishlist.insert(op_id);
+            }
+            None if !trusted => {
+                wish.wishlist.insert(op_id);
+            }
+            None => {
+                ask_set.insert(op_id);
+                wish.in_flight.insert(op_id, (now, node_id));
+            }
+        }
+    }
+    if shared.is_measured {
+        // just for the measurement, remove that on the definitive implementation
+        // ask the most prioritary operations first
+        let asked = by_priority(ask_set.iter().cloned(), shared.op_fees);
+        for chunk in asked.chunks(shared.max_batch_size) {
+            P::ask_operations(node_id, chunk.iter().cloned().collect(), shared.recorder);
+        }
+    }
+    ask_set
+}
+
+/// Ask the operations of the wishlist that are not in flight anymore, or
+/// for more than `retry_period`, to the best node that knows them.
+pub fn on_asking_loop<P: WishlistProtocol>(protocol: &mut P /* self simulation */) {
+    if let Some(scoring) = protocol.shared().scoring {
+        check_ask_timeouts(scoring, protocol);
+    }
+    hooks::pull_if_needed(protocol);
+    let (shared, wish) = protocol.split();
+    let now = shared.clock.now();
+    let received = &shared.received;
+    wish.wishlist.retain(|op_id| !received.contains_key(op_id));
+    let in_flight = &wish.in_flight;
+    let due = wish
+        .wishlist
+        .iter()
+        .cloned()
+        .filter(|op_id| match in_flight.get(op_id) {
+            Some((asked_at, _)) => now > *asked_at + wish.retry_period,
+            None => true,
+        });
+    let due = by_priority(due, shared.op_fees);
+    // the nodes with the best score are asked first
+    let node_infos = &shared.node_infos;
+    let mut node_ids: Vec<NodeId> = node_infos.keys().cloned().collect();
+    let weights = crate::score::weights(shared.scoring);
+    node_ids.sort_unstable_by_key(|node_id| {
+        (
+            Reverse(node_infos[node_id].score().value(&weights)),
+            *node_id,
+        )
+    });
+    let mut asks: HashMap<NodeId, OperationIds> = HashMap::default();
+    for op_id in due {
+        let late = wish.in_flight.get(&op_id).map(|(_, node_id)| *node_id);
+        // not the node that is late, if someone else knows the operation
+        let node_id = node_ids.iter().find(|node_id| {
+            Some(**node_id) != late
+                && node_infos[*node_id].known_op().contains(&op_id)
+                && asks.get(*node_id).map(|ask| ask.len()).unwrap_or(0) < shared.max_batch_size
+        });
+        if let Some(node_id) = node_id {
+            asks.entry(*node_id).or_default().insert(op_id);
+            wish.in_flight.insert(op_id, (now, *node_id));
+        }
+    }
+    if shared.is_measured {
+        // just for the measurement, remove that on the definitive implementation
+        for (node_id, ask) in asks {
+            P::ask_operations(node_id, ask, shared.recorder);
+        }
+    }
+}
+
+/// The nodes asked for an operation we still don't have after
+/// `ask_timeout` are penalized, the operation goes back to the wishlist.
+fn check_ask_timeouts<P: WishlistProtocol>(
+    scoring: ScoreConfig,
+    protocol: &mut P, /* self simulation */
+) {
+    let (shared, wish) = protocol.split();
+    let now = shared.clock.now();
+    let timed_out: Vec<(OperationId, NodeId)> = wish
+        .in_flight
+        .iter()
+        .filter(|(_, (asked_at, _))| now > *asked_at + scoring.ask_timeout)
+        .map(|(op_id, (_, node_id))| (*op_id, *node_id))
+        .collect();
+    for (op_id, node_id) in timed_out {
+        let (shared, wish) = protocol.split();
+        wish.in_flight.remove(&op_id);
+        wish.wishlist.insert(op_id);
+        if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+            node_info.score_mut().timeouts += 1;
+        }
+        ban_if_needed(node_id, protocol);
+    }
+}
+
+/// What we asked to `node_id` will be asked to another node, see
+/// [Protocol::forget_asks_to]
+pub(crate) fn forget_asks_to<P: WishlistProtocol>(
+    node_id: NodeId,
+    protocol: &mut P, /* self simulation */
+) {
+    let (_, wish) = protocol.split();
+    let wishlist = wish.wishlist;
+    wish.in_flight.retain(|op_id, (_, asked)| {
+        if *asked == node_id {
+            wishlist.insert(*op_id);
+        }
+        *asked != node_id
+    });
+}
+
+/// Verify the operations received from `node_id` and update the score of
+/// the node. The invalid ones go back to the wishlist.
+fn filter_valid_operations<P: WishlistProtocol>(
+    node_id: NodeId,
+    mut operations: OperationMap,
+    protocol: &mut P, /* self simulation */
+) -> OperationMap {
+    let (shared, wish) = protocol.split();
+    let validation = shared.op_validation;
+    let mut invalid = vec![];
+    operations.retain(|op_id, operation| {
+        let valid = crate::validation::validate(*op_id, &operation.content, validation);
+        if !valid {
+            invalid.push(*op_id);
+        }
+        valid
+    });
+    for op_id in invalid.iter() {
+        wish.in_flight.remove(op_id);
+        wish.wishlist.insert(*op_id);
+    }
+    if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+        let score = node_info.score_mut();
+        score.invalid += invalid.len() as u64;
+        score.delivered += operations.len() as u64;
+        // don't ask it again to that node
+        for op_id in invalid.iter() {
+            node_info.known_op_mut().remove(op_id);
+        }
+    }
+    operations
+}
+
+/// The valid operations sent by `node_id`, now received. None if the node
+/// is banned, the algorithm tells the other nodes about the ones returned.
+pub(crate) fn receive_operations<P: WishlistProtocol>(
+    node_id: NodeId,
+    operations: OperationMap,
+    protocol: &mut P, /* self simulation */
+) -> Option<OperationMap> {
+    if protocol.shared().banned.contains_key(&node_id) {
+        return None;
+    }
+    let operations = filter_valid_operations(node_id, operations, protocol);
+    ban_if_needed(node_id, protocol);
+    let (shared, wish) = protocol.split();
+    for op_id in operations.keys() {
+        wish.in_flight.remove(op_id);
+        wish.wishlist.remove(op_id);
+        shared.op_fees.remove(op_id);
+    }
+    shared.received.extend(operations.clone());
+    if let Some(node_info) = shared.node_infos.get_mut(&node_id) {
+        node_info.known_op_mut().extend(operations.keys());
+    }
+    Some(operations)
+}
+
+/// `node_id` answered our pull with the ids it received recently, the ones
+/// we miss are processed like an announcement of that node.
+pub fn on_digest_received<P: WishlistProtocol>(
+    node_id: NodeId,
+    digest: OperationIds,
+    protocol: &mut P, /* self simulation */
+) {
+    for batch in hooks::missing_ids(node_id, digest, protocol) {
+        on_batch_received(batch, node_id, protocol);
+    }
+}
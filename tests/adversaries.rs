@@ -0,0 +1,302 @@
+//! The misbehaving peers of [bench_sandbox::adversary] against each
+//! algorithm, driven round by round without the threads of the measurements.
+//! Each test uses the queues of its own algorithm.
+
+use bench_sandbox::{
+    adversary::{Adversaries, Behavior},
+    operation::Operation,
+    rng::{HashMap, HashSet},
+    score::{PeerScore, ScoreConfig, ScoreWeights},
+    validation::{sign, OpValidation},
+};
+use std::time::Duration;
+
+type OperationIds = HashSet<u64>;
+type Answer = Vec<(u64, Option<Operation>)>;
+
+const MAX_BATCH_SIZE: usize = 10;
+/// Each round, every node announces the next `MAX_BATCH_SIZE` operations
+const ROUNDS: u64 = 30;
+/// Rounds during which the first node of a round is alone to announce its
+/// batch, 1ms each
+const LEAD: u64 = 10;
+/// Rounds without announcement at the end, to receive what is late
+const SETTLE_ROUNDS: u64 = 200;
+const HONEST: u64 = 0;
+const NODES: u64 = 6;
+const SCORING: ScoreConfig = ScoreConfig {
+    ask_timeout: Duration::from_millis(5),
+    ban_threshold: -50,
+    weights: ScoreWeights::DEFAULT,
+};
+const OP_VALIDATION: OpValidation = OpValidation::Hash { rounds: 1 };
+
+fn adversaries() -> Adversaries {
+    Adversaries::default()
+        .with(1, Behavior::Mute)
+        .with(2, Behavior::Corrupt)
+        .with(3, Behavior::Flood)
+        .with(4, Behavior::Phantom)
+        .with(5, Behavior::Replay)
+}
+
+/// What the test does with a protocol of type `P`
+struct Driver<P> {
+    /// Give a batch of a node to the protocol
+    announce: fn(u64, OperationIds, &mut P),
+    /// Run the loops of the protocol
+    tick: fn(&mut P),
+    /// Take the asks sent by the protocol
+    asks: fn() -> Vec<(u64, OperationIds)>,
+    /// Give the answer of a node to the protocol
+    deliver: fn(u64, Answer, &mut P),
+}
+
+/// The nodes take turns to announce first. The first node of a round is
+/// alone to announce its batch for `LEAD` rounds, more than the ask timeout:
+/// what it doesn't answer times out before another node is asked.
+fn run<P>(protocol: &mut P, driver: Driver<P>) {
+    let mut adversaries = adversaries();
+    let mut previous: HashMap<u64, OperationIds> = HashMap::default();
+    for round in 0..ROUNDS + LEAD + SETTLE_ROUNDS {
+        let first = (round < ROUNDS).then_some((round, 0..1));
+        let others = (LEAD..ROUNDS + LEAD)
+            .contains(&round)
+            .then(|| (round - LEAD, 1..NODES));
+        for (batch_round, indexes) in first.into_iter().chain(others) {
+            let start = batch_round * MAX_BATCH_SIZE as u64;
+            let batch: OperationIds = (start..start + MAX_BATCH_SIZE as u64).collect();
+            for node_id in indexes.map(|index| (batch_round + index) % NODES) {
+                let batches = adversaries.announce(
+                    node_id,
+                    batch.clone(),
+                    previous.get(&node_id),
+                    MAX_BATCH_SIZE,
+                );
+                previous.insert(node_id, batch.clone());
+                for batch in batches {
+                    (driver.announce)(node_id, batch, protocol);
+                }
+            }
+        }
+        // answered before the ask timeout, however long the sleep is
+        answer_asks(protocol, &driver, &adversaries);
+        std::thread::sleep(Duration::from_millis(1));
+        (driver.tick)(protocol);
+        answer_asks(protocol, &driver, &adversaries);
+    }
+}
+
+fn answer_asks<P>(protocol: &mut P, driver: &Driver<P>, adversaries: &Adversaries) {
+    for (node_id, op_ids) in (driver.asks)() {
+        let answer = adversaries.answer(node_id, &op_ids, |op_id| Operation {
+            fee: 0,
+            content: sign(op_id),
+            wire_size: None,
+        });
+        if let Some(answer) = answer {
+            (driver.deliver)(node_id, answer, protocol);
+        }
+    }
+}
+
+fn answered(answer: Answer) -> HashMap<u64, Operation> {
+    answer
+        .into_iter()
+        .filter_map(|(op_id, op)| op.map(|op| (op_id, op)))
+        .collect()
+}
+
+/// An honest node is never banned, each misbehaving one is banned for its own
+/// misbehavior, the nodes still connected have a score over the threshold,
+/// and every operation is received from the honest node. `connected` gives
+/// the score of the nodes still connected.
+fn check(
+    banned: &HashMap<u64, PeerScore>,
+    connected: impl Fn(u64) -> Option<PeerScore>,
+    received: usize,
+) {
+    let adversaries = adversaries();
+    for node_id in banned.keys() {
+        assert_ne!(adversaries.behavior(*node_id), Behavior::Honest);
+    }
+    assert!(!banned.contains_key(&HONEST));
+    for node_id in 1..NODES {
+        let behavior = adversaries.behavior(node_id);
+        if behavior == Behavior::Replay {
+            // the replayed ids are known already, nothing to blame it for
+            assert!(!banned.contains_key(&node_id));
+            let score = connected(node_id).unwrap();
+            assert_eq!((score.timeouts, score.invalid, score.oversized), (0, 0, 0));
+            continue;
+        }
+        let score = banned
+            .get(&node_id)
+            .unwrap_or_else(|| panic!("{:?} not banned", behavior));
+        match behavior {
+            // the phantom ids are never answered
+            Behavior::Mute | Behavior::Phantom => {
+                assert!(score.timeouts > 0, "{:?}: {:?}", behavior, score);
+                assert_eq!((score.invalid, score.oversized), (0, 0));
+            }
+            Behavior::Corrupt => {
+                assert!(score.invalid > 0, "{:?}", score);
+                assert_eq!(score.oversized, 0);
+            }
+            Behavior::Flood => assert!(score.oversized > 0, "{:?}", score),
+            _ => unreachable!(),
+        }
+    }
+    for node_id in 0..NODES {
+        if let Some(score) = connected(node_id) {
+            let value = score.value(&SCORING.weights);
+            assert!(value >= SCORING.ban_threshold, "{:?}", score);
+        }
+    }
+    assert_eq!(received, (ROUNDS as usize) * MAX_BATCH_SIZE);
+}
+
+#[test]
+fn less_cpu_bans_the_adversaries() {
+    use bench_sandbox::less_cpu::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, 1, 1_000);
+    protocol.scoring = Some(SCORING);
+    protocol.op_validation = OP_VALIDATION;
+    run(
+        &mut protocol,
+        Driver {
+            announce: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            tick: on_send_loop,
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answered(answer), protocol)
+            },
+        },
+    );
+    check(
+        &protocol.banned,
+        |node_id| protocol.node_infos.get(&node_id).map(|info| info.score),
+        protocol.received.len(),
+    );
+}
+
+#[test]
+fn more_cpu_bans_the_adversaries() {
+    use bench_sandbox::more_cpu::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE);
+    protocol.scoring = Some(SCORING);
+    protocol.op_validation = OP_VALIDATION;
+    run(
+        &mut protocol,
+        Driver {
+            announce: on_batch_received_by,
+            tick: on_asking_loop,
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answer.into_iter().collect(), protocol)
+            },
+        },
+    );
+    check(
+        &protocol.banned,
+        |node_id| protocol.node_infos.get(&node_id).map(|info| info.score),
+        protocol.received.len(),
+    );
+
+    fn on_batch_received_by(node_id: u64, batch: OperationIds, protocol: &mut FakeProtocol) {
+        on_batch_received(batch, node_id, protocol);
+    }
+}
+
+#[test]
+fn hybrid_bans_the_adversaries() {
+    use bench_sandbox::hybrid::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, Duration::from_millis(1));
+    protocol.scoring = Some(SCORING);
+    protocol.op_validation = OP_VALIDATION;
+    run(
+        &mut protocol,
+        Driver {
+            announce: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            tick: |protocol| {
+                on_send_loop(protocol);
+                on_asking_loop(protocol);
+            },
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answered(answer), protocol)
+            },
+        },
+    );
+    check(
+        &protocol.banned,
+        |node_id| protocol.node_infos.get(&node_id).map(|info| info.score),
+        protocol.received.len(),
+    );
+}
+
+#[test]
+fn reconcile_bans_the_adversaries() {
+    use bench_sandbox::reconcile::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, Duration::from_millis(1));
+    protocol.scoring = Some(SCORING);
+    protocol.op_validation = OP_VALIDATION;
+    run(
+        &mut protocol,
+        Driver {
+            announce: reconcile,
+            tick: |protocol| {
+                on_send_loop(protocol);
+                on_asking_loop(protocol);
+            },
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answered(answer), protocol)
+            },
+        },
+    );
+    check(
+        &protocol.banned,
+        |node_id| protocol.node_infos.get(&node_id).map(|info| info.score),
+        protocol.received.len(),
+    );
+    assert!(protocol.recon_stats.sketches > 0);
+    assert!(protocol.recon_stats.full_lists + protocol.recon_stats.failed > 0);
+}
+
+/// The node reconciles its batch with us, the batch is sent only when the
+/// reconciliation falls back to the full list (like in the measurements)
+fn reconcile(
+    node_id: u64,
+    batch: OperationIds,
+    protocol: &mut bench_sandbox::reconcile::FakeProtocol,
+) {
+    use bench_sandbox::reconcile::*;
+    let full_list = match on_recon_started(node_id, batch.len(), protocol) {
+        ReconReply::Sketch(cells) => {
+            let sketch = Iblt::from_ids(batch.iter().cloned(), cells);
+            !on_sketch_received(node_id, sketch, protocol)
+        }
+        ReconReply::FullList => true,
+        ReconReply::Refused => false,
+    };
+    if full_list {
+        on_batch_received(batch, node_id, protocol);
+    }
+}
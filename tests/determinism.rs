@@ -0,0 +1,48 @@
+//! A simulation sends the same messages for the same seed, whatever the
+//! process: the maps iterate in a fixed order and the clock is virtual.
+//! Each test uses the queues of its own algorithm.
+
+use bench_sandbox::{
+    less_cpu, more_cpu,
+    scenario::Scenario,
+    trace::{Event, ReplayReport, Trace},
+};
+use std::path::PathBuf;
+
+type Simulate = fn(&Scenario) -> ReplayReport;
+
+/// Trace of the simulation of the adversarial scenario with `seed`
+fn simulated(simulate: Simulate, name: &str, seed: u64, run: usize) -> Trace {
+    let dir = std::env::temp_dir().join(format!("determinism-{}-{}", std::process::id(), run));
+    let scenario = Scenario::adversarial()
+        .with_seed(seed)
+        .with_record(Some(dir.clone()));
+    simulate(&scenario);
+    let path: PathBuf = dir.join(format!("{}-{}-{}.trace", name, scenario.name, seed));
+    let trace = Trace::read(&path).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    trace
+}
+
+fn same_seed_same_messages(simulate: Simulate, name: &str) {
+    let first = simulated(simulate, name, 11, 0);
+    let second = simulated(simulate, name, 11, 1);
+    assert!(first
+        .events
+        .iter()
+        .any(|(_, event)| matches!(event, Event::AskSent(..))));
+    assert_eq!(first.events, second.events);
+    // not the same messages for another seed
+    let other = simulated(simulate, name, 12, 2);
+    assert_ne!(first.events, other.events);
+}
+
+#[test]
+fn less_cpu_sends_the_same_messages_for_the_same_seed() {
+    same_seed_same_messages(less_cpu::measurements::simulate, "less_cpu");
+}
+
+#[test]
+fn more_cpu_sends_the_same_messages_for_the_same_seed() {
+    same_seed_same_messages(more_cpu::measurements::simulate, "more_cpu");
+}
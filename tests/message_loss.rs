@@ -0,0 +1,217 @@
+//! Announcements lost on the way, each algorithm has to learn the missing
+//! ids from the digests of its pulls. Each test uses the queues of its own
+//! algorithm.
+
+use bench_sandbox::{operation::Operation, pull::PullConfig, rng::HashSet, validation::sign};
+use std::time::Duration;
+
+type OperationIds = HashSet<u64>;
+
+const MAX_BATCH_SIZE: usize = 10;
+/// Each round, a node announces the next `MAX_BATCH_SIZE` operations
+const ROUNDS: u64 = 20;
+/// Rounds without announcement at the end, to pull what was lost
+const SETTLE_ROUNDS: u64 = 50;
+const NODES: u64 = 3;
+const PULL: PullConfig = PullConfig {
+    period: Duration::from_millis(1),
+    window: Duration::from_secs(60),
+    max_ids: 1_000,
+};
+
+/// The announcement of a quarter of the operations is lost
+fn is_lost(op_id: u64) -> bool {
+    op_id.is_multiple_of(4)
+}
+
+/// What the test does with a protocol of type `P`
+struct Driver<P> {
+    /// Give a batch of a node to the protocol
+    announce: fn(u64, OperationIds, &mut P),
+    /// Run the loops of the protocol
+    tick: fn(&mut P),
+    /// Take the asks sent by the protocol
+    asks: fn() -> Vec<(u64, OperationIds)>,
+    /// Take the nodes asked for a digest
+    pulls: fn() -> Vec<u64>,
+    /// Give the digest of a node to the protocol
+    digest: fn(u64, OperationIds, &mut P),
+    /// Give the answer of a node to the protocol
+    deliver: fn(u64, Vec<(u64, Operation)>, &mut P),
+    /// Number of operations received by the protocol
+    received: fn(&P) -> usize,
+}
+
+/// Every node received every operation, but only the announcements that
+/// aren't lost reach the protocol
+fn run<P>(protocol: &mut P, driver: Driver<P>) {
+    let mut sent = OperationIds::default();
+    for round in 0..ROUNDS + SETTLE_ROUNDS {
+        if round < ROUNDS {
+            let start = round * MAX_BATCH_SIZE as u64;
+            let batch: OperationIds = (start..start + MAX_BATCH_SIZE as u64).collect();
+            sent.extend(batch.iter().cloned());
+            let announced = batch.into_iter().filter(|op_id| !is_lost(*op_id)).collect();
+            (driver.announce)(round % NODES, announced, protocol);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+        (driver.tick)(protocol);
+        for node_id in (driver.pulls)() {
+            (driver.digest)(node_id, sent.clone(), protocol);
+        }
+        for (node_id, op_ids) in (driver.asks)() {
+            let answer = op_ids
+                .into_iter()
+                .map(|op_id| {
+                    let content = sign(op_id);
+                    (
+                        op_id,
+                        Operation {
+                            fee: 0,
+                            content,
+                            wire_size: None,
+                        },
+                    )
+                })
+                .collect();
+            (driver.deliver)(node_id, answer, protocol);
+        }
+    }
+    assert_eq!(
+        (driver.received)(protocol),
+        (ROUNDS as usize) * MAX_BATCH_SIZE
+    );
+}
+
+#[test]
+fn less_cpu_pulls_the_lost_announcements() {
+    use bench_sandbox::less_cpu::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, 1, 1_000);
+    protocol.pull = Puller::new(Some(PULL));
+    run(
+        &mut protocol,
+        Driver {
+            announce: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            tick: on_send_loop,
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            pulls: || DIGEST_ASK_QUEUE.lock().unwrap().drain(..).collect(),
+            digest: on_digest_received,
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answer.into_iter().collect(), protocol)
+            },
+            received: |protocol| protocol.received.len(),
+        },
+    );
+    assert!(protocol.pull.stats.missing > 0);
+}
+
+#[test]
+fn more_cpu_pulls_the_lost_announcements() {
+    use bench_sandbox::more_cpu::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE);
+    protocol.pull = Puller::new(Some(PULL));
+    run(
+        &mut protocol,
+        Driver {
+            announce: |node_id, batch, protocol| on_batch_received(batch, node_id, protocol),
+            tick: on_asking_loop,
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            pulls: || DIGEST_ASK_QUEUE.lock().unwrap().drain(..).collect(),
+            digest: on_digest_received,
+            deliver: |node_id, answer, protocol| {
+                let answer = answer.into_iter().map(|(op_id, op)| (op_id, Some(op)));
+                on_operation_received(node_id, answer.collect(), protocol)
+            },
+            received: |protocol| protocol.received.len(),
+        },
+    );
+    assert!(protocol.pull.stats.missing > 0);
+}
+
+#[test]
+fn hybrid_pulls_the_lost_announcements() {
+    use bench_sandbox::hybrid::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, Duration::from_millis(1));
+    protocol.pull = Puller::new(Some(PULL));
+    run(
+        &mut protocol,
+        Driver {
+            announce: |node_id, batch, protocol| {
+                on_batch_received(batch, node_id, protocol);
+            },
+            tick: |protocol| {
+                on_send_loop(protocol);
+                on_asking_loop(protocol);
+            },
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            pulls: || DIGEST_ASK_QUEUE.lock().unwrap().drain(..).collect(),
+            digest: on_digest_received,
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answer.into_iter().collect(), protocol)
+            },
+            received: |protocol| protocol.received.len(),
+        },
+    );
+    assert!(protocol.pull.stats.missing > 0);
+}
+
+#[test]
+fn reconcile_pulls_the_lost_announcements() {
+    use bench_sandbox::reconcile::*;
+    let mut protocol = FakeProtocol::new(NODES as usize, MAX_BATCH_SIZE, Duration::from_millis(1));
+    protocol.pull = Puller::new(Some(PULL));
+    run(
+        &mut protocol,
+        Driver {
+            announce: reconcile,
+            tick: |protocol| {
+                on_send_loop(protocol);
+                on_asking_loop(protocol);
+            },
+            asks: || {
+                BATCH_SEND_QUEUE.lock().unwrap().clear();
+                ASK_BATCH_QUEUE.lock().unwrap().drain(..).collect()
+            },
+            pulls: || DIGEST_ASK_QUEUE.lock().unwrap().drain(..).collect(),
+            digest: on_digest_received,
+            deliver: |node_id, answer, protocol| {
+                on_operation_received(node_id, answer.into_iter().collect(), protocol)
+            },
+            received: |protocol| protocol.received.len(),
+        },
+    );
+    assert!(protocol.pull.stats.missing > 0);
+    assert!(protocol.recon_stats.sketches > 0);
+}
+
+/// The node reconciles the ids announced with us, they are sent only when
+/// the reconciliation falls back to the full list (like in the measurements)
+fn reconcile(
+    node_id: u64,
+    batch: OperationIds,
+    protocol: &mut bench_sandbox::reconcile::FakeProtocol,
+) {
+    use bench_sandbox::reconcile::*;
+    let full_list = match on_recon_started(node_id, batch.len(), protocol) {
+        ReconReply::Sketch(cells) => {
+            let sketch = Iblt::from_ids(batch.iter().cloned(), cells);
+            !on_sketch_received(node_id, sketch, protocol)
+        }
+        ReconReply::FullList => true,
+        ReconReply::Refused => false,
+    };
+    if full_list {
+        on_batch_received(batch, node_id, protocol);
+    }
+}